@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS `script_runs`
+(
+    id             INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    script_id      INTEGER  NOT NULL,
+    trigger_source TEXT     NOT NULL DEFAULT 'manual',
+    outcome        TEXT     NOT NULL DEFAULT 'running',
+    error          TEXT     NULL,
+    output         TEXT     NOT NULL DEFAULT '',
+    started_at     DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at    DATETIME NULL,
+    FOREIGN KEY (script_id) REFERENCES scripts (id)
+);
+
+CREATE INDEX script_runs_script_id_index ON script_runs (script_id);
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS `script_rules`
+(
+    id                INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    sensor_id         INTEGER  NOT NULL,
+    script_id         INTEGER  NOT NULL,
+    condition         TEXT     NOT NULL,
+    debounce_reads    INTEGER  NOT NULL DEFAULT 1,
+    cooldown_seconds  INTEGER  NOT NULL DEFAULT 60,
+    enabled           TINYINT  NOT NULL DEFAULT 1,
+    match_count       INTEGER  NOT NULL DEFAULT 0,
+    last_triggered_at DATETIME NULL,
+    created_at        DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at        DATETIME NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors (id),
+    FOREIGN KEY (script_id) REFERENCES scripts (id)
+);
+
+CREATE INDEX script_rules_sensor_id_index ON script_rules (sensor_id);
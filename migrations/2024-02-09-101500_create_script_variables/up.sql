@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS `script_variables`
+(
+    id         INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name       TEXT     NOT NULL UNIQUE,
+    value      TEXT     NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NULL
+);
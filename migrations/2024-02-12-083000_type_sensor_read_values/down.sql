@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE IF NOT EXISTS `sensor_reads_text`
+(
+    id           INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    sensor_id    INTEGER  NOT NULL,
+    sensor_value TEXT     NOT NULL,
+    created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at   DATETIME NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+INSERT INTO sensor_reads_text (id, sensor_id, sensor_value, created_at, updated_at)
+SELECT id, sensor_id, raw_value, created_at, updated_at
+FROM sensor_reads;
+
+DROP TABLE sensor_reads;
+
+ALTER TABLE sensor_reads_text RENAME TO sensor_reads;
+
+CREATE INDEX sensor_reads_sensor_id_index ON sensor_reads (sensor_id);
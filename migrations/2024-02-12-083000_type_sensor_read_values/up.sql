@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS `sensor_reads_typed`
+(
+    id           INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    sensor_id    INTEGER  NOT NULL,
+    sensor_value DOUBLE   NULL,
+    unit         TEXT     NULL,
+    raw_value    TEXT     NOT NULL,
+    created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at   DATETIME NULL,
+    FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+-- Only values that are plain numbers are converted, everything else keeps
+-- its text in raw_value and gets no numeric value.
+INSERT INTO sensor_reads_typed (id, sensor_id, sensor_value, unit, raw_value, created_at, updated_at)
+SELECT id,
+       sensor_id,
+       CASE
+           WHEN trim(sensor_value) GLOB '*[0-9]*'
+               AND trim(sensor_value) NOT GLOB '*[^0-9.eE+-]*'
+               THEN CAST(trim(sensor_value) AS REAL)
+           END,
+       NULL,
+       sensor_value,
+       created_at,
+       updated_at
+FROM sensor_reads;
+
+DROP TABLE sensor_reads;
+
+ALTER TABLE sensor_reads_typed RENAME TO sensor_reads;
+
+CREATE INDEX sensor_reads_sensor_id_index ON sensor_reads (sensor_id);
@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS `sensor_types`
+(
+    id             INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name           TEXT     NOT NULL UNIQUE,
+    display_name   TEXT     NOT NULL,
+    unit           TEXT     NULL,
+    min_value      DOUBLE   NULL,
+    max_value      DOUBLE   NULL,
+    decimal_places INTEGER  NULL,
+    created_at     DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at     DATETIME NULL
+);
+
+INSERT INTO `sensor_types` (name, display_name, unit, min_value, max_value, decimal_places)
+VALUES ('current', 'Current sensor', 'A', 0, 100, 2),
+       ('temperature', 'Temperature sensor', '°C', -40, 85, 1),
+       ('humidity', 'Humidity sensor', '%', 0, 100, 1),
+       ('pressure', 'Pressure sensor', 'hPa', 300, 1100, 1),
+       ('wind_speed', 'Wind sensor', 'm/s', 0, 75, 1),
+       ('wind_direction', 'Wind direction sensor', '°', 0, 360, 0),
+       ('rain', 'Rain sensor', 'mm', 0, 500, 1),
+       ('uv', 'UV sensor', NULL, 0, 15, 1),
+       ('solar_radiation', 'Solar radiation sensor', 'W/m²', 0, 1500, 0);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS `sensor_read_rollups`
+(
+    id           INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    sensor_id    INTEGER  NOT NULL,
+    tier         TEXT     NOT NULL,
+    bucket_start DATETIME NOT NULL,
+    min_value    DOUBLE   NOT NULL,
+    max_value    DOUBLE   NOT NULL,
+    avg_value    DOUBLE   NOT NULL,
+    read_count   INTEGER  NOT NULL,
+    created_at   DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+CREATE UNIQUE INDEX `sensor_read_rollups_bucket_index` ON `sensor_read_rollups` (`sensor_id`, `tier`, `bucket_start`);
+CREATE INDEX `sensor_read_rollups_tier_index` ON `sensor_read_rollups` (`tier`, `bucket_start`);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS `users`
+(
+    id            INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    username      TEXT     NOT NULL UNIQUE,
+    password_hash TEXT     NOT NULL,
+    created_at    DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    DATETIME NULL
+);
+
+CREATE TABLE IF NOT EXISTS `user_sessions`
+(
+    id         INTEGER  NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER  NOT NULL,
+    token_id   TEXT     NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL,
+    revoked_at DATETIME NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX `user_sessions_user_index` ON `user_sessions` (`user_id`);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- The values parsed from raw_value are kept, raw_value still holds the text.
+SELECT 1;
@@ -0,0 +1,66 @@
+-- Reads stored before values were typed only got a number when their whole
+-- text was one. Parse them again like `parse_sensor_value` does: the leading
+-- run of number characters has to be a complete number, and the rest of the
+-- text is its unit. Reads stored since then parse to the same values.
+CREATE TEMP TABLE parsed_sensor_reads
+(
+    id           INTEGER NOT NULL PRIMARY KEY,
+    sensor_value DOUBLE  NULL,
+    unit         TEXT    NULL
+);
+
+INSERT INTO parsed_sensor_reads (id, sensor_value, unit)
+WITH trimmed AS (SELECT id, trim(raw_value, ' ' || char(9, 10, 13)) AS value
+                 FROM sensor_reads),
+     split AS (SELECT id, value, ltrim(value, '0123456789.eE+-') AS rest
+               FROM trimmed),
+     numbers AS (SELECT id, rest, substr(value, 1, length(value) - length(rest)) AS number
+                 FROM split),
+     unsigned AS (SELECT id,
+                         rest,
+                         number,
+                         CASE
+                             WHEN substr(number, 1, 1) IN ('+', '-') THEN substr(number, 2)
+                             ELSE number
+                             END AS digits
+                  FROM numbers),
+     parts AS (SELECT id,
+                      rest,
+                      number,
+                      CASE
+                          WHEN instr(lower(digits), 'e') > 0
+                              THEN substr(digits, 1, instr(lower(digits), 'e') - 1)
+                          ELSE digits
+                          END AS mantissa,
+                      CASE
+                          WHEN instr(lower(digits), 'e') > 0
+                              THEN substr(digits, instr(lower(digits), 'e') + 1)
+                          END AS exponent
+               FROM unsigned),
+     exponents AS (SELECT id,
+                          rest,
+                          number,
+                          mantissa,
+                          CASE
+                              WHEN substr(exponent, 1, 1) IN ('+', '-') THEN substr(exponent, 2)
+                              ELSE exponent
+                              END AS exponent
+                   FROM parts),
+     numeric AS (SELECT id, rest, CAST(number AS REAL) AS sensor_value
+                 FROM exponents
+                 WHERE mantissa NOT IN ('', '.')
+                   AND mantissa NOT GLOB '*[^0-9.]*'
+                   AND mantissa NOT GLOB '*.*.*'
+                   AND (exponent IS NULL OR (exponent <> '' AND exponent NOT GLOB '*[^0-9]*')))
+SELECT trimmed.id,
+       numeric.sensor_value,
+       nullif(trim(numeric.rest, ' ' || char(9, 10, 13)), '')
+FROM trimmed
+         LEFT JOIN numeric ON numeric.id = trimmed.id
+             AND abs(numeric.sensor_value) <= 1.7976931348623157e308;
+
+UPDATE sensor_reads
+SET sensor_value = (SELECT sensor_value FROM parsed_sensor_reads WHERE parsed_sensor_reads.id = sensor_reads.id),
+    unit         = (SELECT unit FROM parsed_sensor_reads WHERE parsed_sensor_reads.id = sensor_reads.id);
+
+DROP TABLE parsed_sensor_reads;
@@ -0,0 +1,7 @@
+ALTER TABLE `users` ADD COLUMN is_admin TINYINT NOT NULL DEFAULT 0;
+
+-- Accounts made before roles existed keep a way to manage users: the first
+-- one, normally the initial user from ADMIN_USERNAME, becomes an admin.
+UPDATE `users`
+SET is_admin = 1
+WHERE id = (SELECT MIN(id) FROM `users`);
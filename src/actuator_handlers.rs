@@ -6,11 +6,15 @@ use crate::events::{
     ACTUATOR_CHANGE_ONLINE_EVENT, ACTUATOR_NAME_CHANGE_EVENT, ACTUATOR_REGISTER_EVENT,
     ACTUATOR_STATE_CHANGE_EVENT, ACTUATOR_UNREGISTER_EVENT,
 };
-use crate::models::Actuator;
+use crate::models::{Actuator, UpdateActuatorState};
+use crate::protocol::{
+    ActuatorOnlineChanged, ActuatorRegistered, ActuatorRenamed, ActuatorStateChanged,
+    ActuatorUnregistered,
+};
 use crate::schema::actuators;
 use crate::schema::actuators::{online, updated_at};
 use crate::CoAPClient;
-use anyhow::Result;
+use anyhow::{Error, Result};
 use coap_lite::{CoapRequest, RequestType};
 use diesel::prelude::*;
 use diesel::update;
@@ -37,18 +41,10 @@ pub fn actuator_register_handler<'a>(
         match register_actuator(payload) {
             Ok(actuator) => {
                 if let Some(ns) = socket.of("/") {
-                    match ns.broadcast().emit(
-                        ACTUATOR_REGISTER_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                               "actuator_name": actuator.get_name(),
-                               "actuator_ip_address": actuator.get_ip_address(),
-                               "actuator_port": actuator.get_port(),
-                               "actuator_pulse": actuator.get_pulse(),
-                               "online": actuator.get_online(),
-                               "created_at": actuator.get_created_at(),
-                        }),
-                    ) {
+                    match ns
+                        .broadcast()
+                        .emit(ACTUATOR_REGISTER_EVENT, ActuatorRegistered::new(&actuator))
+                    {
                         Ok(_) => {}
                         Err(e) => {
                             println!("Error emitting actuator register event broadcast: {:?}", e);
@@ -57,18 +53,7 @@ pub fn actuator_register_handler<'a>(
                 }
 
                 if let Some(ns) = socket.of("/") {
-                    match ns.emit(
-                        ACTUATOR_REGISTER_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                               "actuator_name": actuator.get_name(),
-                               "actuator_ip_address": actuator.get_ip_address(),
-                               "actuator_port": actuator.get_port(),
-                               "actuator_pulse": actuator.get_pulse(),
-                               "online": actuator.get_online(),
-                               "created_at": actuator.get_created_at(),
-                        }),
-                    ) {
+                    match ns.emit(ACTUATOR_REGISTER_EVENT, ActuatorRegistered::new(&actuator)) {
                         Ok(_) => {}
                         Err(e) => {
                             println!("Error emitting actuator register event: {:?}", e);
@@ -110,9 +95,7 @@ pub fn actuator_unregister_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.broadcast().emit(
                         ACTUATOR_UNREGISTER_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                        }),
+                        ActuatorUnregistered::new(actuator.get_id()),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -127,9 +110,7 @@ pub fn actuator_unregister_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.emit(
                         ACTUATOR_UNREGISTER_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                        }),
+                        ActuatorUnregistered::new(actuator.get_id()),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -166,14 +147,10 @@ pub fn actuator_update_handler<'a>(
         match change_actuator_name(payload) {
             Ok(actuator) => {
                 if let Some(ns) = socket.of("/") {
-                    match ns.broadcast().emit(
-                        ACTUATOR_NAME_CHANGE_EVENT,
-                        json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_name": actuator.get_name(),
-                            "updated_at": actuator.get_updated_at(),
-                        }),
-                    ) {
+                    match ns
+                        .broadcast()
+                        .emit(ACTUATOR_NAME_CHANGE_EVENT, ActuatorRenamed::new(&actuator))
+                    {
                         Ok(_) => {}
                         Err(e) => {
                             println!(
@@ -185,14 +162,7 @@ pub fn actuator_update_handler<'a>(
                 }
 
                 if let Some(ns) = socket.of("/") {
-                    match ns.emit(
-                        ACTUATOR_NAME_CHANGE_EVENT,
-                        json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_name": actuator.get_name(),
-                            "updated_at": actuator.get_updated_at(),
-                        }),
-                    ) {
+                    match ns.emit(ACTUATOR_NAME_CHANGE_EVENT, ActuatorRenamed::new(&actuator)) {
                         Ok(_) => {}
                         Err(e) => {
                             println!("Error emitting actuator name changed event: {:?}", e);
@@ -230,11 +200,7 @@ pub fn actuator_update_state_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.broadcast().emit(
                         ACTUATOR_STATE_CHANGE_EVENT,
-                        json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_state": actuator.get_state(),
-                            "updated_at": actuator.get_updated_at(),
-                        }),
+                        ActuatorStateChanged::new(&actuator),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -249,11 +215,7 @@ pub fn actuator_update_state_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.emit(
                         ACTUATOR_STATE_CHANGE_EVENT,
-                        json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_state": actuator.get_state(),
-                            "updated_at": actuator.get_updated_at(),
-                        }),
+                        ActuatorStateChanged::new(&actuator),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -306,11 +268,7 @@ pub fn ping_actuator(actuator: &Actuator, socket: &SocketIo) {
                 if let Some(ns) = socket.of("/") {
                     match ns.broadcast().emit(
                         ACTUATOR_CHANGE_ONLINE_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                               "online": true,
-                               "updated_at": uat,
-                        }),
+                        ActuatorOnlineChanged::new(actuator.get_id(), true, uat),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -322,11 +280,7 @@ pub fn ping_actuator(actuator: &Actuator, socket: &SocketIo) {
                 if let Some(ns) = socket.of("/") {
                     match ns.emit(
                         ACTUATOR_CHANGE_ONLINE_EVENT,
-                        json!({
-                               "actuator_id": actuator.get_id(),
-                               "online": true,
-                               "updated_at": uat,
-                        }),
+                        ActuatorOnlineChanged::new(actuator.get_id(), true, uat),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -361,11 +315,7 @@ pub fn ping_actuator(actuator: &Actuator, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.broadcast().emit(
                     ACTUATOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "actuator_id": actuator.get_id(),
-                           "online": false,
-                           "updated_at": uat,
-                    }),
+                    ActuatorOnlineChanged::new(actuator.get_id(), false, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -377,11 +327,7 @@ pub fn ping_actuator(actuator: &Actuator, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.emit(
                     ACTUATOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "actuator_id": actuator.get_id(),
-                           "online": false,
-                           "updated_at": uat,
-                    }),
+                    ActuatorOnlineChanged::new(actuator.get_id(), false, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -423,3 +369,22 @@ pub fn send_message_to_actuator(actuator_id: i32, message: &String) -> Result<St
         Err(_) => Ok("KO".to_string()),
     }
 }
+
+/// Turns an actuator on or off and stores the state it confirmed.
+pub fn switch_actuator(actuator_id: i32, actuator_state: bool) -> Result<Actuator> {
+    let command = if actuator_state { "ON" } else { "OFF" }.to_string();
+
+    let reply = send_message_to_actuator(actuator_id, &command)?;
+
+    if reply != command {
+        return Err(Error::msg(format!(
+            "Actuator {} did not confirm '{}'",
+            actuator_id, command
+        )));
+    }
+
+    change_actuator_state(serde_json::to_string(&UpdateActuatorState::new(
+        actuator_id,
+        actuator_state,
+    ))?)
+}
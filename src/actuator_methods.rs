@@ -141,6 +141,14 @@ pub fn change_actuator_state(payload: String) -> Result<Actuator> {
     }
 }
 
+pub fn get_actuator(actuator_id: i32) -> Result<Actuator> {
+    let conn = &mut connect()?;
+
+    let actuator = actuators::table.find(actuator_id).first(conn)?;
+
+    Ok(actuator)
+}
+
 pub fn get_all_registered_actuators() -> Result<Vec<Actuator>> {
     let conn = &mut connect()?;
 
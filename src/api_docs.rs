@@ -0,0 +1,228 @@
+use crate::api_handlers::{ActuatorStateBody, ApiErrorBody, RenameBody, ScheduleBody, ScriptBody};
+use crate::auth::LoginSession;
+use crate::models::{
+    Actuator, Script, ScriptRun, Sensor, SensorRead, SensorReadingBucket, SensorReadings,
+    SensorReadingsPage, User, UserCredentials,
+};
+use crate::script_lexer::ScriptDiagnostic;
+use axum::Json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, OpenApi as OpenApiDocument, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+
+pub const OPENAPI_PATH: &str = "/api/openapi.json";
+
+const BEARER_TOKEN_SCHEME: &str = "bearer_token";
+
+/// The HTTP API as an OpenAPI 3 document, generated from the handlers'
+/// `utoipa::path` attributes and the models they take and return.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "homesoil",
+        description = "Sensors, actuators and scripts of a homesoil hub. Log in with `POST /api/v1/auth/login`, then send its token as `Authorization: Bearer <token>`."
+    ),
+    paths(
+        crate::api_handlers::login_user,
+        crate::api_handlers::logout_user,
+        crate::api_handlers::show_current_user,
+        crate::api_handlers::list_sensors,
+        crate::api_handlers::show_sensor,
+        crate::api_handlers::rename_sensor,
+        crate::api_handlers::remove_sensor,
+        crate::api_handlers::sensor_readings,
+        crate::api_handlers::latest_sensor_reading,
+        crate::api_handlers::latest_readings,
+        crate::api_handlers::list_actuators,
+        crate::api_handlers::show_actuator,
+        crate::api_handlers::rename_actuator,
+        crate::api_handlers::remove_actuator,
+        crate::api_handlers::change_actuator_state,
+        crate::api_handlers::list_scripts,
+        crate::api_handlers::create_script,
+        crate::api_handlers::show_script,
+        crate::api_handlers::modify_script,
+        crate::api_handlers::remove_script,
+        crate::api_handlers::run_script,
+        crate::api_handlers::cancel_script,
+        crate::api_handlers::script_runs,
+        crate::api_handlers::list_schedules,
+        crate::api_handlers::add_script_schedule,
+        crate::api_handlers::remove_script_schedule,
+        crate::export_handlers::export_sensor_reads_handler,
+    ),
+    components(schemas(
+        Sensor,
+        SensorRead,
+        SensorReadingBucket,
+        SensorReadings,
+        SensorReadingsPage,
+        Actuator,
+        Script,
+        ScriptRun,
+        ScriptDiagnostic,
+        RenameBody,
+        ActuatorStateBody,
+        ScriptBody,
+        ScheduleBody,
+        ApiErrorBody,
+        User,
+        UserCredentials,
+        LoginSession,
+    )),
+    modifiers(&BearerTokenAuth, &NoLicense),
+    security(("bearer_token" = [])),
+    tags(
+        (name = "auth", description = "Logging in and out"),
+        (name = "sensors", description = "Registered sensors and their readings"),
+        (name = "actuators", description = "Registered actuators and their state"),
+        (name = "scripts", description = "Scripts and their runs"),
+        (name = "schedules", description = "Scripts that run on a schedule"),
+        (name = "exports", description = "Bulk exports of sensor history"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token scheme and the 401 every operation can answer.
+struct BearerTokenAuth;
+
+impl Modify for BearerTokenAuth {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                BEARER_TOKEN_SCHEME,
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+
+        let unauthorized = ResponseBuilder::new()
+            .description("Missing, expired or revoked token")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ApiErrorBody"))
+                    .build(),
+            )
+            .build();
+
+        for path_item in openapi.paths.paths.values_mut() {
+            for operation in path_item.operations.values_mut() {
+                operation
+                    .responses
+                    .responses
+                    .entry("401".to_string())
+                    .or_insert_with(|| unauthorized.clone().into());
+            }
+        }
+    }
+}
+
+/// The crate has no license field, which utoipa turns into an empty license.
+struct NoLicense;
+
+impl Modify for NoLicense {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        openapi.info.license = None;
+    }
+}
+
+/// Serves the document, without a token so clients can be generated from it.
+pub async fn openapi_json_handler() -> Json<OpenApiDocument> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_handlers::{api_routes, API_V1_PATH};
+    use crate::export_handlers::EXPORT_SENSOR_READS_PATH;
+    use std::collections::BTreeSet;
+    use utoipa::openapi::PathItemType;
+
+    fn get_method_name(item_type: &PathItemType) -> &'static str {
+        match item_type {
+            PathItemType::Get => "GET",
+            PathItemType::Post => "POST",
+            PathItemType::Put => "PUT",
+            PathItemType::Delete => "DELETE",
+            PathItemType::Options => "OPTIONS",
+            PathItemType::Head => "HEAD",
+            PathItemType::Patch => "PATCH",
+            PathItemType::Trace => "TRACE",
+            PathItemType::Connect => "CONNECT",
+        }
+    }
+
+    /// `/sensors/:id` as OpenAPI writes it, `/sensors/{id}`.
+    fn to_openapi_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => format!("{{{}}}", name),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+
+    #[test]
+    fn test_openapi_matches_routes() {
+        let openapi = ApiDoc::openapi();
+
+        let documented = openapi
+            .paths
+            .paths
+            .iter()
+            .flat_map(|(path, path_item)| {
+                path_item
+                    .operations
+                    .keys()
+                    .map(move |item_type| (get_method_name(item_type).to_string(), path.clone()))
+            })
+            .collect::<BTreeSet<(String, String)>>();
+
+        let mut served = api_routes()
+            .iter()
+            .map(|route| {
+                (
+                    route.get_method().to_string(),
+                    to_openapi_path(&format!("{}{}", API_V1_PATH, route.get_path())),
+                )
+            })
+            .collect::<BTreeSet<(String, String)>>();
+        served.insert(("GET".to_string(), EXPORT_SENSOR_READS_PATH.to_string()));
+
+        assert_eq!(
+            documented.difference(&served).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "documented but not served"
+        );
+        assert_eq!(
+            served.difference(&documented).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "served but not documented"
+        );
+
+        let json = serde_json::to_value(&openapi).unwrap();
+        assert_eq!(
+            json["paths"]["/api/v1/sensors/{id}"]["get"]["responses"]["401"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ApiErrorBody"
+        );
+        assert!(json["components"]["schemas"]["SensorReadingsPage"].is_object());
+
+        // Public routes opt out of the document-wide bearer token.
+        for route in api_routes() {
+            let path = to_openapi_path(&format!("{}{}", API_V1_PATH, route.get_path()));
+            let operation =
+                &json["paths"][&path][route.get_method().as_str().to_lowercase().as_str()];
+
+            assert_eq!(
+                operation["security"] == serde_json::json!([{}]),
+                !route.get_requires_session(),
+                "security of {} {}",
+                route.get_method(),
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,939 @@
+use crate::actuator_handlers::switch_actuator;
+use crate::actuator_methods::{
+    change_actuator_name, get_actuator, get_all_registered_actuators, unregister_actuator,
+};
+use crate::auth::{
+    authenticate_headers, disconnect_sessions, login, revoke_session, AuthError, AuthSession,
+    LoginSession,
+};
+use crate::events::{
+    ACTUATOR_NAME_CHANGE_EVENT, ACTUATOR_STATE_CHANGE_EVENT, ACTUATOR_UNREGISTER_EVENT,
+    SCRIPT_DELETED_EVENT, SCRIPT_MODIFIED_EVENT, SCRIPT_SAVED_EVENT, SCRIPT_SCHEDULE_ADDED_EVENT,
+    SCRIPT_SCHEDULE_REMOVED_EVENT, SENSOR_NAME_CHANGE_EVENT, SENSOR_UNREGISTER_EVENT,
+};
+use crate::helper::{DashboardTarget, InvalidInput};
+use crate::models::{
+    Actuator, GetSensorReadings, NewScript, Script, ScriptRun, Sensor, SensorRead,
+    SensorReadingsPage, SensorUnregister, UpdateActuatorName, UpdateScript, UpdateSensorName, User,
+    UserCredentials,
+};
+use crate::protocol::{
+    ActuatorRenamed, ActuatorStateChanged, ActuatorUnregistered, ScriptChanged, SensorRenamed,
+    SensorUnregistered,
+};
+use crate::script_lexer::ScriptDiagnostic;
+use crate::script_methods::{
+    delete_script, get_scheduled_scripts, get_script, get_script_runs, get_scripts,
+    save_new_script, set_script_schedule, update_script, ScriptValidationError,
+    SCRIPT_TRIGGER_MANUAL,
+};
+use crate::script_runner::{spawn_script, stop_script, ScriptSchedule};
+use crate::sensor_methods::{
+    change_sensor_name, get_all_last_sensor_readings, get_all_registered_sensors,
+    get_last_sensor_read, get_sensor, get_sensor_readings, unregister_sensor,
+};
+use anyhow::Error;
+use axum::extract::{Path, Query, State};
+use axum::handler::Handler;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{on, MethodFilter, MethodRouter};
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use socketioxide::SocketIo;
+use utoipa::{IntoParams, ToSchema};
+
+/// An error answered with its HTTP status and `{"error": message}`. Script
+/// validation errors also carry their diagnostics.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    error: Error,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Self {
+            status,
+            error: Error::msg(message.to_string()),
+        }
+    }
+
+    pub fn get_status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// Rejected parameters and payloads are told apart by their error type, as
+/// are missing rows; any other error is a failure on the server's side.
+impl<E: Into<Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        let error = error.into();
+
+        let status = if error.is::<AuthError>() {
+            StatusCode::UNAUTHORIZED
+        } else if error.is::<ScriptValidationError>() {
+            StatusCode::UNPROCESSABLE_ENTITY
+        } else if error.is::<InvalidInput>() || error.is::<serde_json::Error>() {
+            StatusCode::BAD_REQUEST
+        } else if let Some(diesel::result::Error::NotFound) =
+            error.downcast_ref::<diesel::result::Error>()
+        {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+
+        Self { status, error }
+    }
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diagnostics: Option<Vec<ScriptDiagnostic>>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ApiErrorBody {
+            error: self.error.to_string(),
+            diagnostics: self
+                .error
+                .downcast_ref::<ScriptValidationError>()
+                .map(|validation_error| validation_error.get_diagnostics().clone()),
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Runs the database work of a handler on the blocking thread pool, so slow
+/// queries do not hold up the runtime serving the sockets.
+async fn run_blocking<T, F>(f: F) -> ApiResult<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f).await??)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RenameBody {
+    name: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ActuatorStateBody {
+    state: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScriptBody {
+    title: String,
+    code: String,
+    schedule: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScheduleBody {
+    schedule: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReadingsQuery {
+    /// Local time (`2024-02-14 10:00:00`) or RFC 3339.
+    from_date: String,
+    to_date: String,
+    /// `auto` (default), `raw`, `minute`, `hour` or `day`.
+    bucket: Option<String>,
+    /// `avg` (default), `min`, `max`, `sum` or `count`.
+    aggregate: Option<String>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+    /// 1000 by default, at most 10000.
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScriptRunsQuery {
+    /// Id of the last run of the previous page.
+    before_id: Option<i32>,
+    /// 20 by default, at most 200.
+    limit: Option<i64>,
+}
+
+/// Requests need `Authorization: Bearer <token>` with the token of a login.
+/// Handlers can take the session as an `Extension<AuthSession>`.
+async fn require_session<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let headers = request.headers().clone();
+
+    let auth_session = match run_blocking(move || authenticate_headers(&headers)).await {
+        Ok(auth_session) => auth_session,
+        Err(e) => return e.into_response(),
+    };
+
+    request.extensions_mut().insert(auth_session);
+
+    next.run(request).await
+}
+
+pub const API_V1_PATH: &str = "/api/v1";
+
+/// One method and path of the API. `api_router` is built from these, so the
+/// OpenAPI document can be checked against the routes actually served.
+pub struct ApiRoute {
+    method: Method,
+    path: &'static str,
+    handler: MethodRouter<SocketIo>,
+    requires_session: bool,
+}
+
+impl ApiRoute {
+    fn new<H, T>(method: Method, path: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, SocketIo>,
+        T: 'static,
+    {
+        let method_filter = MethodFilter::try_from(method.clone()).expect("Unsupported method");
+
+        Self {
+            method,
+            path,
+            handler: on(method_filter, handler),
+            requires_session: true,
+        }
+    }
+
+    /// A route that can be called without a session token.
+    fn public<H, T>(method: Method, path: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, SocketIo>,
+        T: 'static,
+    {
+        Self {
+            requires_session: false,
+            ..Self::new(method, path, handler)
+        }
+    }
+
+    pub fn get_method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Path below `/api/v1`, with axum's `:id` parameters.
+    pub fn get_path(&self) -> &'static str {
+        self.path
+    }
+
+    pub fn get_requires_session(&self) -> bool {
+        self.requires_session
+    }
+}
+
+pub fn api_routes() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute::public(Method::POST, "/auth/login", login_user),
+        ApiRoute::new(Method::POST, "/auth/logout", logout_user),
+        ApiRoute::new(Method::GET, "/auth/me", show_current_user),
+        ApiRoute::new(Method::GET, "/sensors", list_sensors),
+        ApiRoute::new(Method::GET, "/sensors/:id", show_sensor),
+        ApiRoute::new(Method::PATCH, "/sensors/:id", rename_sensor),
+        ApiRoute::new(Method::DELETE, "/sensors/:id", remove_sensor),
+        ApiRoute::new(Method::GET, "/sensors/:id/readings", sensor_readings),
+        ApiRoute::new(
+            Method::GET,
+            "/sensors/:id/readings/latest",
+            latest_sensor_reading,
+        ),
+        ApiRoute::new(Method::GET, "/readings/latest", latest_readings),
+        ApiRoute::new(Method::GET, "/actuators", list_actuators),
+        ApiRoute::new(Method::GET, "/actuators/:id", show_actuator),
+        ApiRoute::new(Method::PATCH, "/actuators/:id", rename_actuator),
+        ApiRoute::new(Method::DELETE, "/actuators/:id", remove_actuator),
+        ApiRoute::new(Method::PUT, "/actuators/:id/state", change_actuator_state),
+        ApiRoute::new(Method::GET, "/scripts", list_scripts),
+        ApiRoute::new(Method::POST, "/scripts", create_script),
+        ApiRoute::new(Method::GET, "/scripts/:id", show_script),
+        ApiRoute::new(Method::PUT, "/scripts/:id", modify_script),
+        ApiRoute::new(Method::DELETE, "/scripts/:id", remove_script),
+        ApiRoute::new(Method::POST, "/scripts/:id/run", run_script),
+        ApiRoute::new(Method::POST, "/scripts/:id/stop", cancel_script),
+        ApiRoute::new(Method::GET, "/scripts/:id/runs", script_runs),
+        ApiRoute::new(Method::PUT, "/scripts/:id/schedule", add_script_schedule),
+        ApiRoute::new(
+            Method::DELETE,
+            "/scripts/:id/schedule",
+            remove_script_schedule,
+        ),
+        ApiRoute::new(Method::GET, "/schedules", list_schedules),
+    ]
+}
+
+/// Version 1 of the JSON API, nested under `/api/v1`. Changes made through it
+/// are announced to the dashboards like their Socket.IO counterparts.
+pub fn api_router(io: SocketIo) -> Router {
+    let (session_routes, public_routes): (Vec<ApiRoute>, Vec<ApiRoute>) = api_routes()
+        .into_iter()
+        .partition(|route| route.requires_session);
+
+    let router = session_routes
+        .into_iter()
+        .fold(Router::new(), |router, route| {
+            router.route(route.path, route.handler)
+        })
+        .route_layer(middleware::from_fn(require_session));
+
+    public_routes
+        .into_iter()
+        .fold(router, |router, route| {
+            router.route(route.path, route.handler)
+        })
+        .with_state(io)
+}
+
+//AUTH
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    request_body = UserCredentials,
+    responses(
+        (status = 200, description = "A session token for the other requests", body = LoginSession),
+        (status = 401, description = "Invalid username or password", body = ApiErrorBody),
+    ),
+    security(())
+)]
+async fn login_user(Json(body): Json<UserCredentials>) -> ApiResult<Json<LoginSession>> {
+    Ok(Json(
+        run_blocking(move || login(body.get_username(), body.get_password())).await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Session revoked, its sockets are disconnected"),
+    )
+)]
+async fn logout_user(
+    State(io): State<SocketIo>,
+    Extension(auth_session): Extension<AuthSession>,
+) -> ApiResult<StatusCode> {
+    let token_id = auth_session.get_session().get_token_id().to_string();
+
+    let revoked_token_id = token_id.clone();
+
+    run_blocking(move || revoke_session(&revoked_token_id)).await?;
+    disconnect_sessions(&io, &[token_id]);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The user the token belongs to", body = User),
+    )
+)]
+async fn show_current_user(Extension(auth_session): Extension<AuthSession>) -> Json<User> {
+    Json(auth_session.get_user().clone())
+}
+
+//SENSORS
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sensors",
+    tag = "sensors",
+    responses(
+        (status = 200, description = "Registered sensors", body = Vec<Sensor>),
+    )
+)]
+async fn list_sensors() -> ApiResult<Json<Vec<Sensor>>> {
+    Ok(Json(run_blocking(get_all_registered_sensors).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sensors/{id}",
+    tag = "sensors",
+    params(("id" = i32, Path, description = "Sensor id")),
+    responses(
+        (status = 200, description = "The sensor", body = Sensor),
+        (status = 404, description = "No such sensor", body = ApiErrorBody),
+    )
+)]
+async fn show_sensor(Path(sensor_id): Path<i32>) -> ApiResult<Json<Sensor>> {
+    Ok(Json(run_blocking(move || get_sensor(sensor_id)).await?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/sensors/{id}",
+    tag = "sensors",
+    params(("id" = i32, Path, description = "Sensor id")),
+    request_body = RenameBody,
+    responses(
+        (status = 200, description = "The renamed sensor", body = Sensor),
+        (status = 404, description = "No such sensor", body = ApiErrorBody),
+    )
+)]
+async fn rename_sensor(
+    State(io): State<SocketIo>,
+    Path(sensor_id): Path<i32>,
+    Json(body): Json<RenameBody>,
+) -> ApiResult<Json<Sensor>> {
+    let payload = serde_json::to_string(&UpdateSensorName::new(sensor_id, &body.name))?;
+    let sensor = run_blocking(move || change_sensor_name(payload)).await?;
+
+    DashboardTarget::All(io).emit_to_all(SENSOR_NAME_CHANGE_EVENT, SensorRenamed::new(&sensor));
+
+    Ok(Json(sensor))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/sensors/{id}",
+    tag = "sensors",
+    params(("id" = i32, Path, description = "Sensor id")),
+    responses(
+        (status = 204, description = "Sensor unregistered"),
+        (status = 404, description = "No such sensor", body = ApiErrorBody),
+    )
+)]
+async fn remove_sensor(
+    State(io): State<SocketIo>,
+    Path(sensor_id): Path<i32>,
+) -> ApiResult<StatusCode> {
+    let payload = serde_json::to_string(&SensorUnregister::new(sensor_id))?;
+    let sensor = run_blocking(move || unregister_sensor(payload)).await?;
+
+    DashboardTarget::All(io).emit_to_all(
+        SENSOR_UNREGISTER_EVENT,
+        SensorUnregistered::new(sensor.get_id()),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sensors/{id}/readings",
+    tag = "sensors",
+    params(("id" = i32, Path, description = "Sensor id"), ReadingsQuery),
+    responses(
+        (status = 200, description = "One page of the sensor's history", body = SensorReadingsPage),
+        (status = 400, description = "Invalid dates, bucket or aggregate", body = ApiErrorBody),
+        (status = 404, description = "No such sensor", body = ApiErrorBody),
+    )
+)]
+async fn sensor_readings(
+    Path(sensor_id): Path<i32>,
+    Query(query): Query<ReadingsQuery>,
+) -> ApiResult<Json<SensorReadingsPage>> {
+    let mut request = GetSensorReadings::new(sensor_id, query.from_date, query.to_date);
+    request.set_bucket(query.bucket);
+    request.set_aggregate(query.aggregate);
+    request.set_cursor(query.cursor);
+    request.set_limit(query.limit);
+
+    let page = run_blocking(move || {
+        get_sensor(sensor_id)?;
+        get_sensor_readings(&request)
+    })
+    .await?;
+
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sensors/{id}/readings/latest",
+    tag = "sensors",
+    params(("id" = i32, Path, description = "Sensor id")),
+    responses(
+        (status = 200, description = "Newest reading of the sensor", body = SensorRead),
+        (status = 404, description = "No such sensor or no readings yet", body = ApiErrorBody),
+    )
+)]
+async fn latest_sensor_reading(Path(sensor_id): Path<i32>) -> ApiResult<Json<SensorRead>> {
+    let sensor_read = run_blocking(move || {
+        get_sensor(sensor_id)?;
+        get_last_sensor_read(sensor_id)
+    })
+    .await?;
+
+    match sensor_read {
+        Some(sensor_read) => Ok(Json(sensor_read)),
+        None => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "Sensor has no readings yet",
+        )),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/readings/latest",
+    tag = "sensors",
+    responses(
+        (status = 200, description = "Newest reading of every sensor", body = Vec<SensorRead>),
+    )
+)]
+async fn latest_readings() -> ApiResult<Json<Vec<SensorRead>>> {
+    Ok(Json(run_blocking(get_all_last_sensor_readings).await?))
+}
+
+//ACTUATORS
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/actuators",
+    tag = "actuators",
+    responses(
+        (status = 200, description = "Registered actuators", body = Vec<Actuator>),
+    )
+)]
+async fn list_actuators() -> ApiResult<Json<Vec<Actuator>>> {
+    Ok(Json(run_blocking(get_all_registered_actuators).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/actuators/{id}",
+    tag = "actuators",
+    params(("id" = i32, Path, description = "Actuator id")),
+    responses(
+        (status = 200, description = "The actuator", body = Actuator),
+        (status = 404, description = "No such actuator", body = ApiErrorBody),
+    )
+)]
+async fn show_actuator(Path(actuator_id): Path<i32>) -> ApiResult<Json<Actuator>> {
+    Ok(Json(run_blocking(move || get_actuator(actuator_id)).await?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/actuators/{id}",
+    tag = "actuators",
+    params(("id" = i32, Path, description = "Actuator id")),
+    request_body = RenameBody,
+    responses(
+        (status = 200, description = "The renamed actuator", body = Actuator),
+        (status = 404, description = "No such actuator", body = ApiErrorBody),
+    )
+)]
+async fn rename_actuator(
+    State(io): State<SocketIo>,
+    Path(actuator_id): Path<i32>,
+    Json(body): Json<RenameBody>,
+) -> ApiResult<Json<Actuator>> {
+    let payload = serde_json::to_string(&UpdateActuatorName::new(actuator_id, &body.name))?;
+    let actuator = run_blocking(move || change_actuator_name(payload)).await?;
+
+    DashboardTarget::All(io)
+        .emit_to_all(ACTUATOR_NAME_CHANGE_EVENT, ActuatorRenamed::new(&actuator));
+
+    Ok(Json(actuator))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/actuators/{id}",
+    tag = "actuators",
+    params(("id" = i32, Path, description = "Actuator id")),
+    responses(
+        (status = 204, description = "Actuator unregistered"),
+        (status = 404, description = "No such actuator", body = ApiErrorBody),
+    )
+)]
+async fn remove_actuator(
+    State(io): State<SocketIo>,
+    Path(actuator_id): Path<i32>,
+) -> ApiResult<StatusCode> {
+    let payload = serde_json::to_string(&SensorUnregister::new(actuator_id))?;
+    let actuator = run_blocking(move || unregister_actuator(payload)).await?;
+
+    DashboardTarget::All(io).emit_to_all(
+        ACTUATOR_UNREGISTER_EVENT,
+        ActuatorUnregistered::new(actuator.get_id()),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends ON or OFF to the device and stores the state once it confirms.
+#[utoipa::path(
+    put,
+    path = "/api/v1/actuators/{id}/state",
+    tag = "actuators",
+    params(("id" = i32, Path, description = "Actuator id")),
+    request_body = ActuatorStateBody,
+    responses(
+        (status = 200, description = "The actuator in its new state", body = Actuator),
+        (status = 404, description = "No such actuator", body = ApiErrorBody),
+        (status = 502, description = "The device did not confirm the state", body = ApiErrorBody),
+    )
+)]
+async fn change_actuator_state(
+    State(io): State<SocketIo>,
+    Path(actuator_id): Path<i32>,
+    Json(body): Json<ActuatorStateBody>,
+) -> ApiResult<Json<Actuator>> {
+    run_blocking(move || get_actuator(actuator_id)).await?;
+
+    let actuator =
+        match tokio::task::spawn_blocking(move || switch_actuator(actuator_id, body.state)).await {
+            Ok(Ok(actuator)) => actuator,
+            Ok(Err(e)) => {
+                return Err(ApiError::new(StatusCode::BAD_GATEWAY, &e.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+    DashboardTarget::All(io).emit_to_all(
+        ACTUATOR_STATE_CHANGE_EVENT,
+        ActuatorStateChanged::new(&actuator),
+    );
+
+    Ok(Json(actuator))
+}
+
+//SCRIPTS
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/scripts",
+    tag = "scripts",
+    responses(
+        (status = 200, description = "Saved scripts", body = Vec<Script>),
+    )
+)]
+async fn list_scripts() -> ApiResult<Json<Vec<Script>>> {
+    Ok(Json(run_blocking(get_scripts).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/scripts/{id}",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id")),
+    responses(
+        (status = 200, description = "The script", body = Script),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn show_script(Path(script_id): Path<i32>) -> ApiResult<Json<Script>> {
+    Ok(Json(run_blocking(move || get_script(script_id)).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/scripts",
+    tag = "scripts",
+    request_body = ScriptBody,
+    responses(
+        (status = 201, description = "The saved script", body = Script),
+        (status = 400, description = "Invalid schedule", body = ApiErrorBody),
+        (status = 422, description = "Invalid script, with diagnostics", body = ApiErrorBody),
+    )
+)]
+async fn create_script(
+    State(io): State<SocketIo>,
+    Json(body): Json<ScriptBody>,
+) -> ApiResult<(StatusCode, Json<Script>)> {
+    if let Some(schedule) = &body.schedule {
+        ScriptSchedule::parse(schedule)?;
+    }
+
+    let mut new_script = NewScript::new(&body.code);
+    new_script.set_title(body.title);
+    new_script.set_schedule(body.schedule);
+
+    let payload = serde_json::to_string(&new_script)?;
+    let script = run_blocking(move || save_new_script(payload)).await?;
+
+    DashboardTarget::All(io).emit_to_all(SCRIPT_SAVED_EVENT, ScriptChanged::new(script.clone()));
+
+    Ok((StatusCode::CREATED, Json(script)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/scripts/{id}",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id")),
+    request_body = ScriptBody,
+    responses(
+        (status = 200, description = "The updated script", body = Script),
+        (status = 400, description = "Invalid schedule", body = ApiErrorBody),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+        (status = 422, description = "Invalid script, with diagnostics", body = ApiErrorBody),
+    )
+)]
+async fn modify_script(
+    State(io): State<SocketIo>,
+    Path(script_id): Path<i32>,
+    Json(body): Json<ScriptBody>,
+) -> ApiResult<Json<Script>> {
+    if let Some(schedule) = &body.schedule {
+        ScriptSchedule::parse(schedule)?;
+    }
+
+    let script = run_blocking(move || {
+        let current_script = get_script(script_id)?;
+
+        let mut update = UpdateScript::new(script_id, &body.code);
+        update.set_title(body.title);
+        update.set_schedule(body.schedule);
+        update.set_status(current_script.get_status());
+        update.set_updated_at(chrono::Local::now().naive_local());
+
+        update_script(serde_json::to_string(&update)?)
+    })
+    .await?;
+
+    DashboardTarget::All(io).emit_to_all(SCRIPT_MODIFIED_EVENT, ScriptChanged::new(script.clone()));
+
+    Ok(Json(script))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/scripts/{id}",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id")),
+    responses(
+        (status = 204, description = "Script and its runs deleted"),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn remove_script(
+    State(io): State<SocketIo>,
+    Path(script_id): Path<i32>,
+) -> ApiResult<StatusCode> {
+    let script = run_blocking(move || get_script(script_id)).await?;
+
+    stop_script(script_id);
+    run_blocking(move || delete_script(script_id)).await?;
+
+    DashboardTarget::All(io).emit_to_all(SCRIPT_DELETED_EVENT, ScriptChanged::new(script));
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Starts a run in the background; its output goes to every dashboard.
+#[utoipa::path(
+    post,
+    path = "/api/v1/scripts/{id}/run",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id")),
+    responses(
+        (status = 202, description = "Run started"),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn run_script(
+    State(io): State<SocketIo>,
+    Path(script_id): Path<i32>,
+) -> ApiResult<StatusCode> {
+    run_blocking(move || get_script(script_id)).await?;
+
+    spawn_script(script_id, SCRIPT_TRIGGER_MANUAL, DashboardTarget::All(io));
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/scripts/{id}/stop",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id")),
+    responses(
+        (status = 204, description = "Run cancelled"),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+        (status = 409, description = "Script is not running", body = ApiErrorBody),
+    )
+)]
+async fn cancel_script(Path(script_id): Path<i32>) -> ApiResult<StatusCode> {
+    run_blocking(move || get_script(script_id)).await?;
+
+    if !stop_script(script_id) {
+        return Err(ApiError::new(StatusCode::CONFLICT, "Script is not running"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/scripts/{id}/runs",
+    tag = "scripts",
+    params(("id" = i32, Path, description = "Script id"), ScriptRunsQuery),
+    responses(
+        (status = 200, description = "Runs of the script, newest first", body = Vec<ScriptRun>),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn script_runs(
+    Path(script_id): Path<i32>,
+    Query(query): Query<ScriptRunsQuery>,
+) -> ApiResult<Json<Vec<ScriptRun>>> {
+    let script_runs = run_blocking(move || {
+        get_script(script_id)?;
+        get_script_runs(script_id, query.before_id, query.limit)
+    })
+    .await?;
+
+    Ok(Json(script_runs))
+}
+
+//SCHEDULES
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/schedules",
+    tag = "schedules",
+    responses(
+        (status = 200, description = "Scripts that run on a schedule", body = Vec<Script>),
+    )
+)]
+async fn list_schedules() -> ApiResult<Json<Vec<Script>>> {
+    Ok(Json(run_blocking(get_scheduled_scripts).await?))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/scripts/{id}/schedule",
+    tag = "schedules",
+    params(("id" = i32, Path, description = "Script id")),
+    request_body = ScheduleBody,
+    responses(
+        (status = 200, description = "The scheduled script", body = Script),
+        (status = 400, description = "Invalid schedule", body = ApiErrorBody),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn add_script_schedule(
+    State(io): State<SocketIo>,
+    Path(script_id): Path<i32>,
+    Json(body): Json<ScheduleBody>,
+) -> ApiResult<Json<Script>> {
+    let script = run_blocking(move || set_script_schedule(script_id, Some(body.schedule))).await?;
+
+    DashboardTarget::All(io).emit_to_all(
+        SCRIPT_SCHEDULE_ADDED_EVENT,
+        ScriptChanged::new(script.clone()),
+    );
+
+    Ok(Json(script))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/scripts/{id}/schedule",
+    tag = "schedules",
+    params(("id" = i32, Path, description = "Script id")),
+    responses(
+        (status = 200, description = "The unscheduled script", body = Script),
+        (status = 404, description = "No such script", body = ApiErrorBody),
+    )
+)]
+async fn remove_script_schedule(
+    State(io): State<SocketIo>,
+    Path(script_id): Path<i32>,
+) -> ApiResult<Json<Script>> {
+    let script = run_blocking(move || set_script_schedule(script_id, None)).await?;
+
+    DashboardTarget::All(io).emit_to_all(
+        SCRIPT_SCHEDULE_REMOVED_EVENT,
+        ScriptChanged::new(script.clone()),
+    );
+
+    Ok(Json(script))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_api_error_status() {
+        let status = |error: Error| ApiError::from(error).get_status();
+
+        assert_eq!(
+            status(Error::from(diesel::result::Error::NotFound)),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(Error::from(diesel::result::Error::BrokenTransactionManager)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(Error::from(
+                serde_json::from_str::<ScheduleBody>("{}").unwrap_err()
+            )),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(ScriptSchedule::parse("every -1 minutes").err().unwrap()),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(
+                ScriptSchedule::parse("every 99999999999999999999 minutes")
+                    .err()
+                    .unwrap()
+            ),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(Error::msg("Error hashing password")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(Error::from(std::io::Error::other("disk full"))),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_requires_token() {
+        let (_, io) = SocketIo::new_layer();
+        let app = Router::new().nest(API_V1_PATH, api_router(io));
+
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(
+                b"GET /api/v1/sensors HTTP/1.1\r\nHost: homesoil\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.ends_with(r#"{"error":"Invalid token"}"#));
+    }
+}
@@ -1 +1,609 @@
+use crate::db::connect;
+use crate::models::{NewUser, NewUserSession, User, UserSession};
+use crate::schema::{user_sessions, users};
+use anyhow::{Error, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::http::{header, HeaderMap};
+use chrono::Duration;
+use diesel::prelude::*;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use socketioxide::SocketIo;
+use std::env;
+use std::fmt;
+use std::sync::OnceLock;
+use utoipa::ToSchema;
 
+const DEFAULT_SESSION_TTL_HOURS: i64 = 24;
+
+const MIN_SESSION_SECRET_LENGTH: usize = 32;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+const MAX_USERNAME_LENGTH: usize = 64;
+
+/// Hash checked when the username is unknown, so a failed login takes as
+/// long whether or not the account exists. Made with the default argon2
+/// parameters, the same as `hash_password`.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$F5JUWNiqiSh/yN5auKcrsw$f9fqkVQNEpEgfr3RY8+U5lenr7YUydqApL/H2z3tEG8";
+
+static AUTH_CONFIG: OnceLock<AuthConfig> = OnceLock::new();
+
+/// Wrong credentials, or a missing, expired or revoked session token.
+#[derive(Debug)]
+pub struct AuthError {
+    message: &'static str,
+}
+
+fn auth_error(message: &'static str) -> Error {
+    Error::new(AuthError { message })
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Key session tokens are signed with and how long they last.
+pub struct AuthConfig {
+    secret: Vec<u8>,
+    session_ttl: Duration,
+}
+
+impl AuthConfig {
+    pub fn new(secret: &[u8], session_ttl: Duration) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            session_ttl,
+        }
+    }
+
+    /// `SESSION_SECRET` is required, `SESSION_TTL_HOURS` defaults to 24.
+    fn from_env() -> Result<Self> {
+        let secret = env::var("SESSION_SECRET").unwrap_or_default();
+
+        if secret.len() < MIN_SESSION_SECRET_LENGTH {
+            return Err(Error::msg(format!(
+                "SESSION_SECRET must be set to at least {} characters",
+                MIN_SESSION_SECRET_LENGTH
+            )));
+        }
+
+        let hours = env::var("SESSION_TTL_HOURS")
+            .ok()
+            .and_then(|hours| hours.trim().parse::<i64>().ok())
+            .unwrap_or(DEFAULT_SESSION_TTL_HOURS);
+
+        Ok(Self::new(secret.as_bytes(), Duration::hours(hours.max(1))))
+    }
+
+    pub fn get_session_ttl(&self) -> Duration {
+        self.session_ttl
+    }
+
+    fn sign_token(&self, claims: &SessionClaims) -> Result<String> {
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            claims,
+            &EncodingKey::from_secret(&self.secret),
+        )?;
+
+        Ok(token)
+    }
+
+    /// Checks the signature and expiry, not whether the session was revoked.
+    fn decode_token(&self, token: &str) -> Result<SessionClaims> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub", "jti"]);
+
+        match decode::<SessionClaims>(token, &DecodingKey::from_secret(&self.secret), &validation) {
+            Ok(data) => Ok(data.claims),
+            Err(e) => match e.kind() {
+                ErrorKind::ExpiredSignature => Err(auth_error("Session expired")),
+                _ => Err(auth_error("Invalid token")),
+            },
+        }
+    }
+}
+
+/// Read once from the environment. `main` calls this at startup, so a missing
+/// secret stops the hub instead of failing every login.
+pub fn get_auth_config() -> Result<&'static AuthConfig> {
+    if let Some(config) = AUTH_CONFIG.get() {
+        return Ok(config);
+    }
+
+    let config = AuthConfig::from_env()?;
+
+    Ok(AUTH_CONFIG.get_or_init(|| config))
+}
+
+/// Claims of a session token. `jti` is the `token_id` of its session row,
+/// which is what revocation marks.
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+    sub: String,
+    jti: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// A signed in user and the session their token belongs to.
+#[derive(Debug, Clone)]
+pub struct AuthSession {
+    user: User,
+    session: UserSession,
+}
+
+impl AuthSession {
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn get_session(&self) -> &UserSession {
+        &self.session
+    }
+}
+
+/// Answer to a successful login.
+#[derive(Debug, Clone, Serialize, ToSchema, JsonSchema)]
+pub struct LoginSession {
+    /// Sent as `Authorization: Bearer <token>`, or as `{"token": ...}` in the
+    /// Socket.IO handshake.
+    token: String,
+    expires_at: chrono::NaiveDateTime,
+    user: User,
+}
+
+impl LoginSession {
+    pub fn get_token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn get_expires_at(&self) -> &chrono::NaiveDateTime {
+        &self.expires_at
+    }
+
+    pub fn get_user(&self) -> &User {
+        &self.user
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(Error::msg(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(Error::msg(format!("Error hashing password: {}", e))),
+    }
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn normalize_username(username: &str) -> Result<String> {
+    let username = username.trim();
+
+    if username.is_empty()
+        || username.chars().count() > MAX_USERNAME_LENGTH
+        || username
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(Error::msg(format!("Invalid username '{}'", username)));
+    }
+
+    Ok(username.to_string())
+}
+
+fn generate_token_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+//USERS
+
+pub fn get_users() -> Result<Vec<User>> {
+    let conn = &mut connect()?;
+
+    let users = users::table
+        .order(users::username.asc())
+        .load::<User>(conn)?;
+
+    Ok(users)
+}
+
+/// Only admins can add and remove users.
+pub fn require_admin(user: &User) -> Result<()> {
+    if !user.get_is_admin() {
+        return Err(auth_error("Only admins can manage users"));
+    }
+
+    Ok(())
+}
+
+pub fn create_user(username: &str, password: &str, is_admin: bool) -> Result<User> {
+    let conn = &mut connect()?;
+
+    let username = normalize_username(username)?;
+
+    let existing = users::table
+        .filter(users::username.eq(&username))
+        .first::<User>(conn)
+        .optional()?;
+
+    if existing.is_some() {
+        return Err(Error::msg(format!("User '{}' already exists", username)));
+    }
+
+    let mut user = NewUser::new(&username, &hash_password(password)?);
+    user.set_is_admin(is_admin);
+    user.set_created_at(chrono::Local::now().naive_local());
+
+    let user = diesel::insert_into(users::table)
+        .values(&user)
+        .get_result::<User>(conn)?;
+
+    Ok(user)
+}
+
+/// Deletes a user with their sessions. The last admin cannot be deleted, or
+/// nobody could manage the users again. Returns the user and the token ids
+/// of the sessions that were still open.
+pub fn delete_user(user_id: i32) -> Result<(User, Vec<String>)> {
+    let conn = &mut connect()?;
+
+    let user = users::table.find(user_id).first::<User>(conn)?;
+
+    if user.get_is_admin() {
+        let admin_count = users::table
+            .filter(users::is_admin.eq(true))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        if admin_count <= 1 {
+            return Err(Error::msg("Cannot delete the last admin"));
+        }
+    }
+
+    let token_ids = conn.transaction(|conn| {
+        let token_ids = user_sessions::table
+            .filter(user_sessions::user_id.eq(user_id))
+            .filter(user_sessions::revoked_at.is_null())
+            .select(user_sessions::token_id)
+            .load::<String>(conn)?;
+
+        diesel::delete(user_sessions::table.filter(user_sessions::user_id.eq(user_id)))
+            .execute(conn)?;
+
+        diesel::delete(users::table.find(user_id)).execute(conn)?;
+
+        diesel::QueryResult::Ok(token_ids)
+    })?;
+
+    Ok((user, token_ids))
+}
+
+/// Changes the password of a signed in user and revokes their sessions but
+/// the one the change was made from. Returns the user and the token ids of
+/// the revoked sessions.
+pub fn change_password(
+    auth_session: &AuthSession,
+    current_password: &str,
+    new_password: &str,
+) -> Result<(User, Vec<String>)> {
+    let conn = &mut connect()?;
+
+    // The session's copy of the user predates any earlier change.
+    let user = users::table
+        .find(auth_session.get_user().get_id())
+        .first::<User>(conn)?;
+
+    if !verify_password(current_password, user.get_password_hash()) {
+        return Err(auth_error("Invalid password"));
+    }
+
+    let password_hash = hash_password(new_password)?;
+
+    let user = diesel::update(users::table.find(user.get_id()))
+        .set((
+            users::password_hash.eq(password_hash),
+            users::updated_at.eq(chrono::Local::now().naive_local()),
+        ))
+        .get_result::<User>(conn)?;
+
+    let revoked_token_ids = revoke_user_sessions(
+        user.get_id(),
+        Some(auth_session.get_session().get_token_id()),
+    )?;
+
+    Ok((user, revoked_token_ids))
+}
+
+/// Creates the first user, an admin, from `ADMIN_USERNAME` and
+/// `ADMIN_PASSWORD` when the users table is empty.
+pub fn ensure_initial_user() -> Result<Option<User>> {
+    let conn = &mut connect()?;
+
+    let user_count = users::table.count().get_result::<i64>(conn)?;
+
+    if user_count > 0 {
+        return Ok(None);
+    }
+
+    let (username, password) =
+        match (env::var("ADMIN_USERNAME"), env::var("ADMIN_PASSWORD")) {
+            (Ok(username), Ok(password)) => (username, password),
+            _ => return Err(Error::msg(
+                "There are no users, set ADMIN_USERNAME and ADMIN_PASSWORD to create the first one",
+            )),
+        };
+
+    Ok(Some(create_user(&username, &password, true)?))
+}
+
+//SESSIONS
+
+/// Checks the credentials and opens a session.
+pub fn login(username: &str, password: &str) -> Result<LoginSession> {
+    let config = get_auth_config()?;
+    let conn = &mut connect()?;
+
+    let user = users::table
+        .filter(users::username.eq(username.trim()))
+        .first::<User>(conn)
+        .optional()?;
+
+    let user = match user {
+        Some(user) if verify_password(password, user.get_password_hash()) => user,
+        Some(_) => return Err(auth_error("Invalid username or password")),
+        None => {
+            verify_password(password, DUMMY_PASSWORD_HASH);
+            return Err(auth_error("Invalid username or password"));
+        }
+    };
+
+    let token_id = generate_token_id();
+    let now = chrono::Local::now().naive_local();
+    let expires_at = now + config.get_session_ttl();
+
+    let issued_at = chrono::Utc::now().timestamp();
+
+    let token = config.sign_token(&SessionClaims {
+        sub: user.get_id().to_string(),
+        jti: token_id.clone(),
+        iat: issued_at,
+        exp: issued_at + config.get_session_ttl().num_seconds(),
+    })?;
+
+    diesel::insert_into(user_sessions::table)
+        .values(&NewUserSession::new(
+            user.get_id(),
+            &token_id,
+            now,
+            expires_at,
+        ))
+        .execute(conn)?;
+
+    Ok(LoginSession {
+        token,
+        expires_at,
+        user,
+    })
+}
+
+/// The user and session of a token, as long as it is correctly signed, not
+/// expired and not revoked.
+pub fn authenticate(token: &str) -> Result<AuthSession> {
+    if token.is_empty() {
+        return Err(auth_error("Invalid token"));
+    }
+
+    let claims = get_auth_config()?.decode_token(token)?;
+
+    let conn = &mut connect()?;
+
+    let row = user_sessions::table
+        .inner_join(users::table)
+        .filter(user_sessions::token_id.eq(&claims.jti))
+        .select((UserSession::as_select(), User::as_select()))
+        .first::<(UserSession, User)>(conn)
+        .optional()?;
+
+    let (session, user) = match row {
+        Some((session, user)) if user.get_id().to_string() == claims.sub => (session, user),
+        _ => return Err(auth_error("Invalid token")),
+    };
+
+    if session.get_revoked_at().is_some() {
+        return Err(auth_error("Session revoked"));
+    }
+
+    if *session.get_expires_at() <= chrono::Local::now().naive_local() {
+        return Err(auth_error("Session expired"));
+    }
+
+    Ok(AuthSession { user, session })
+}
+
+/// Token of an `Authorization: Bearer <token>` header.
+pub fn get_bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+pub fn authenticate_headers(headers: &HeaderMap) -> Result<AuthSession> {
+    match get_bearer_token(headers) {
+        Some(token) => authenticate(token),
+        None => Err(auth_error("Invalid token")),
+    }
+}
+
+pub fn revoke_session(token_id: &str) -> Result<()> {
+    let conn = &mut connect()?;
+
+    diesel::update(
+        user_sessions::table
+            .filter(user_sessions::token_id.eq(token_id))
+            .filter(user_sessions::revoked_at.is_null()),
+    )
+    .set(user_sessions::revoked_at.eq(chrono::Local::now().naive_local()))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Revokes every open session of a user, but `except`. Returns the token ids
+/// of the revoked sessions.
+pub fn revoke_user_sessions(user_id: i32, except: Option<&str>) -> Result<Vec<String>> {
+    let conn = &mut connect()?;
+
+    let now = chrono::Local::now().naive_local();
+
+    let token_ids = user_sessions::table
+        .filter(user_sessions::user_id.eq(user_id))
+        .filter(user_sessions::revoked_at.is_null())
+        .filter(user_sessions::expires_at.gt(now))
+        .select(user_sessions::token_id)
+        .load::<String>(conn)?
+        .into_iter()
+        .filter(|token_id| except != Some(token_id.as_str()))
+        .collect::<Vec<String>>();
+
+    diesel::update(user_sessions::table.filter(user_sessions::token_id.eq_any(&token_ids)))
+        .set(user_sessions::revoked_at.eq(now))
+        .execute(conn)?;
+
+    Ok(token_ids)
+}
+
+/// Expired tokens fail their signature check anyway, so their sessions,
+/// revoked or not, are no longer needed.
+pub fn delete_expired_sessions() -> Result<usize> {
+    let conn = &mut connect()?;
+
+    let deleted = diesel::delete(
+        user_sessions::table
+            .filter(user_sessions::expires_at.le(chrono::Local::now().naive_local())),
+    )
+    .execute(conn)?;
+
+    Ok(deleted)
+}
+
+/// Sockets join the room of the session they connected with, so revoking a
+/// session can also close its connections.
+pub fn get_session_room(token_id: &str) -> String {
+    format!("session:{}", token_id)
+}
+
+pub fn disconnect_sessions(io: &SocketIo, token_ids: &[String]) {
+    if token_ids.is_empty() {
+        return;
+    }
+
+    let rooms = token_ids
+        .iter()
+        .map(|token_id| get_session_room(token_id))
+        .collect::<Vec<String>>();
+
+    if let Some(ns) = io.of("/") {
+        match ns.to(rooms).disconnect() {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error disconnecting revoked sessions: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify_password() {
+        let hash = hash_password("correct horse").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horse!", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+
+        assert!(hash_password("short").is_err());
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+
+        // The dummy hash has to cost as much to check as a real one.
+        let params = |hash: &str| hash.rsplitn(3, '$').last().unwrap().to_string();
+        assert_eq!(params(DUMMY_PASSWORD_HASH), params(&hash));
+        assert!(!verify_password("correct horse", DUMMY_PASSWORD_HASH));
+    }
+
+    #[test]
+    fn test_require_admin() {
+        assert!(require_admin(&User::new("admin", true)).is_ok());
+
+        let error = require_admin(&User::new("guest", false)).unwrap_err();
+        assert_eq!(error.to_string(), "Only admins can manage users");
+        assert!(error.is::<AuthError>());
+    }
+
+    #[test]
+    fn test_session_token_signature_and_expiry() {
+        let config = AuthConfig::new(&[7; 32], Duration::hours(1));
+        let now = chrono::Utc::now().timestamp();
+
+        let claims = |exp: i64| SessionClaims {
+            sub: "1".to_string(),
+            jti: "abc".to_string(),
+            iat: now,
+            exp,
+        };
+
+        let token = config.sign_token(&claims(now + 60)).unwrap();
+        let decoded = config.decode_token(&token).unwrap();
+        assert_eq!(decoded.sub, "1");
+        assert_eq!(decoded.jti, "abc");
+
+        let other_config = AuthConfig::new(&[8; 32], Duration::hours(1));
+        let error = other_config.decode_token(&token).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid token");
+        assert!(error.is::<AuthError>());
+
+        let expired_token = config.sign_token(&claims(now - 60)).unwrap();
+        assert_eq!(
+            config.decode_token(&expired_token).unwrap_err().to_string(),
+            "Session expired"
+        );
+
+        assert!(config.decode_token("not.a.token").is_err());
+    }
+}
@@ -56,7 +56,7 @@ impl CoAPClient {
                             message_id: 0,
                         })
                 }),
-                None => Err(Error::new(ErrorKind::Other, "no address")),
+                None => Err(Error::other("no address")),
             })
     }
 
@@ -66,7 +66,7 @@ impl CoAPClient {
             .and_then(|mut iter| match iter.next() {
                 Some(SocketAddr::V4(_)) => Self::new_with_specific_source("0.0.0.0:0", addr),
                 Some(SocketAddr::V6(_)) => Self::new_with_specific_source(":::0", addr),
-                None => Err(Error::new(ErrorKind::Other, "no address")),
+                None => Err(Error::other("no address")),
             })
     }
 
@@ -222,7 +222,7 @@ impl CoAPClient {
 
         let socket = match self.socket.try_clone() {
             Ok(good_socket) => good_socket,
-            Err(_) => return Err(Error::new(ErrorKind::Other, "network error")),
+            Err(_) => return Err(Error::other("network error")),
         };
 
         let peer_addr = self.peer_addr;
@@ -307,7 +307,7 @@ impl CoAPClient {
         while let Some((idx, elem)) = it.next() {
             let more_blocks = it.peek().is_some();
             let block = BlockValue::new(idx, more_blocks, self.block1_size)
-                .map_err(|_| Error::new(ErrorKind::Other, "could not set block size"))?;
+                .map_err(|_| Error::other("could not set block size"))?;
 
             request.message.clear_option(CoapOption::Block1);
             request
@@ -374,7 +374,7 @@ impl CoAPClient {
                 if size == bytes.len() {
                     Ok(())
                 } else {
-                    Err(Error::new(ErrorKind::Other, "send length error"))
+                    Err(Error::other("send length error"))
                 }
             }
             Err(_) => Err(Error::new(ErrorKind::InvalidInput, "packet error")),
@@ -443,7 +443,7 @@ impl CoAPClient {
         if size == message_bytes.len() {
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::Other, "send length error"))
+            Err(Error::other("send length error"))
         }
     }
 
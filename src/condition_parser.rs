@@ -1,7 +1,10 @@
 type Operator = &'static str;
 
 use crate::script_parser::{Args, Value, Variables};
+use crate::script_time::compare_times;
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use std::cmp::Ordering;
 
 const PARENTHESIS_OPEN: Operator = "(";
 const PARENTHESIS_CLOSE: Operator = ")";
@@ -46,6 +49,34 @@ fn match_operator(operator: &Value) -> Result<Operator> {
     }
 }
 
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int32(n) => Some(*n as f64),
+        Value::Int64(n) => Some(*n as f64),
+        Value::Float32(n) => Some(*n as f64),
+        Value::Float64(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Numbers compare by value whatever their width, so `30.5 > 30` holds, and
+/// times or dates in text compare chronologically, so `"6:30" < "18:00"`.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (as_number(left), as_number(right)) {
+        (Some(left), Some(right)) => left.partial_cmp(&right),
+        _ => match (left, right) {
+            (Value::String(l), Value::String(r)) => {
+                compare_times(l, r).or_else(|| left.partial_cmp(right))
+            }
+            _ => left.partial_cmp(right),
+        },
+    }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    compare(left, right) == Some(Ordering::Equal)
+}
+
 #[derive(Clone)]
 pub struct Expression {
     left: Value,
@@ -54,26 +85,28 @@ pub struct Expression {
 }
 
 impl Expression {
-    fn set_left(&mut self, left: Value) {
-        self.left = left;
-    }
-
-    fn set_operator(&mut self, operator: Operator) {
-        self.operator = operator;
-    }
-
-    fn set_right(&mut self, right: Value) {
-        self.right = right;
+    fn new(left: Value, operator: Operator, right: Value) -> Self {
+        Expression {
+            left,
+            operator,
+            right,
+        }
     }
 
     fn evaluate(&self) -> bool {
         match self.operator {
-            EQUAL => self.left == self.right,
-            NOT_EQUAL => self.left != self.right,
-            LESS => self.left < self.right,
-            LESS_OR_EQUAL => self.left <= self.right,
-            GREATER => self.left > self.right,
-            GREATER_OR_EQUAL => self.left >= self.right,
+            EQUAL => values_equal(&self.left, &self.right),
+            NOT_EQUAL => !values_equal(&self.left, &self.right),
+            LESS => compare(&self.left, &self.right) == Some(Ordering::Less),
+            LESS_OR_EQUAL => matches!(
+                compare(&self.left, &self.right),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            GREATER => compare(&self.left, &self.right) == Some(Ordering::Greater),
+            GREATER_OR_EQUAL => matches!(
+                compare(&self.left, &self.right),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
             IN => {
                 let right_vec = match &self.right {
                     Value::Array(right_vec) => right_vec,
@@ -81,7 +114,7 @@ impl Expression {
                 };
 
                 for right_value in right_vec {
-                    if self.left == *right_value {
+                    if values_equal(&self.left, right_value) {
                         return true;
                     }
                 }
@@ -95,7 +128,7 @@ impl Expression {
                 };
 
                 for right_value in right_vec {
-                    if self.left == *right_value {
+                    if values_equal(&self.left, right_value) {
                         return false;
                     }
                 }
@@ -140,85 +173,193 @@ impl Condition {
     }
 }
 
-pub fn parse_condition(mut condition_component_vec: Args, variables: &Variables) -> Condition {
-    let mut before_operator = true;
-    let mut sub_condition = None;
+fn is_symbol(value: Option<&Value>, symbol: &str) -> bool {
+    matches!(value, Some(Value::String(s)) if s == symbol)
+}
 
-    let mut expression = Expression {
-        left: Value::String("".to_string()),
-        operator: EQUAL,
-        right: Value::String("".to_string()),
-    };
+/// Recursive-descent parser over condition components: `||` binds looser than
+/// `&&`, parentheses group, and a lone operand is true when it equals `true`.
+struct ConditionParser<'a> {
+    components: Args,
+    position: usize,
+    variables: &'a Variables,
+    now: &'a DateTime<Local>,
+}
+
+impl<'a> ConditionParser<'a> {
+    fn peek(&self) -> Option<&Value> {
+        self.components.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Value> {
+        let component = self.components.get(self.position).cloned();
+        self.position += 1;
+        component
+    }
 
-    let mut final_condition = Condition::Expression(Expression {
-        left: Value::String("".to_string()),
-        operator: EQUAL,
-        right: Value::String("".to_string()),
-    });
-
-    while !condition_component_vec.is_empty() {
-        let component = condition_component_vec.remove(0);
-
-        if component.to_string(variables) == PARENTHESIS_OPEN {
-            let last_close_parentesis_index = condition_component_vec
-                .iter()
-                .rposition(|x| x.to_string(variables) == PARENTHESIS_CLOSE)
-                .unwrap();
-            let sub_condition_component_vec = condition_component_vec
-                .drain(0..last_close_parentesis_index)
-                .collect::<Args>();
-            sub_condition = Some(parse_condition(sub_condition_component_vec, variables));
-            continue;
+    fn parse_or(&mut self) -> Result<Condition> {
+        let mut conditions = vec![self.parse_and()?];
+
+        while is_symbol(self.peek(), OR) {
+            self.next();
+            conditions.push(self.parse_and()?);
         }
 
-        if component.to_string(variables) == AND {
-            if before_operator && sub_condition.is_some() {
-                final_condition =
-                    Condition::And(vec![sub_condition.clone().unwrap(), final_condition]);
-            } else if !before_operator && sub_condition.is_some() {
-                final_condition =
-                    Condition::And(vec![final_condition, sub_condition.clone().unwrap()]);
-            } else if sub_condition.is_none() {
-                final_condition = Condition::And(vec![final_condition]);
-            } else {
-                final_condition = sub_condition.clone().unwrap();
-            }
+        if conditions.len() == 1 {
+            return Ok(conditions.remove(0));
+        }
 
-            before_operator = false;
-            continue;
+        Ok(Condition::Or(conditions))
+    }
+
+    fn parse_and(&mut self) -> Result<Condition> {
+        let mut conditions = vec![self.parse_primary()?];
+
+        while is_symbol(self.peek(), AND) {
+            self.next();
+            conditions.push(self.parse_primary()?);
         }
 
-        if component.to_string(variables) == OR {
-            if before_operator && sub_condition.is_some() {
-                final_condition =
-                    Condition::Or(vec![sub_condition.clone().unwrap(), final_condition]);
-            } else if !before_operator && sub_condition.is_some() {
-                final_condition =
-                    Condition::Or(vec![final_condition, sub_condition.clone().unwrap()]);
-            } else if sub_condition.is_none() {
-                final_condition = Condition::Or(vec![final_condition]);
-            } else {
-                final_condition = sub_condition.clone().unwrap();
-            }
-            before_operator = false;
-            continue;
+        if conditions.len() == 1 {
+            return Ok(conditions.remove(0));
         }
 
-        if OPERATORS.contains(&component.to_string(variables).as_str()) {
-            expression.set_operator(match_operator(&component).unwrap());
-            before_operator = false;
-            continue;
+        Ok(Condition::And(conditions))
+    }
+
+    fn parse_primary(&mut self) -> Result<Condition> {
+        if is_symbol(self.peek(), PARENTHESIS_OPEN) {
+            self.next();
+
+            let condition = self.parse_or()?;
+
+            if !is_symbol(self.peek(), PARENTHESIS_CLOSE) {
+                return Err(anyhow!("Missing closing parenthesis in condition"));
+            }
+
+            self.next();
+
+            return Ok(condition);
         }
 
-        if before_operator {
-            expression.set_left(component);
-            continue;
+        let left = self.parse_operand()?;
+
+        let operator = match self.peek() {
+            Some(Value::String(s)) if s == "not" => {
+                self.next();
+
+                if !is_symbol(self.peek(), IN) {
+                    return Err(anyhow!("Expected 'in' after 'not' in condition"));
+                }
+
+                self.next();
+                NOT_IN
+            }
+            Some(component)
+                if OPERATORS.contains(&component.to_string(self.variables, self.now).as_str()) =>
+            {
+                let operator = match_operator(component)?;
+                self.next();
+                operator
+            }
+            _ => {
+                return Ok(Condition::Expression(Expression::new(
+                    left,
+                    EQUAL,
+                    Value::Boolean(true),
+                )));
+            }
+        };
+
+        let right = self.parse_operand()?;
+
+        Ok(Condition::Expression(Expression::new(
+            left, operator, right,
+        )))
+    }
+
+    fn parse_operand(&mut self) -> Result<Value> {
+        match self.next() {
+            Some(Value::String(s))
+                if [PARENTHESIS_OPEN, PARENTHESIS_CLOSE, AND, OR].contains(&s.as_str())
+                    || OPERATORS.contains(&s.as_str()) =>
+            {
+                Err(anyhow!("Unexpected '{}' in condition", s))
+            }
+            Some(component) => Ok(component.resolve(self.variables, self.now)),
+            None => Err(anyhow!("Incomplete condition")),
         }
+    }
+}
 
-        expression.set_right(component);
-        final_condition = Condition::Expression(expression.clone());
+pub fn parse_condition(
+    condition_component_vec: Args,
+    variables: &Variables,
+    now: &DateTime<Local>,
+) -> Result<Condition> {
+    let mut parser = ConditionParser {
+        components: condition_component_vec,
+        position: 0,
+        variables,
+        now,
+    };
+
+    let condition = parser.parse_or()?;
+
+    if let Some(component) = parser.peek() {
+        return Err(anyhow!(
+            "Unexpected '{}' in condition",
+            component.to_string(variables, now)
+        ));
     }
 
-    final_condition
+    Ok(condition)
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::script_parser::parse_arguments;
+    use std::collections::HashMap;
+
+    fn evaluate(condition: &str, variables: &Variables) -> bool {
+        parse_condition(
+            parse_arguments(condition).unwrap(),
+            variables,
+            &Local::now(),
+        )
+        .unwrap()
+        .evaluate()
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let mut variables: Variables = HashMap::new();
+        variables.insert("$a".to_string(), Value::Int32(5));
+        variables.insert("$b".to_string(), Value::Float64(2.5));
+        variables.insert("$on".to_string(), Value::Boolean(true));
+
+        assert!(evaluate("$a > 3 && $b < 3", &variables));
+        assert!(!evaluate("$a > 3 && $b > 3", &variables));
+        assert!(evaluate("$a < 3 || $b < 3 && $on", &variables));
+        assert!(!evaluate("($a < 3 || $b < 3) && $a == 4", &variables));
+        assert!(evaluate("$a in [1, 5, 9] && $b not in [1, 2]", &variables));
+        assert!(evaluate("$on", &variables));
+
+        assert!(parse_condition(
+            parse_arguments("($a > 3").unwrap(),
+            &variables,
+            &Local::now()
+        )
+        .is_err());
+        assert!(
+            parse_condition(parse_arguments("$a > ").unwrap(), &variables, &Local::now()).is_err()
+        );
+        assert!(parse_condition(
+            parse_arguments("$a > 3 3").unwrap(),
+            &variables,
+            &Local::now()
+        )
+        .is_err());
+    }
+}
@@ -1,17 +1,45 @@
-use crate::actuator_methods::{change_actuator_name, unregister_actuator};
-use crate::db::connect;
-use crate::helper::{send_message_to_dashboard, DashboardMessageType};
-use crate::models::{Actuator, GetSensorReadings, UpdateActuatorState};
-use crate::schema::actuators;
-use crate::schema::actuators::{id, state, updated_at};
-use crate::script_methods::{delete_script, get_scripts, save_new_script, update_script};
-use crate::script_parser::{CommandFunctionResult, Script};
+use crate::actuator_methods::{
+    change_actuator_name, change_actuator_state, get_actuator, unregister_actuator,
+};
+use crate::auth::{
+    change_password, create_user, delete_user, disconnect_sessions, get_users, require_admin,
+    revoke_session, AuthSession,
+};
+use crate::export_handlers::spawn_sensor_reads_export;
+use crate::helper::{send_message_to_dashboard, DashboardMessageType, DashboardTarget};
+use crate::models::{
+    Actuator, ChangePassword, DebugScript, DryRunScript, GetScriptRuns, GetSensorReadings,
+    NewScript, NewScriptRule, NewScriptVariable, NewSensorType, SensorReadsExport,
+    SensorUnregister, UpdateActuatorName, UpdateActuatorState, UpdateScript, UpdateScriptRule,
+    UpdateSensorName, UserCredentials,
+};
+use crate::protocol::{
+    parse_request, AckResult, ActuatorRenamed, ActuatorStateChanged, ActuatorUnregistered,
+    AllScriptRules, AllScriptVariables, AllScripts, AllSensorTypes, AllUsers, ScriptChanged,
+    ScriptDiagnostics, ScriptRuleChanged, ScriptRuleDeleted, ScriptRunsPage, ScriptVariableChanged,
+    ScriptVariableDeleted, SensorReadingsResponse, SensorRenamed, SensorTypeDeleted,
+    SensorTypeSaved, SensorUnregistered, UserDeleted, UserSaved,
+};
+use crate::rule_methods::{
+    delete_script_rule, get_script_rules, save_new_script_rule, update_script_rule,
+};
+use crate::script_methods::{
+    delete_script, get_script, get_script_runs, get_scripts, save_new_script, update_script,
+    ScriptValidationError, SCRIPT_TRIGGER_MANUAL,
+};
+use crate::script_runner::{
+    continue_script, get_debug_state, spawn_debug_script, spawn_dry_run, spawn_script, step_script,
+    stop_script,
+};
 use crate::sensor_methods::{change_sensor_name, get_sensor_readings, unregister_sensor};
+use crate::sensor_type_methods::{delete_sensor_type, get_sensor_types, save_sensor_type};
+use crate::variable_methods::{delete_script_variable, get_script_variables, save_script_variable};
 use crate::CoAPClient;
-use diesel::prelude::*;
-use diesel::{update, ExpressionMethods};
-use serde_json::json;
-use socketioxide::extract::{Data, SocketRef};
+use anyhow::{Context, Error, Result};
+use serde::Serialize;
+use serde_json::Value;
+use socketioxide::extract::{AckSender, Data, SocketRef};
+use socketioxide::SocketIo;
 
 //GENERIC
 pub const MESSAGE_SENT_EVENT: &str = "message-sent";
@@ -33,6 +61,21 @@ pub const RENAME_SENSOR_EVENT: &str = "rename-sensor";
 
 pub const REMOVE_SENSOR_EVENT: &str = "remove-sensor";
 
+//SENSOR READ EXPORTS
+pub const EXPORT_SENSOR_READS_EVENT: &str = "export-sensor-reads";
+
+pub const SENSOR_READS_EXPORT_CHUNK_EVENT: &str = "sensor-reads-export-chunk";
+pub const SENSOR_READS_EXPORT_FINISHED_EVENT: &str = "sensor-reads-export-finished";
+
+//SENSOR TYPES
+pub const GET_ALL_SENSOR_TYPES_EVENT: &str = "get-all-sensor-types";
+pub const SAVE_SENSOR_TYPE_EVENT: &str = "save-sensor-type";
+pub const REMOVE_SENSOR_TYPE_EVENT: &str = "remove-sensor-type";
+
+pub const ALL_SENSOR_TYPES_EVENT: &str = "all-sensor-types";
+pub const SENSOR_TYPE_SAVED_EVENT: &str = "sensor-type-saved";
+pub const SENSOR_TYPE_DELETED_EVENT: &str = "sensor-type-deleted";
+
 //ACTUATORS
 
 pub const ALL_ACTUATORS_EVENT: &str = "all-actuators";
@@ -55,6 +98,12 @@ pub const REMOVE_ACTUATOR_EVENT: &str = "remove-actuator";
 //SCRIPTS
 pub const GET_ALL_SCRIPTS_EVENT: &str = "get-all-scripts";
 pub const RUN_SCRIPT_EVENT: &str = "run-script";
+pub const STOP_SCRIPT_EVENT: &str = "stop-script";
+pub const DRY_RUN_SCRIPT_EVENT: &str = "dry-run-script";
+pub const DEBUG_SCRIPT_EVENT: &str = "debug-script";
+pub const DEBUG_STEP_EVENT: &str = "debug-step";
+pub const DEBUG_CONTINUE_EVENT: &str = "debug-continue";
+pub const DEBUG_VARIABLES_EVENT: &str = "debug-variables";
 pub const ADD_SCRIPT_EVENT: &str = "add-script";
 pub const REMOVE_SCRIPT_EVENT: &str = "remove-script";
 pub const MODIFY_SCRIPT_EVENT: &str = "modify-script";
@@ -69,881 +118,760 @@ pub const SCRIPT_STATUS_CHANGE_EVENT: &str = "script-status-change";
 pub const SCRIPT_SCHEDULE_ADDED_EVENT: &str = "script-schedule-added";
 pub const SCRIPT_SCHEDULE_REMOVED_EVENT: &str = "script-schedule-removed";
 
-pub fn register_all_callbacks(socket: &SocketRef) {
-    socket.on(
-        GET_SENSOR_READINGS_EVENT,
-        |s: SocketRef, data: Data<String>| {
-            let payload = data.0;
-
-            let gsr = match serde_json::from_str::<GetSensorReadings>(&payload) {
-                Ok(gsr) => gsr,
-                Err(e) => {
-                    println!("Error parsing sensor readings request: {:?}", e);
-                    return;
-                }
-            };
-
-            match get_sensor_readings(gsr.get_id(), gsr.get_from_date(), gsr.get_to_date()) {
-                Ok(sensor_reads) => {
-                    let _: Result<(), _> = s.emit(
-                        ALL_SENSOR_READINGS_EVENT,
-                        json!({
-                            "sensor_reads": sensor_reads,
-                        }),
-                    );
-                }
-                Err(e) => {
-                    match send_message_to_dashboard(
-                        &s,
-                        format!("Error getting sensor readings: {:?}", e).to_string(),
-                        DashboardMessageType::Error,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error sending message to dashboard: {:?}", e);
-                        }
-                    };
-                }
+pub const GET_SCRIPT_RUNS_EVENT: &str = "get-script-runs";
+pub const SCRIPT_RUNS_EVENT: &str = "script-runs";
+pub const SCRIPT_DIAGNOSTICS_EVENT: &str = "script-diagnostics";
+pub const SCRIPT_DRY_RUN_EVENT: &str = "script-dry-run";
+pub const SCRIPT_DEBUG_PAUSED_EVENT: &str = "script-debug-paused";
+pub const SCRIPT_DEBUG_VARIABLES_EVENT: &str = "script-debug-variables";
+pub const SCRIPT_DEBUG_FINISHED_EVENT: &str = "script-debug-finished";
+
+//SCRIPT RULES
+pub const GET_ALL_SCRIPT_RULES_EVENT: &str = "get-all-script-rules";
+pub const ADD_SCRIPT_RULE_EVENT: &str = "add-script-rule";
+pub const MODIFY_SCRIPT_RULE_EVENT: &str = "modify-script-rule";
+pub const REMOVE_SCRIPT_RULE_EVENT: &str = "remove-script-rule";
+
+pub const ALL_SCRIPT_RULES_EVENT: &str = "all-script-rules";
+pub const SCRIPT_RULE_SAVED_EVENT: &str = "script-rule-saved";
+pub const SCRIPT_RULE_MODIFIED_EVENT: &str = "script-rule-modified";
+pub const SCRIPT_RULE_DELETED_EVENT: &str = "script-rule-deleted";
+
+//SCRIPT VARIABLES
+pub const GET_ALL_SCRIPT_VARIABLES_EVENT: &str = "get-all-script-variables";
+pub const SET_SCRIPT_VARIABLE_EVENT: &str = "set-script-variable";
+pub const REMOVE_SCRIPT_VARIABLE_EVENT: &str = "remove-script-variable";
+
+pub const ALL_SCRIPT_VARIABLES_EVENT: &str = "all-script-variables";
+pub const SCRIPT_VARIABLE_CHANGED_EVENT: &str = "script-variable-changed";
+pub const SCRIPT_VARIABLE_DELETED_EVENT: &str = "script-variable-deleted";
+
+//USERS
+pub const LOGOUT_EVENT: &str = "logout";
+pub const GET_ALL_USERS_EVENT: &str = "get-all-users";
+pub const ADD_USER_EVENT: &str = "add-user";
+pub const REMOVE_USER_EVENT: &str = "remove-user";
+pub const CHANGE_PASSWORD_EVENT: &str = "change-password";
+
+pub const ALL_USERS_EVENT: &str = "all-users";
+pub const USER_SAVED_EVENT: &str = "user-saved";
+pub const USER_DELETED_EVENT: &str = "user-deleted";
+
+/// Sends the line-numbered problems of a rejected script to the socket that
+/// tried to save it, so the editor can mark them.
+fn emit_script_diagnostics(s: &SocketRef, e: &Error) {
+    if let Some(validation_error) = e.downcast_ref::<ScriptValidationError>() {
+        match s.emit(
+            SCRIPT_DIAGNOSTICS_EVENT,
+            ScriptDiagnostics::new(validation_error.get_diagnostics().clone()),
+        ) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting script diagnostics event: {:?}", e);
             }
-        },
-    );
+        }
+    }
+}
 
-    socket.on(PULSE_ACTUATOR_EVENT, |s: SocketRef, data: Data<i32>| {
-        let actuator_id = data.0;
+/// Emits to the socket that made the request and to every other dashboard.
+fn emit_to_dashboards(s: &SocketRef, event: &'static str, data: &impl Serialize) {
+    match s.emit(event, data) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Error emitting {} event: {:?}", event, e);
+        }
+    }
 
-        let conn = &mut match connect() {
-            Ok(conn) => conn,
+    match s.broadcast().emit(event, data) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Error emitting {} event broadcast: {:?}", event, e);
+        }
+    }
+}
+
+fn send_ack<T: Serialize>(ack: AckSender, res: Result<T>) {
+    match ack.send(AckResult::new(res)) {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Error sending ack: {:?}", e);
+        }
+    }
+}
+
+/// Answers a request with its ack. A failure is also shown on the dashboard
+/// that made the request, after `error_context`.
+fn acknowledge<T: Serialize>(s: &SocketRef, ack: AckSender, res: Result<T>, error_context: &str) {
+    if let Err(e) = &res {
+        match send_message_to_dashboard(
+            s,
+            format!("{}: {:?}", error_context, e),
+            DashboardMessageType::Error,
+        ) {
+            Ok(_) => {}
             Err(e) => {
-                println!("Error connecting to database: {:?}", e);
-                return;
+                println!("Error sending message to dashboard: {:?}", e);
             }
         };
+    }
 
-        let actuator = match actuators::table
-            .filter(id.eq(actuator_id))
-            .get_result::<Actuator>(conn)
-        {
-            Ok(actuator) => actuator,
-            Err(_) => {
-                println!("Error loading actuator");
-                return;
+    send_ack(ack, res);
+}
+
+/// Same as `acknowledge` for requests that fail because a script is not in
+/// the state they need, which the dashboard shows as a warning.
+fn acknowledge_warning<T: Serialize>(s: &SocketRef, ack: AckSender, res: Result<T>) {
+    if let Err(e) = &res {
+        match send_message_to_dashboard(s, format!("{:#}", e), DashboardMessageType::Warning) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error sending message to dashboard: {:?}", e);
             }
         };
+    }
 
-        let address = "coap://".to_owned()
-            + actuator.get_ip_address()
-            + ":"
-            + actuator.get_port().to_string().as_str();
+    send_ack(ack, res);
+}
 
-        let response_actuator = match CoAPClient::post(&address, b"ON-PULSE".to_vec()) {
-            Ok(response) => response,
-            Err(_) => {
-                println!("Error changing actuator state");
-                return;
-            }
-        };
+fn script_not_paused() -> Error {
+    Error::msg("Script is not paused in the debugger")
+}
 
-        let payload = match String::from_utf8(response_actuator.message.payload.clone()) {
-            Ok(p) => p,
-            Err(_) => {
-                println!("Error parsing actuator response");
-                return;
-            }
-        };
+/// Answer of an actuator to a GET, or to a POST of `command`.
+fn request_actuator(actuator: &Actuator, command: Option<&str>) -> Result<String> {
+    let address = format!(
+        "coap://{}:{}",
+        actuator.get_ip_address(),
+        actuator.get_port()
+    );
 
-        if payload == "ON-PULSE" {
-            let mut uas = UpdateActuatorState::new(actuator_id, true);
+    let response = match command {
+        Some(command) => CoAPClient::post(&address, command.as_bytes().to_vec()),
+        None => CoAPClient::get(&address),
+    }
+    .context("Error contacting actuator")?;
+
+    String::from_utf8(response.message.payload).context("Error parsing actuator response")
+}
 
-            uas.set_updated_at(chrono::Local::now().naive_local());
+fn set_actuator_state(actuator_id: i32, actuator_state: bool) -> Result<Actuator> {
+    change_actuator_state(serde_json::to_string(&UpdateActuatorState::new(
+        actuator_id,
+        actuator_state,
+    ))?)
+}
 
-            let res = update(actuators::table.find(actuator_id))
-                .set((
-                    updated_at.eq(uas.get_updated_at()),
-                    state.eq(uas.get_state()),
-                ))
-                .execute(conn);
+/// Switches an actuator to the opposite of the state it reports.
+fn toggle_actuator(actuator_id: i32) -> Result<Actuator> {
+    let actuator = get_actuator(actuator_id)?;
 
-            match res {
-                Ok(_) => {}
-                Err(_) => {
-                    println!("Error updating actuator");
-                    return;
-                }
-            }
+    let current_state = request_actuator(&actuator, None)?;
 
-            match s.emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Error emitting actuator state change event: {:?}", e);
-                }
-            }
+    let command = if current_state == "ON" || current_state == "ON-PULSE" {
+        "OFF"
+    } else {
+        "ON"
+    };
 
-            match s.broadcast().emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!(
-                        "Error emitting actuator state change event broadcast: {:?}",
-                        e
-                    );
-                }
-            }
+    let reply = request_actuator(&actuator, Some(command))?;
 
-            std::thread::sleep(std::time::Duration::from_millis(2000));
+    if reply != "ON" && reply != "OFF" && reply != "ON-PULSE" {
+        return Err(Error::msg(format!(
+            "Actuator {} answered '{}' to '{}'",
+            actuator_id, reply, command
+        )));
+    }
 
-            uas.set_state(false);
-            uas.set_updated_at(chrono::Local::now().naive_local());
+    set_actuator_state(actuator_id, reply.contains("ON"))
+}
 
-            let res = update(actuators::table.find(actuator_id))
-                .set((
-                    updated_at.eq(uas.get_updated_at()),
-                    state.eq(uas.get_state()),
-                ))
-                .execute(conn);
+/// Turns a pulse actuator on, tells the dashboards, and records it as off
+/// again two seconds later.
+fn pulse_actuator(s: &SocketRef, actuator_id: i32) -> Result<Actuator> {
+    let actuator = get_actuator(actuator_id)?;
 
-            match res {
-                Ok(_) => {}
-                Err(_) => {
-                    println!("Error updating actuator");
-                    return;
-                }
-            }
+    let reply = request_actuator(&actuator, Some("ON-PULSE"))?;
 
-            match s.emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Error emitting actuator state change event: {:?}", e);
-                }
-            }
+    if reply != "ON-PULSE" {
+        return Err(Error::msg(format!(
+            "Actuator {} answered '{}' to 'ON-PULSE'",
+            actuator_id, reply
+        )));
+    }
 
-            match s.broadcast().emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!(
-                        "Error emitting actuator state change event broadcast: {:?}",
-                        e
-                    );
-                }
-            }
+    let actuator = set_actuator_state(actuator_id, true)?;
 
-            return;
-        }
+    emit_to_dashboards(
+        s,
+        ACTUATOR_STATE_CHANGE_EVENT,
+        &ActuatorStateChanged::new(&actuator),
+    );
 
-        println!("Error changing actuator state");
-    });
+    std::thread::sleep(std::time::Duration::from_millis(2000));
 
-    socket.on(TOGGLE_ACTUATOR_EVENT, |s: SocketRef, data: Data<i32>| {
-        let actuator_id = data.0;
+    set_actuator_state(actuator_id, false)
+}
 
-        let conn = &mut match connect() {
-            Ok(conn) => conn,
-            Err(e) => {
-                println!("Error connecting to database: {:?}", e);
-                return;
-            }
-        };
+/// `auth_session` is the session the socket connected with.
+pub fn register_all_callbacks(socket: &SocketRef, io: &SocketIo, auth_session: &AuthSession) {
+    socket.on(
+        GET_SENSOR_READINGS_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<GetSensorReadings>(payload).and_then(|gsr| {
+                get_sensor_readings(&gsr)
+                    .map(|page| SensorReadingsResponse::new(gsr.get_id(), page))
+            });
 
-        let actuator = match actuators::table
-            .filter(id.eq(actuator_id))
-            .get_result::<Actuator>(conn)
-        {
-            Ok(actuator) => actuator,
-            Err(_) => {
-                println!("Error loading actuator");
-                return;
+            if let Ok(sensor_readings) = &res {
+                let _: Result<(), _> = s.emit(ALL_SENSOR_READINGS_EVENT, sensor_readings);
             }
-        };
 
-        let address = "coap://".to_owned()
-            + actuator.get_ip_address()
-            + ":"
-            + actuator.get_port().to_string().as_str();
+            acknowledge(&s, ack, res, "Error getting sensor readings");
+        },
+    );
 
-        let response_actuator = match CoAPClient::get(&address) {
-            Ok(response) => response,
-            Err(_) => {
-                println!("Error retrieving actuator state");
-                return;
-            }
-        };
+    let export_io = io.clone();
+    socket.on(
+        EXPORT_SENSOR_READS_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<SensorReadsExport>(payload).map(|export| {
+                spawn_sensor_reads_export(export, DashboardTarget::socket(&export_io, &s));
+            });
 
-        let payload = match String::from_utf8(response_actuator.message.payload.clone()) {
-            Ok(p) => p,
-            Err(_) => {
-                println!("Error parsing actuator response");
-                return;
-            }
-        };
+            acknowledge(&s, ack, res, "Error parsing sensor reads export");
+        },
+    );
 
-        let b = if payload == "ON" || payload == "ON-PULSE" {
-            b"OFF".to_vec()
-        } else {
-            b"ON".to_vec()
-        };
+    socket.on(
+        PULSE_ACTUATOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload)
+                .and_then(|actuator_id| pulse_actuator(&s, actuator_id))
+                .map(|actuator| ActuatorStateChanged::new(&actuator));
 
-        let response_actuator = match CoAPClient::post(&address, b) {
-            Ok(response) => response,
-            Err(_) => {
-                println!("Error changing actuator state");
-                return;
+            if let Ok(actuator_state_changed) = &res {
+                emit_to_dashboards(&s, ACTUATOR_STATE_CHANGE_EVENT, actuator_state_changed);
             }
-        };
 
-        let payload = match String::from_utf8(response_actuator.message.payload.clone()) {
-            Ok(p) => p,
-            Err(_) => {
-                println!("Error parsing actuator response");
-                return;
+            acknowledge(&s, ack, res, "Error pulsing actuator");
+        },
+    );
+
+    socket.on(
+        TOGGLE_ACTUATOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload)
+                .and_then(toggle_actuator)
+                .map(|actuator| ActuatorStateChanged::new(&actuator));
+
+            if let Ok(actuator_state_changed) = &res {
+                emit_to_dashboards(&s, ACTUATOR_STATE_CHANGE_EVENT, actuator_state_changed);
             }
-        };
 
-        if payload == "ON" || payload == "OFF" || payload == "ON-PULSE" {
-            let mut uas = UpdateActuatorState::new(actuator_id, payload.contains("ON"));
+            acknowledge(&s, ack, res, "Error toggling actuator");
+        },
+    );
+
+    socket.on(
+        RENAME_SENSOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<UpdateSensorName>(payload)
+                .and_then(|request| change_sensor_name(serde_json::to_string(&request)?))
+                .map(|sensor| SensorRenamed::new(&sensor));
 
-            uas.set_updated_at(chrono::Local::now().naive_local());
+            if let Ok(sensor_renamed) = &res {
+                emit_to_dashboards(&s, SENSOR_NAME_CHANGE_EVENT, sensor_renamed);
+            }
 
-            let res = update(actuators::table.find(actuator_id))
-                .set((
-                    updated_at.eq(uas.get_updated_at()),
-                    state.eq(uas.get_state()),
-                ))
-                .execute(conn);
+            acknowledge(&s, ack, res, "Error renaming sensor");
+        },
+    );
 
-            match res {
-                Ok(_) => {}
-                Err(_) => {
-                    println!("Error updating actuator");
-                    return;
-                }
+    socket.on(
+        RENAME_ACTUATOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<UpdateActuatorName>(payload)
+                .and_then(|request| change_actuator_name(serde_json::to_string(&request)?))
+                .map(|actuator| ActuatorRenamed::new(&actuator));
+
+            if let Ok(actuator_renamed) = &res {
+                emit_to_dashboards(&s, ACTUATOR_NAME_CHANGE_EVENT, actuator_renamed);
             }
 
-            match s.emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Error emitting actuator state change event: {:?}", e);
-                }
+            acknowledge(&s, ack, res, "Error renaming actuator");
+        },
+    );
+
+    socket.on(
+        REMOVE_ACTUATOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<SensorUnregister>(payload)
+                .and_then(|request| unregister_actuator(serde_json::to_string(&request)?))
+                .map(|actuator| ActuatorUnregistered::new(actuator.get_id()));
+
+            if let Ok(actuator_unregistered) = &res {
+                emit_to_dashboards(&s, ACTUATOR_UNREGISTER_EVENT, actuator_unregistered);
             }
 
-            match s.broadcast().emit(
-                ACTUATOR_STATE_CHANGE_EVENT,
-                json!({
-                    "actuator_id": actuator.get_id(),
-                    "actuator_state": uas.get_state(),
-                    "updated_at": actuator.get_updated_at()
-                }),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Error emitting actuator state change event: {:?}", e);
-                }
+            acknowledge(&s, ack, res, "Error unregistering actuator");
+        },
+    );
+
+    socket.on(
+        REMOVE_SENSOR_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<SensorUnregister>(payload)
+                .and_then(|request| unregister_sensor(serde_json::to_string(&request)?))
+                .map(|sensor| SensorUnregistered::new(sensor.get_id()));
+
+            if let Ok(sensor_unregistered) = &res {
+                emit_to_dashboards(&s, SENSOR_UNREGISTER_EVENT, sensor_unregistered);
             }
-        } else {
-            println!("Error changing actuator state");
+
+            acknowledge(&s, ack, res, "Error unregistering sensor");
+        },
+    );
+
+    socket.on(GET_ALL_SCRIPTS_EVENT, |s: SocketRef, ack: AckSender| {
+        let res = get_scripts().map(AllScripts::new);
+
+        if let Ok(all_scripts) = &res {
+            emit_to_dashboards(&s, ALL_SCRIPTS_EVENT, all_scripts);
         }
+
+        acknowledge(&s, ack, res, "Error getting scripts");
     });
 
-    socket.on(RENAME_SENSOR_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        let sensor = change_sensor_name(payload);
-
-        match sensor {
-            Ok(sensor) => {
-                match s.emit(
-                    SENSOR_NAME_CHANGE_EVENT,
-                    json!({
-                            "sensor_id": sensor.get_id(),
-                            "sensor_name": sensor.get_name(),
-                            "updated_at": sensor.get_updated_at(),
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting sensor name change event: {:?}", e);
-                    }
-                }
+    let run_script_io = io.clone();
+    socket.on(
+        RUN_SCRIPT_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload).and_then(|script_id| {
+                get_script(script_id)?;
+
+                spawn_script(
+                    script_id,
+                    SCRIPT_TRIGGER_MANUAL,
+                    DashboardTarget::socket(&run_script_io, &s),
+                );
+
+                Ok(())
+            });
+
+            acknowledge(&s, ack, res, "Error running script");
+        },
+    );
+
+    socket.on(
+        STOP_SCRIPT_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res =
+                parse_request::<i32>(payload).and_then(|script_id| match stop_script(script_id) {
+                    true => Ok(()),
+                    false => Err(Error::msg("Script is not running")),
+                });
+
+            acknowledge_warning(&s, ack, res);
+        },
+    );
+
+    let dry_run_io = io.clone();
+    socket.on(
+        DRY_RUN_SCRIPT_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<DryRunScript>(payload).map(|drs| {
+                spawn_dry_run(
+                    drs.get_script_id(),
+                    drs.get_sensor_values(),
+                    DashboardTarget::socket(&dry_run_io, &s),
+                );
+            });
+
+            acknowledge(&s, ack, res, "Error parsing dry run request");
+        },
+    );
 
-                match s.broadcast().emit(
-                    SENSOR_NAME_CHANGE_EVENT,
-                    json!({
-                            "sensor_id": sensor.get_id(),
-                            "sensor_name": sensor.get_name(),
-                            "updated_at": sensor.get_updated_at(),
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting sensor name change event broadcast: {:?}", e);
-                    }
+    let debug_script_io = io.clone();
+    socket.on(
+        DEBUG_SCRIPT_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<DebugScript>(payload).and_then(|ds| {
+                spawn_debug_script(
+                    ds.get_script_id(),
+                    ds.get_breakpoints(),
+                    DashboardTarget::socket(&debug_script_io, &s),
+                )
+                .context("Error debugging script")?;
+
+                Ok(())
+            });
+
+            acknowledge_warning(&s, ack, res);
+        },
+    );
+
+    socket.on(
+        DEBUG_STEP_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res =
+                parse_request::<i32>(payload).and_then(|script_id| match step_script(script_id) {
+                    true => Ok(()),
+                    false => Err(script_not_paused()),
+                });
+
+            acknowledge_warning(&s, ack, res);
+        },
+    );
+
+    socket.on(
+        DEBUG_CONTINUE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload).and_then(|script_id| {
+                match continue_script(script_id) {
+                    true => Ok(()),
+                    false => Err(script_not_paused()),
                 }
+            });
+
+            acknowledge_warning(&s, ack, res);
+        },
+    );
+
+    socket.on(
+        DEBUG_VARIABLES_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload)
+                .and_then(|script_id| get_debug_state(script_id).ok_or_else(script_not_paused));
+
+            if let Ok(debug_state) = &res {
+                let _: Result<(), _> = s.emit(SCRIPT_DEBUG_VARIABLES_EVENT, debug_state);
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error renaming sensor: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
-            }
-        }
-    });
 
-    socket.on(RENAME_ACTUATOR_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        let actuator = change_actuator_name(payload);
-
-        match actuator {
-            Ok(actuator) => {
-                match s.emit(
-                    ACTUATOR_NAME_CHANGE_EVENT,
-                    json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_name": actuator.get_name(),
-                            "updated_at": actuator.get_updated_at(),
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting actuator name change event: {:?}", e);
-                    }
-                }
+            acknowledge_warning(&s, ack, res);
+        },
+    );
 
-                match s.broadcast().emit(
-                    ACTUATOR_NAME_CHANGE_EVENT,
-                    json!({
-                            "actuator_id": actuator.get_id(),
-                            "actuator_name": actuator.get_name(),
-                            "updated_at": actuator.get_updated_at(),
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!(
-                            "Error emitting actuator name change event broadcast: {:?}",
-                            e
-                        );
-                    }
-                }
+    socket.on(
+        ADD_SCRIPT_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<NewScript>(payload)
+                .and_then(|request| save_new_script(serde_json::to_string(&request)?))
+                .map(ScriptChanged::new);
+
+            match &res {
+                Ok(script_saved) => emit_to_dashboards(&s, SCRIPT_SAVED_EVENT, script_saved),
+                Err(e) => emit_script_diagnostics(&s, e),
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error renaming actuator: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+
+            acknowledge(&s, ack, res, "Error adding script");
+        },
+    );
+
+    socket.on(
+        REMOVE_SCRIPT_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload).and_then(|script_id| {
+                let script = get_script(script_id)?;
+
+                stop_script(script_id);
+                delete_script(script_id)?;
+
+                Ok(ScriptChanged::new(script))
+            });
+
+            if let Ok(script_deleted) = &res {
+                emit_to_dashboards(&s, SCRIPT_DELETED_EVENT, script_deleted);
             }
-        }
-    });
 
-    socket.on(REMOVE_ACTUATOR_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        match unregister_actuator(payload) {
-            Ok(actuator) => {
-                match s.broadcast().emit(
-                    ACTUATOR_UNREGISTER_EVENT,
-                    json!({
-                           "actuator_id": actuator.get_id(),
-                    }),
-                ) {
-                    Ok(_) => {
-                        match s.emit(
-                            ACTUATOR_UNREGISTER_EVENT,
-                            json!({
-                                   "actuator_id": actuator.get_id(),
-                            }),
-                        ) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Error emitting actuator unregister event: {:?}", e);
-                            }
-                        }
-
-                        match s.broadcast().emit(
-                            ACTUATOR_UNREGISTER_EVENT,
-                            json!({
-                                   "actuator_id": actuator.get_id(),
-                            }),
-                        ) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!(
-                                    "Error emitting actuator unregister event broadcast: {:?}",
-                                    e
-                                );
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!(
-                            "Error emitting actuator unregister event broadcast: {:?}",
-                            e
-                        );
-                    }
+            acknowledge(&s, ack, res, "Error deleting script");
+        },
+    );
+
+    for (event, changed_event) in [
+        (MODIFY_SCRIPT_EVENT, SCRIPT_MODIFIED_EVENT),
+        (ADD_SCRIPT_SCHEDULE_EVENT, SCRIPT_SCHEDULE_ADDED_EVENT),
+        (REMOVE_SCRIPT_SCHEDULE_EVENT, SCRIPT_SCHEDULE_REMOVED_EVENT),
+    ] {
+        socket.on(
+            event,
+            move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+                let res = parse_request::<UpdateScript>(payload)
+                    .and_then(|request| update_script(serde_json::to_string(&request)?))
+                    .map(ScriptChanged::new);
+
+                match &res {
+                    Ok(script_changed) => emit_to_dashboards(&s, changed_event, script_changed),
+                    Err(e) => emit_script_diagnostics(&s, e),
                 }
+
+                acknowledge(&s, ack, res, "Error updating script");
+            },
+        );
+    }
+
+    socket.on(
+        GET_SCRIPT_RUNS_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<GetScriptRuns>(payload).and_then(|gsr| {
+                get_script_runs(gsr.get_script_id(), gsr.get_before_id(), gsr.get_limit())
+                    .map(|script_runs| ScriptRunsPage::new(gsr.get_script_id(), script_runs))
+            });
+
+            if let Ok(script_runs_page) = &res {
+                let _: Result<(), _> = s.emit(SCRIPT_RUNS_EVENT, script_runs_page);
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error unregistering actuator: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+
+            acknowledge(&s, ack, res, "Error getting script runs");
+        },
+    );
+
+    socket.on(
+        GET_ALL_SCRIPT_RULES_EVENT,
+        |s: SocketRef, ack: AckSender| {
+            let res = get_script_rules().map(AllScriptRules::new);
+
+            if let Ok(all_script_rules) = &res {
+                let _: Result<(), _> = s.emit(ALL_SCRIPT_RULES_EVENT, all_script_rules);
             }
-        }
-    });
 
-    socket.on(REMOVE_SENSOR_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        match unregister_sensor(payload) {
-            Ok(sensor) => {
-                match s.broadcast().emit(
-                    SENSOR_UNREGISTER_EVENT,
-                    json!({
-                           "sensor_id": sensor.get_id(),
-                    }),
-                ) {
-                    Ok(_) => {
-                        match s.emit(
-                            SENSOR_UNREGISTER_EVENT,
-                            json!({
-                                   "sensor_id": sensor.get_id(),
-                            }),
-                        ) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Error emitting sensor unregister event: {:?}", e);
-                            }
-                        }
-
-                        match s.broadcast().emit(
-                            SENSOR_UNREGISTER_EVENT,
-                            json!({
-                                   "sensor_id": sensor.get_id(),
-                            }),
-                        ) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!(
-                                    "Error emitting sensor unregister event broadcast: {:?}",
-                                    e
-                                );
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("Error emitting sensor unregister event broadcast: {:?}", e);
-                    }
-                }
+            acknowledge(&s, ack, res, "Error getting script rules");
+        },
+    );
+
+    socket.on(
+        ADD_SCRIPT_RULE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<NewScriptRule>(payload)
+                .and_then(|request| save_new_script_rule(serde_json::to_string(&request)?))
+                .map(ScriptRuleChanged::new);
+
+            if let Ok(script_rule_saved) = &res {
+                emit_to_dashboards(&s, SCRIPT_RULE_SAVED_EVENT, script_rule_saved);
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error unregistering sensor: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+
+            acknowledge(&s, ack, res, "Error adding script rule");
+        },
+    );
+
+    socket.on(
+        MODIFY_SCRIPT_RULE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<UpdateScriptRule>(payload)
+                .and_then(|request| update_script_rule(serde_json::to_string(&request)?))
+                .map(ScriptRuleChanged::new);
+
+            if let Ok(script_rule_modified) = &res {
+                emit_to_dashboards(&s, SCRIPT_RULE_MODIFIED_EVENT, script_rule_modified);
             }
-        }
-    });
 
-    socket.on(GET_ALL_SCRIPTS_EVENT, |s: SocketRef| {
-        let all_scripts = get_scripts();
-
-        if let Ok(scripts) = all_scripts {
-            let _: Result<(), _> = s.emit(
-                ALL_SCRIPTS_EVENT,
-                json!({
-                       "scripts_array": scripts,
-                }),
-            );
-
-            let _: Result<(), _> = s.broadcast().emit(
-                ALL_SCRIPTS_EVENT,
-                json!({
-                       "scripts_array": scripts,
-                }),
-            );
-        }
-    });
+            acknowledge(&s, ack, res, "Error updating script rule");
+        },
+    );
 
-    socket.on(RUN_SCRIPT_EVENT, |s: SocketRef, data: Data<i32>| {
-        let payload = data.0;
+    socket.on(
+        REMOVE_SCRIPT_RULE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<i32>(payload).and_then(|script_rule_id| {
+                delete_script_rule(script_rule_id)?;
 
-        let script = match Script::parse(payload) {
-            Ok(script) => script,
-            Err(e) => {
-                let _: Result<(), _> = send_message_to_dashboard(
-                    &s,
-                    format!("Error parsing script: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                );
-                return;
+                Ok(ScriptRuleDeleted::new(script_rule_id))
+            });
+
+            if let Ok(script_rule_deleted) = &res {
+                emit_to_dashboards(&s, SCRIPT_RULE_DELETED_EVENT, script_rule_deleted);
             }
-        };
 
-        let _: Result<(), _> = s.emit(
-            SCRIPT_STATUS_CHANGE_EVENT,
-            json!({
-                   "script_id": script.get_id(),
-                   "status": 1,
-            }),
-        );
+            acknowledge(&s, ack, res, "Error deleting script rule");
+        },
+    );
 
-        let _: Result<(), _> = s.broadcast().emit(
-            SCRIPT_STATUS_CHANGE_EVENT,
-            json!({
-                   "script_id": script.get_id(),
-                   "status": 1,
-            }),
-        );
+    socket.on(
+        GET_ALL_SCRIPT_VARIABLES_EVENT,
+        |s: SocketRef, ack: AckSender| {
+            let res = get_script_variables().map(AllScriptVariables::new);
 
-        let res = script.run(&s);
-
-        match res {
-            Ok(res) => {
-                if let CommandFunctionResult::Error(e) = res {
-                    let _: Result<(), _> = s.emit(
-                        SCRIPT_STATUS_CHANGE_EVENT,
-                        json!({
-                               "script_id": script.get_id(),
-                               "status": -1,
-                        }),
-                    );
-
-                    let _: Result<(), _> = s.broadcast().emit(
-                        SCRIPT_STATUS_CHANGE_EVENT,
-                        json!({
-                               "script_id": script.get_id(),
-                               "status": -1,
-                        }),
-                    );
-
-                    match send_message_to_dashboard(
-                        &s,
-                        format!("Error running script: {:?}", e).to_string(),
-                        DashboardMessageType::Error,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error sending message to dashboard: {:?}", e);
-                        }
-                    };
-                }
+            if let Ok(all_script_variables) = &res {
+                let _: Result<(), _> = s.emit(ALL_SCRIPT_VARIABLES_EVENT, all_script_variables);
             }
-            Err(e) => {
-                let _: Result<(), _> = s.emit(
-                    SCRIPT_STATUS_CHANGE_EVENT,
-                    json!({
-                           "script_id": script.get_id(),
-                           "status": -2,
-                    }),
-                );
 
-                let _: Result<(), _> = s.broadcast().emit(
-                    SCRIPT_STATUS_CHANGE_EVENT,
-                    json!({
-                           "script_id": script.get_id(),
-                           "status": -2,
-                    }),
-                );
+            acknowledge(&s, ack, res, "Error getting script variables");
+        },
+    );
+
+    socket.on(
+        SET_SCRIPT_VARIABLE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<NewScriptVariable>(payload)
+                .and_then(|request| save_script_variable(serde_json::to_string(&request)?))
+                .map(ScriptVariableChanged::new);
 
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error running script: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+            if let Ok(script_variable_changed) = &res {
+                emit_to_dashboards(&s, SCRIPT_VARIABLE_CHANGED_EVENT, script_variable_changed);
             }
-        }
-    });
 
-    socket.on(ADD_SCRIPT_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        match save_new_script(payload) {
-            Ok(script) => {
-                match s.emit(
-                    SCRIPT_SAVED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting add script event: {:?}", e);
-                    }
-                }
+            acknowledge(&s, ack, res, "Error setting script variable");
+        },
+    );
 
-                match s.broadcast().emit(
-                    SCRIPT_SAVED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting add script event broadcast: {:?}", e);
-                    }
-                }
+    socket.on(
+        REMOVE_SCRIPT_VARIABLE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<String>(payload).and_then(|name| {
+                delete_script_variable(&name)?;
+
+                Ok(ScriptVariableDeleted::new(name))
+            });
+
+            if let Ok(script_variable_deleted) = &res {
+                emit_to_dashboards(&s, SCRIPT_VARIABLE_DELETED_EVENT, script_variable_deleted);
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error adding script: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+
+            acknowledge(&s, ack, res, "Error deleting script variable");
+        },
+    );
+
+    socket.on(
+        GET_ALL_SENSOR_TYPES_EVENT,
+        |s: SocketRef, ack: AckSender| {
+            let res = get_sensor_types().map(AllSensorTypes::new);
+
+            if let Ok(all_sensor_types) = &res {
+                let _: Result<(), _> = s.emit(ALL_SENSOR_TYPES_EVENT, all_sensor_types);
             }
-        }
-    });
 
-    socket.on(REMOVE_SCRIPT_EVENT, |s: SocketRef, data: Data<i32>| {
-        let payload = data.0;
-
-        match delete_script(payload) {
-            Ok(script) => {
-                match s.emit(
-                    SCRIPT_DELETED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting delete script event: {:?}", e);
-                    }
-                }
+            acknowledge(&s, ack, res, "Error getting sensor types");
+        },
+    );
 
-                match s.broadcast().emit(
-                    SCRIPT_DELETED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting delete script event broadcast: {:?}", e);
-                    }
-                }
+    socket.on(
+        SAVE_SENSOR_TYPE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<NewSensorType>(payload)
+                .and_then(|request| save_sensor_type(serde_json::to_string(&request)?))
+                .map(SensorTypeSaved::new);
+
+            if let Ok(sensor_type_saved) = &res {
+                emit_to_dashboards(&s, SENSOR_TYPE_SAVED_EVENT, sensor_type_saved);
             }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error deleting script: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
+
+            acknowledge(&s, ack, res, "Error saving sensor type");
+        },
+    );
+
+    socket.on(
+        REMOVE_SENSOR_TYPE_EVENT,
+        |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<String>(payload).and_then(|name| {
+                delete_sensor_type(&name)?;
+
+                Ok(SensorTypeDeleted::new(name))
+            });
+
+            if let Ok(sensor_type_deleted) = &res {
+                emit_to_dashboards(&s, SENSOR_TYPE_DELETED_EVENT, sensor_type_deleted);
             }
+
+            acknowledge(&s, ack, res, "Error deleting sensor type");
+        },
+    );
+
+    register_user_callbacks(socket, io, auth_session);
+}
+
+fn register_user_callbacks(socket: &SocketRef, io: &SocketIo, auth_session: &AuthSession) {
+    let logout_io = io.clone();
+    let logout_token_id = auth_session.get_session().get_token_id().to_string();
+    socket.on(LOGOUT_EVENT, move |s: SocketRef, ack: AckSender| {
+        let res = revoke_session(&logout_token_id);
+        let logged_out = res.is_ok();
+
+        acknowledge(&s, ack, res, "Error logging out");
+
+        if logged_out {
+            disconnect_sessions(&logout_io, std::slice::from_ref(&logout_token_id));
         }
     });
 
-    socket.on(MODIFY_SCRIPT_EVENT, |s: SocketRef, data: Data<String>| {
-        let payload = data.0;
-
-        match update_script(payload) {
-            Ok(script) => {
-                match s.emit(
-                    SCRIPT_MODIFIED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting update script event: {:?}", e);
-                    }
-                }
+    socket.on(GET_ALL_USERS_EVENT, |s: SocketRef, ack: AckSender| {
+        let res = get_users().map(AllUsers::new);
 
-                match s.broadcast().emit(
-                    SCRIPT_MODIFIED_EVENT,
-                    json!({
-                        "script": script,
-                    }),
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error emitting update script event broadcast: {:?}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                match send_message_to_dashboard(
-                    &s,
-                    format!("Error updating script: {:?}", e).to_string(),
-                    DashboardMessageType::Error,
-                ) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending message to dashboard: {:?}", e);
-                    }
-                };
-            }
+        if let Ok(all_users) = &res {
+            let _: Result<(), _> = s.emit(ALL_USERS_EVENT, all_users);
         }
+
+        acknowledge(&s, ack, res, "Error getting users");
     });
 
+    let add_user = auth_session.get_user().clone();
     socket.on(
-        ADD_SCRIPT_SCHEDULE_EVENT,
-        |s: SocketRef, data: Data<String>| {
-            let payload = data.0;
-
-            match update_script(payload) {
-                Ok(script) => {
-                    match s.emit(
-                        SCRIPT_SCHEDULE_ADDED_EVENT,
-                        json!({
-                            "script": script,
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting add script schedule event: {:?}", e);
-                        }
-                    }
-
-                    match s.broadcast().emit(
-                        SCRIPT_SCHEDULE_ADDED_EVENT,
-                        json!({
-                            "script": script,
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!(
-                                "Error emitting add script schedule event broadcast: {:?}",
-                                e
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    match send_message_to_dashboard(
-                        &s,
-                        format!("Error updating script: {:?}", e).to_string(),
-                        DashboardMessageType::Error,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error sending message to dashboard: {:?}", e);
-                        }
-                    };
-                }
+        ADD_USER_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = require_admin(&add_user)
+                .and_then(|_| parse_request::<UserCredentials>(payload))
+                .and_then(|request| {
+                    create_user(request.get_username(), request.get_password(), false)
+                })
+                .map(UserSaved::new);
+
+            if let Ok(user_saved) = &res {
+                emit_to_dashboards(&s, USER_SAVED_EVENT, user_saved);
             }
+
+            acknowledge(&s, ack, res, "Error adding user");
         },
     );
 
+    let remove_io = io.clone();
+    let remove_user = auth_session.get_user().clone();
     socket.on(
-        REMOVE_SCRIPT_SCHEDULE_EVENT,
-        |s: SocketRef, data: Data<String>| {
-            let payload = data.0;
-
-            match update_script(payload) {
-                Ok(script) => {
-                    match s.emit(
-                        SCRIPT_SCHEDULE_REMOVED_EVENT,
-                        json!({
-                            "script": script,
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting remove script schedule event: {:?}", e);
-                        }
-                    }
-
-                    match s.broadcast().emit(
-                        SCRIPT_SCHEDULE_REMOVED_EVENT,
-                        json!({
-                            "script": script,
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!(
-                                "Error emitting remove script schedule event broadcast: {:?}",
-                                e
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    match send_message_to_dashboard(
-                        &s,
-                        format!("Error updating script: {:?}", e).to_string(),
-                        DashboardMessageType::Error,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error sending message to dashboard: {:?}", e);
-                        }
-                    };
-                }
+        REMOVE_USER_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = require_admin(&remove_user)
+                .and_then(|_| parse_request::<i32>(payload))
+                .and_then(delete_user);
+
+            let token_ids = match &res {
+                Ok((_, token_ids)) => token_ids.clone(),
+                Err(_) => vec![],
+            };
+
+            let res = res.map(|(user, _)| UserDeleted::new(user.get_id()));
+
+            if let Ok(user_deleted) = &res {
+                emit_to_dashboards(&s, USER_DELETED_EVENT, user_deleted);
             }
+
+            acknowledge(&s, ack, res, "Error removing user");
+
+            // After the ack, as the user may have removed themselves.
+            disconnect_sessions(&remove_io, &token_ids);
+        },
+    );
+
+    let password_io = io.clone();
+    let password_session = auth_session.clone();
+    socket.on(
+        CHANGE_PASSWORD_EVENT,
+        move |s: SocketRef, Data(payload): Data<Value>, ack: AckSender| {
+            let res = parse_request::<ChangePassword>(payload).and_then(|request| {
+                change_password(
+                    &password_session,
+                    request.get_current_password(),
+                    request.get_new_password(),
+                )
+            });
+
+            let res = res.map(|(user, token_ids)| {
+                disconnect_sessions(&password_io, &token_ids);
+
+                UserSaved::new(user)
+            });
+
+            if let Ok(user_saved) = &res {
+                emit_to_dashboards(&s, USER_SAVED_EVENT, user_saved);
+            }
+
+            acknowledge(&s, ack, res, "Error changing password");
         },
     );
 }
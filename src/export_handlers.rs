@@ -0,0 +1,185 @@
+use crate::auth::{authenticate_headers, AuthError};
+use crate::events::{SENSOR_READS_EXPORT_CHUNK_EVENT, SENSOR_READS_EXPORT_FINISHED_EVENT};
+use crate::export_methods::{export_sensor_reads, ChunkWriter, ExportFormat};
+use crate::helper::DashboardTarget;
+use crate::models::SensorReadsExport;
+use crate::protocol::{SensorReadsExportChunk, SensorReadsExportFinished};
+use crate::sensor_methods::parse_reading_timestamp;
+use anyhow::{Error, Result};
+use axum::body::StreamBody;
+use axum::extract::Query;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::io;
+use std::thread::spawn;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::IntoParams;
+
+/// HTTP responses are streamed in chunks of this size, Socket.IO exports
+/// send one chunk event each.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+pub const EXPORT_SENSOR_READS_PATH: &str = "/export/sensor-reads";
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SensorReadsExportQuery {
+    /// Comma separated sensor ids, every sensor when left out.
+    sensor_ids: Option<String>,
+    from_date: String,
+    to_date: String,
+    /// `csv` (default), `jsonl` or `parquet`.
+    format: Option<String>,
+}
+
+impl SensorReadsExportQuery {
+    fn into_export(self) -> Result<SensorReadsExport> {
+        let sensor_ids = self
+            .sensor_ids
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim())
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse::<i32>()
+                    .map_err(|_| Error::msg(format!("Invalid sensor id '{}'", id)))
+            })
+            .collect::<Result<Vec<i32>>>()?;
+
+        let mut export = SensorReadsExport::new(sensor_ids, &self.from_date, &self.to_date);
+        export.set_format(self.format);
+
+        Ok(export)
+    }
+}
+
+/// Checks what can fail before anything is streamed, so bad requests still
+/// get a proper error.
+fn validate_export(export: &SensorReadsExport) -> Result<ExportFormat> {
+    parse_reading_timestamp(export.get_from_date())?;
+    parse_reading_timestamp(export.get_to_date())?;
+
+    ExportFormat::parse(export.get_format().as_deref())
+}
+
+/// Streams the reads of the selected sensors between two dates, joined with
+/// the sensor's name and type.
+#[utoipa::path(
+    get,
+    path = "/export/sensor-reads",
+    tag = "exports",
+    params(SensorReadsExportQuery),
+    responses(
+        (status = 200, description = "The export file, in the requested format", content(
+            ("text/csv" = String),
+            ("application/x-ndjson" = String),
+            ("application/vnd.apache.parquet" = Vec<u8>),
+        )),
+        (status = 400, description = "Invalid sensor ids, dates or format", body = String),
+        (status = 401, description = "Missing or invalid token", body = String),
+    )
+)]
+pub async fn export_sensor_reads_handler(
+    headers: HeaderMap,
+    Query(query): Query<SensorReadsExportQuery>,
+) -> Response {
+    match authenticate_headers(&headers) {
+        Ok(_) => {}
+        Err(e) if e.is::<AuthError>() => {
+            return (StatusCode::UNAUTHORIZED, e.to_string()).into_response();
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+
+    let export = match query.into_export() {
+        Ok(export) => export,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let format = match validate_export(&export) {
+        Ok(format) => format,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+
+    tokio::task::spawn_blocking(move || {
+        let chunk_tx = tx.clone();
+
+        let writer = ChunkWriter::new(EXPORT_CHUNK_SIZE, move |chunk| {
+            chunk_tx
+                .blocking_send(Ok(Bytes::from(chunk)))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Export cancelled"))
+        });
+
+        match export_sensor_reads(&export, format, writer) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error exporting sensor reads: {:?}", e);
+
+                // Aborts the response, so a cut-off file is not mistaken for
+                // a complete one.
+                let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+            }
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, format.get_content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.get_file_name()),
+            ),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+/// Runs the export in the background and sends the file to `target` as
+/// numbered binary chunks, followed by a finished event with the row count
+/// or the error.
+pub fn spawn_sensor_reads_export(export: SensorReadsExport, target: DashboardTarget) {
+    spawn(move || {
+        let export_id = export.get_export_id().clone();
+
+        let res = validate_export(&export).and_then(|format| {
+            let mut index = 0;
+
+            let writer = ChunkWriter::new(EXPORT_CHUNK_SIZE, |chunk| {
+                let res = target.emit_binary(
+                    SENSOR_READS_EXPORT_CHUNK_EVENT,
+                    SensorReadsExportChunk::new(export_id.clone(), format.get_name(), index),
+                    vec![chunk],
+                );
+                index += 1;
+
+                res.map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+            });
+
+            export_sensor_reads(&export, format, writer)
+        });
+
+        let (rows, error) = match res {
+            Ok(rows) => (rows, None),
+            Err(e) => {
+                println!("Error exporting sensor reads: {:?}", e);
+                (0, Some(e.to_string()))
+            }
+        };
+
+        match target.emit(
+            SENSOR_READS_EXPORT_FINISHED_EVENT,
+            SensorReadsExportFinished::new(export_id, rows, error),
+        ) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting sensor reads export finished event: {:?}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,369 @@
+use crate::db::connect;
+use crate::models::{SensorReadExportRow, SensorReadsExport};
+use crate::schema::{sensor_reads, sensors};
+use crate::sensor_methods::parse_reading_timestamp;
+use anyhow::{Error, Result};
+use diesel::prelude::*;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Reads are loaded and written this many at a time, so exports of months
+/// of history never sit in memory whole. Each batch is one Parquet row group.
+const EXPORT_BATCH_SIZE: i64 = 10000;
+
+const PARQUET_SCHEMA: &str = "
+    message sensor_read {
+        REQUIRED INT32 id;
+        REQUIRED INT32 sensor_id;
+        OPTIONAL BYTE_ARRAY sensor_name (UTF8);
+        REQUIRED BYTE_ARRAY sensor_type (UTF8);
+        OPTIONAL DOUBLE sensor_value;
+        OPTIONAL BYTE_ARRAY unit (UTF8);
+        REQUIRED BYTE_ARRAY raw_value (UTF8);
+        REQUIRED INT64 created_at (TIMESTAMP(MICROS,false));
+    }
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    JsonLines,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn parse(format: Option<&str>) -> Result<Self> {
+        match format.map(|format| format.trim().to_lowercase()).as_deref() {
+            None | Some("csv") => Ok(ExportFormat::Csv),
+            Some("jsonl") | Some("ndjson") => Ok(ExportFormat::JsonLines),
+            Some("parquet") => Ok(ExportFormat::Parquet),
+            Some(format) => Err(Error::msg(format!("Unknown export format '{}'", format))),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn get_content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::JsonLines => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn get_file_name(&self) -> String {
+        format!("sensor-reads.{}", self.get_name())
+    }
+}
+
+/// Writes export rows batch by batch in one of the export formats.
+pub enum ExportWriter<W: Write + Send> {
+    Csv(csv::Writer<W>),
+    JsonLines(W),
+    Parquet(SerializedFileWriter<W>),
+}
+
+fn optional_column(values: Vec<Option<ByteArray>>) -> (Vec<ByteArray>, Vec<i16>) {
+    let definition_levels = values
+        .iter()
+        .map(|value| value.is_some() as i16)
+        .collect::<Vec<i16>>();
+
+    (values.into_iter().flatten().collect(), definition_levels)
+}
+
+impl<W: Write + Send> ExportWriter<W> {
+    pub fn new(format: ExportFormat, writer: W) -> Result<Self> {
+        match format {
+            ExportFormat::Csv => Ok(ExportWriter::Csv(csv::Writer::from_writer(writer))),
+            ExportFormat::JsonLines => Ok(ExportWriter::JsonLines(writer)),
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+
+                Ok(ExportWriter::Parquet(SerializedFileWriter::new(
+                    writer,
+                    Arc::new(parse_message_type(PARQUET_SCHEMA)?),
+                    Arc::new(properties),
+                )?))
+            }
+        }
+    }
+
+    pub fn write_batch(&mut self, rows: &[SensorReadExportRow]) -> Result<()> {
+        match self {
+            ExportWriter::Csv(writer) => {
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+            }
+            ExportWriter::JsonLines(writer) => {
+                for row in rows {
+                    serde_json::to_writer(&mut *writer, row)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+            ExportWriter::Parquet(writer) => {
+                if rows.is_empty() {
+                    return Ok(());
+                }
+
+                let mut row_group = writer.next_row_group()?;
+                let mut column_index = 0;
+
+                while let Some(mut column) = row_group.next_column()? {
+                    match column_index {
+                        0 | 1 => {
+                            let values = rows
+                                .iter()
+                                .map(|row| match column_index {
+                                    0 => row.get_id(),
+                                    _ => row.get_sensor_id(),
+                                })
+                                .collect::<Vec<i32>>();
+
+                            column
+                                .typed::<Int32Type>()
+                                .write_batch(&values, None, None)?;
+                        }
+                        4 => {
+                            let definition_levels = rows
+                                .iter()
+                                .map(|row| row.get_sensor_value().is_some() as i16)
+                                .collect::<Vec<i16>>();
+                            let values = rows
+                                .iter()
+                                .filter_map(|row| row.get_sensor_value())
+                                .collect::<Vec<f64>>();
+
+                            column.typed::<DoubleType>().write_batch(
+                                &values,
+                                Some(&definition_levels),
+                                None,
+                            )?;
+                        }
+                        7 => {
+                            let values = rows
+                                .iter()
+                                .map(|row| row.get_created_at().and_utc().timestamp_micros())
+                                .collect::<Vec<i64>>();
+
+                            column
+                                .typed::<Int64Type>()
+                                .write_batch(&values, None, None)?;
+                        }
+                        _ => {
+                            let (values, definition_levels) = optional_column(
+                                rows.iter()
+                                    .map(|row| match column_index {
+                                        2 => row.get_sensor_name().as_deref(),
+                                        3 => Some(row.get_sensor_type()),
+                                        5 => row.get_unit().as_deref(),
+                                        _ => Some(row.get_raw_value()),
+                                    })
+                                    .map(|value| value.map(ByteArray::from))
+                                    .collect(),
+                            );
+
+                            let definition_levels = match column_index {
+                                2 | 5 => Some(definition_levels.as_slice()),
+                                _ => None,
+                            };
+
+                            column.typed::<ByteArrayType>().write_batch(
+                                &values,
+                                definition_levels,
+                                None,
+                            )?;
+                        }
+                    }
+
+                    column.close()?;
+                    column_index += 1;
+                }
+
+                row_group.close()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes what is still buffered, and the footer for Parquet.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            ExportWriter::Csv(mut writer) => writer.flush()?,
+            ExportWriter::JsonLines(mut writer) => writer.flush()?,
+            ExportWriter::Parquet(mut writer) => {
+                writer.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Streams the requested reads, joined with their sensor's name and type,
+/// into `writer`. Returns the number of exported reads.
+pub fn export_sensor_reads<W: Write + Send>(
+    export: &SensorReadsExport,
+    format: ExportFormat,
+    writer: W,
+) -> Result<usize> {
+    let conn = &mut connect()?;
+
+    let from_datetime = parse_reading_timestamp(export.get_from_date())?;
+    let to_datetime = parse_reading_timestamp(export.get_to_date())?;
+    let sensor_ids = export.get_sensor_ids();
+
+    let mut export_writer = ExportWriter::new(format, writer)?;
+    let mut last_id = 0;
+    let mut exported = 0;
+
+    loop {
+        let mut query = sensor_reads::table
+            .inner_join(sensors::table)
+            .filter(sensor_reads::created_at.ge(from_datetime))
+            .filter(sensor_reads::created_at.le(to_datetime))
+            .filter(sensor_reads::id.gt(last_id))
+            .select((
+                sensor_reads::id,
+                sensor_reads::sensor_id,
+                sensors::name,
+                sensors::sensor_type,
+                sensor_reads::sensor_value,
+                sensor_reads::unit,
+                sensor_reads::raw_value,
+                sensor_reads::created_at,
+            ))
+            .order_by(sensor_reads::id.asc())
+            .limit(EXPORT_BATCH_SIZE)
+            .into_boxed();
+
+        if !sensor_ids.is_empty() {
+            query = query.filter(sensor_reads::sensor_id.eq_any(&sensor_ids));
+        }
+
+        let rows = query.load::<SensorReadExportRow>(conn)?;
+
+        export_writer.write_batch(&rows)?;
+        exported += rows.len();
+
+        match rows.last() {
+            Some(row) if rows.len() as i64 == EXPORT_BATCH_SIZE => last_id = row.get_id(),
+            _ => break,
+        }
+    }
+
+    export_writer.finish()?;
+
+    Ok(exported)
+}
+
+/// Buffers written bytes and hands them on in chunks of about `chunk_size`,
+/// e.g. to an HTTP response stream or a Socket.IO event.
+pub struct ChunkWriter<F: FnMut(Vec<u8>) -> std::io::Result<()> + Send> {
+    buffer: Vec<u8>,
+    chunk_size: usize,
+    send_chunk: F,
+}
+
+impl<F: FnMut(Vec<u8>) -> std::io::Result<()> + Send> ChunkWriter<F> {
+    pub fn new(chunk_size: usize, send_chunk: F) -> Self {
+        Self {
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+            send_chunk,
+        }
+    }
+}
+
+impl<F: FnMut(Vec<u8>) -> std::io::Result<()> + Send> Write for ChunkWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= self.chunk_size {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.chunk_size));
+
+        (self.send_chunk)(chunk)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{Sensor, SensorRead};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn export(format: ExportFormat, rows: &[SensorReadExportRow]) -> Vec<u8> {
+        let mut chunks = vec![];
+
+        let writer = ChunkWriter::new(16, |chunk| {
+            chunks.push(chunk);
+            Ok(())
+        });
+
+        let mut export_writer = ExportWriter::new(format, writer).unwrap();
+        export_writer.write_batch(rows).unwrap();
+        export_writer.write_batch(&rows[1..]).unwrap();
+        export_writer.finish().unwrap();
+
+        chunks.concat()
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let mut sensor = Sensor::new(1, "temperature", "10.0.0.5");
+        sensor.set_name(Some("Greenhouse".to_string()));
+
+        let rows = vec![
+            SensorReadExportRow::new(&SensorRead::new(1, 1, "21.5 °C"), &sensor),
+            SensorReadExportRow::new(&SensorRead::new(2, 1, "offline"), &sensor),
+        ];
+
+        let csv = String::from_utf8(export(ExportFormat::Csv, &rows)).unwrap();
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(
+            lines[0],
+            "id,sensor_id,sensor_name,sensor_type,sensor_value,unit,raw_value,created_at"
+        );
+        assert!(lines[1].starts_with("1,1,Greenhouse,temperature,21.5,°C,21.5 °C,"));
+        assert!(lines[2].starts_with("2,1,Greenhouse,temperature,,,offline,"));
+        assert_eq!(lines.len(), 4);
+
+        let jsonl = String::from_utf8(export(ExportFormat::JsonLines, &rows)).unwrap();
+        let first = serde_json::from_str::<SensorReadExportRow>(jsonl.lines().next().unwrap());
+        assert_eq!(first.unwrap(), rows[0]);
+        assert_eq!(jsonl.lines().count(), 3);
+
+        let parquet = export(ExportFormat::Parquet, &rows);
+        let reader = SerializedFileReader::new(bytes::Bytes::from(parquet)).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+        assert!(ExportFormat::parse(Some("xlsx")).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+type Operator = &'static str;
+
+use crate::script_parser::{get_variable, Args, Value, Variables};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+
+const PARENTHESIS_OPEN: Operator = "(";
+const PARENTHESIS_CLOSE: Operator = ")";
+const ARGUMENT_SEPARATOR: Operator = ",";
+
+pub const ADD: Operator = "+";
+pub const SUBTRACT: Operator = "-";
+pub const MULTIPLY: Operator = "*";
+pub const DIVIDE: Operator = "/";
+pub const MODULO: Operator = "%";
+
+pub const FUNCTION_MIN: &str = "min";
+pub const FUNCTION_MAX: &str = "max";
+const FUNCTION_ABS: &str = "abs";
+const FUNCTION_ROUND: &str = "round";
+pub const FUNCTION_AVG: &str = "avg";
+
+const FUNCTIONS: [&str; 5] = [
+    FUNCTION_MIN,
+    FUNCTION_MAX,
+    FUNCTION_ABS,
+    FUNCTION_ROUND,
+    FUNCTION_AVG,
+];
+
+fn match_operator(operator: &str) -> Option<Operator> {
+    match operator {
+        ADD => Some(ADD),
+        SUBTRACT => Some(SUBTRACT),
+        MULTIPLY => Some(MULTIPLY),
+        DIVIDE => Some(DIVIDE),
+        MODULO => Some(MODULO),
+        _ => None,
+    }
+}
+
+fn match_function(name: &str) -> Option<&'static str> {
+    FUNCTIONS
+        .iter()
+        .find(|function| **function == name)
+        .copied()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn from_value(value: &Value) -> Result<Number> {
+        match value {
+            Value::Int32(n) => Ok(Number::Int(*n as i64)),
+            Value::Int64(n) => Ok(Number::Int(*n)),
+            Value::Float32(n) => Ok(Number::Float(*n as f64)),
+            Value::Float64(n) => Ok(Number::Float(*n)),
+            Value::None => Err(anyhow!("Cannot use an empty value in an expression")),
+            value => Err(anyhow!("{:?} is not a number", value)),
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Number::Int(n) => *n as f64,
+            Number::Float(n) => *n,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Number::Int(n) => Value::Int64(n),
+            Number::Float(n) => Value::Float64(n),
+        }
+    }
+}
+
+/// Integers stay integers unless a division leaves a remainder; anything
+/// involving a float is computed as a float.
+fn apply_operator(operator: Operator, left: Number, right: Number) -> Result<Number> {
+    let overflow = || anyhow!("Integer overflow");
+
+    if let (Number::Int(left), Number::Int(right)) = (left, right) {
+        return match operator {
+            ADD => left
+                .checked_add(right)
+                .map(Number::Int)
+                .ok_or_else(overflow),
+            SUBTRACT => left
+                .checked_sub(right)
+                .map(Number::Int)
+                .ok_or_else(overflow),
+            MULTIPLY => left
+                .checked_mul(right)
+                .map(Number::Int)
+                .ok_or_else(overflow),
+            DIVIDE if right == 0 => Err(anyhow!("Division by zero")),
+            DIVIDE if left % right == 0 => left
+                .checked_div(right)
+                .map(Number::Int)
+                .ok_or_else(overflow),
+            DIVIDE => Ok(Number::Float(left as f64 / right as f64)),
+            MODULO if right == 0 => Err(anyhow!("Division by zero")),
+            MODULO => left
+                .checked_rem(right)
+                .map(Number::Int)
+                .ok_or_else(overflow),
+            _ => Err(anyhow!("Invalid operator {}", operator)),
+        };
+    }
+
+    let (left, right) = (left.as_float(), right.as_float());
+
+    match operator {
+        ADD => Ok(Number::Float(left + right)),
+        SUBTRACT => Ok(Number::Float(left - right)),
+        MULTIPLY => Ok(Number::Float(left * right)),
+        DIVIDE | MODULO if right == 0.0 => Err(anyhow!("Division by zero")),
+        DIVIDE => Ok(Number::Float(left / right)),
+        MODULO => Ok(Number::Float(left % right)),
+        _ => Err(anyhow!("Invalid operator {}", operator)),
+    }
+}
+
+/// `min`, `max` and `avg` take any number of values; arrays are spread so
+/// `avg([1, 2, 3])` works too.
+fn spread_numbers(values: Vec<Value>) -> Result<Vec<Number>> {
+    let mut numbers = Vec::new();
+
+    for value in values {
+        match value {
+            Value::Array(array) => numbers.extend(spread_numbers(array)?),
+            value => numbers.push(Number::from_value(&value)?),
+        }
+    }
+
+    Ok(numbers)
+}
+
+fn call_function(name: &str, values: Vec<Value>) -> Result<Number> {
+    match name {
+        FUNCTION_ABS | FUNCTION_ROUND if values.is_empty() => {
+            Err(anyhow!("{} requires an argument", name))
+        }
+        FUNCTION_ABS => match (values.len(), Number::from_value(&values[0])?) {
+            (1, Number::Int(n)) => n
+                .checked_abs()
+                .map(Number::Int)
+                .ok_or_else(|| anyhow!("Integer overflow")),
+            (1, Number::Float(n)) => Ok(Number::Float(n.abs())),
+            _ => Err(anyhow!("abs takes one argument")),
+        },
+        FUNCTION_ROUND => {
+            let number = Number::from_value(&values[0])?;
+
+            match values.get(1).map(Number::from_value).transpose()? {
+                None if values.len() == 1 => match number {
+                    Number::Int(n) => Ok(Number::Int(n)),
+                    Number::Float(n) => Ok(Number::Int(n.round() as i64)),
+                },
+                Some(Number::Int(digits)) if values.len() == 2 => {
+                    let factor = 10f64.powi(digits.clamp(-15, 15) as i32);
+                    Ok(Number::Float((number.as_float() * factor).round() / factor))
+                }
+                _ => Err(anyhow!(
+                    "round takes a value and an optional number of digits"
+                )),
+            }
+        }
+        _ => {
+            let numbers = spread_numbers(values)?;
+
+            let first = match numbers.first() {
+                Some(first) => *first,
+                None => {
+                    return Err(anyhow!("{} requires at least one value", name));
+                }
+            };
+
+            match name {
+                FUNCTION_MIN => Ok(numbers.into_iter().fold(first, |min, n| {
+                    if n.as_float() < min.as_float() {
+                        n
+                    } else {
+                        min
+                    }
+                })),
+                FUNCTION_MAX => Ok(numbers.into_iter().fold(first, |max, n| {
+                    if n.as_float() > max.as_float() {
+                        n
+                    } else {
+                        max
+                    }
+                })),
+                FUNCTION_AVG => {
+                    let count = Number::Int(numbers.len() as i64);
+
+                    let mut sum = Number::Int(0);
+
+                    for number in numbers {
+                        sum = apply_operator(ADD, sum, number)?;
+                    }
+
+                    apply_operator(DIVIDE, sum, count)
+                }
+                _ => Err(anyhow!("Unknown function {}", name)),
+            }
+        }
+    }
+}
+
+/// Applies an expression function such as `avg` directly to a list of values.
+pub fn apply_function(name: &str, values: Vec<Value>) -> Result<Value> {
+    call_function(name, values).map(Number::into_value)
+}
+
+#[derive(Clone, Debug)]
+pub enum ValueExpression {
+    Value(Value),
+    Negate(Box<ValueExpression>),
+    Operation(Operator, Box<ValueExpression>, Box<ValueExpression>),
+    Function(&'static str, Vec<ValueExpression>),
+}
+
+impl ValueExpression {
+    pub fn evaluate(&self, variables: &Variables, now: &DateTime<Local>) -> Result<Value> {
+        match self {
+            ValueExpression::Value(Value::Variable(name)) => {
+                match get_variable(name, variables, now) {
+                    Some(value) => Ok(value),
+                    None => Err(anyhow!("Variable {} not found", name)),
+                }
+            }
+            ValueExpression::Value(Value::Array(values)) => Ok(Value::Array(
+                values
+                    .iter()
+                    .map(|value| value.clone().resolve(variables, now))
+                    .collect(),
+            )),
+            ValueExpression::Value(value) => Ok(value.clone()),
+            ValueExpression::Negate(expression) => {
+                let number = Number::from_value(&expression.evaluate(variables, now)?)?;
+
+                apply_operator(SUBTRACT, Number::Int(0), number).map(Number::into_value)
+            }
+            ValueExpression::Operation(operator, left, right) => {
+                let left = left.evaluate(variables, now)?;
+                let right = right.evaluate(variables, now)?;
+
+                // `+` joins text, so messages can be built as `"temp: " + $t`.
+                if *operator == ADD
+                    && (matches!(left, Value::String(_)) || matches!(right, Value::String(_)))
+                {
+                    return Ok(Value::String(
+                        left.to_string(variables, now) + &right.to_string(variables, now),
+                    ));
+                }
+
+                apply_operator(
+                    operator,
+                    Number::from_value(&left)?,
+                    Number::from_value(&right)?,
+                )
+                .map(Number::into_value)
+            }
+            ValueExpression::Function(name, arguments) => {
+                let mut values = Vec::new();
+
+                for argument in arguments {
+                    values.push(argument.evaluate(variables, now)?);
+                }
+
+                call_function(name, values).map(Number::into_value)
+            }
+        }
+    }
+}
+
+fn is_symbol(value: Option<&Value>, symbol: &str) -> bool {
+    matches!(value, Some(Value::String(s)) if s == symbol)
+}
+
+/// Recursive-descent parser over expression components: `*`, `/` and `%`
+/// bind tighter than `+` and `-`, unary minus tighter still, and parentheses
+/// group.
+struct ExpressionParser {
+    components: Args,
+    position: usize,
+}
+
+impl ExpressionParser {
+    fn peek(&self) -> Option<&Value> {
+        self.components.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Value> {
+        let component = self.components.get(self.position).cloned();
+        self.position += 1;
+        component
+    }
+
+    fn peek_operator(&self, operators: &[Operator]) -> Option<Operator> {
+        match self.peek() {
+            Some(Value::String(s)) => operators.iter().find(|operator| **operator == s).copied(),
+            _ => None,
+        }
+    }
+
+    /// `$a -1` reaches the parser as an operand followed by the number `-1`,
+    /// which reads as a subtraction. The smallest integers have no positive
+    /// counterpart of their own size and move up to the next wider type.
+    fn next_negative_number(&mut self) -> Option<Value> {
+        let number = match self.peek() {
+            Some(Value::Int32(n)) if *n < 0 => match n.checked_neg() {
+                Some(n) => Value::Int32(n),
+                None => Value::Int64(-(*n as i64)),
+            },
+            Some(Value::Int64(n)) if *n < 0 => match n.checked_neg() {
+                Some(n) => Value::Int64(n),
+                None => Value::Float64(-(*n as f64)),
+            },
+            Some(Value::Float64(n)) if *n < 0.0 => Value::Float64(-n),
+            _ => return None,
+        };
+
+        self.next();
+
+        Some(number)
+    }
+
+    fn parse_additive(&mut self) -> Result<ValueExpression> {
+        let mut expression = self.parse_multiplicative()?;
+
+        loop {
+            if let Some(number) = self.next_negative_number() {
+                expression = ValueExpression::Operation(
+                    SUBTRACT,
+                    Box::new(expression),
+                    Box::new(ValueExpression::Value(number)),
+                );
+                continue;
+            }
+
+            let operator = match self.peek_operator(&[ADD, SUBTRACT]) {
+                Some(operator) => operator,
+                None => break,
+            };
+
+            self.next();
+
+            let right = self.parse_multiplicative()?;
+            expression =
+                ValueExpression::Operation(operator, Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<ValueExpression> {
+        let mut expression = self.parse_unary()?;
+
+        while let Some(operator) = self.peek_operator(&[MULTIPLY, DIVIDE, MODULO]) {
+            self.next();
+
+            let right = self.parse_unary()?;
+            expression =
+                ValueExpression::Operation(operator, Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_unary(&mut self) -> Result<ValueExpression> {
+        if is_symbol(self.peek(), SUBTRACT) {
+            self.next();
+
+            return Ok(ValueExpression::Negate(Box::new(self.parse_unary()?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<ValueExpression> {
+        match self.next() {
+            Some(Value::String(s)) if s == PARENTHESIS_OPEN => {
+                let expression = self.parse_additive()?;
+
+                if !is_symbol(self.peek(), PARENTHESIS_CLOSE) {
+                    return Err(anyhow!("Missing closing parenthesis in expression"));
+                }
+
+                self.next();
+
+                Ok(expression)
+            }
+            Some(Value::String(s)) if is_symbol(self.peek(), PARENTHESIS_OPEN) => {
+                let name = match match_function(&s) {
+                    Some(name) => name,
+                    None => {
+                        return Err(anyhow!("Unknown function {}", s));
+                    }
+                };
+
+                self.next();
+
+                Ok(ValueExpression::Function(
+                    name,
+                    self.parse_function_arguments()?,
+                ))
+            }
+            Some(Value::String(s))
+                if match_operator(&s).is_some()
+                    || [PARENTHESIS_CLOSE, ARGUMENT_SEPARATOR].contains(&s.as_str()) =>
+            {
+                Err(anyhow!("Unexpected '{}' in expression", s))
+            }
+            Some(component) => Ok(ValueExpression::Value(component)),
+            None => Err(anyhow!("Incomplete expression")),
+        }
+    }
+
+    fn parse_function_arguments(&mut self) -> Result<Vec<ValueExpression>> {
+        let mut arguments = Vec::new();
+
+        if is_symbol(self.peek(), PARENTHESIS_CLOSE) {
+            self.next();
+            return Ok(arguments);
+        }
+
+        loop {
+            arguments.push(self.parse_additive()?);
+
+            match self.next() {
+                Some(Value::String(s)) if s == ARGUMENT_SEPARATOR => {}
+                Some(Value::String(s)) if s == PARENTHESIS_CLOSE => {
+                    return Ok(arguments);
+                }
+                _ => {
+                    return Err(anyhow!("Missing closing parenthesis in expression"));
+                }
+            }
+        }
+    }
+}
+
+pub fn parse_expression(expression_component_vec: Args) -> Result<ValueExpression> {
+    let mut parser = ExpressionParser {
+        components: expression_component_vec,
+        position: 0,
+    };
+
+    let expression = parser.parse_additive()?;
+
+    if let Some(component) = parser.peek() {
+        return Err(anyhow!(
+            "Unexpected '{}' in expression",
+            component.to_string(&Variables::new(), &Local::now())
+        ));
+    }
+
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::script_parser::parse_arguments;
+    use std::collections::HashMap;
+
+    fn evaluate(expression: &str, variables: &Variables) -> Result<Value> {
+        parse_expression(parse_arguments(expression)?)?.evaluate(variables, &Local::now())
+    }
+
+    #[test]
+    fn test_evaluate_expression() {
+        let mut variables: Variables = HashMap::new();
+        variables.insert("$a".to_string(), Value::Int32(3));
+        variables.insert("$b".to_string(), Value::Int32(5));
+        variables.insert("$t".to_string(), Value::Float64(21.5));
+
+        assert_eq!(
+            evaluate("($a + $b) / 2", &variables).unwrap(),
+            Value::Int64(4)
+        );
+        assert_eq!(
+            evaluate("$a + $b / 2", &variables).unwrap(),
+            Value::Float64(5.5)
+        );
+        assert_eq!(evaluate("$b -1", &variables).unwrap(), Value::Int64(4));
+        assert_eq!(
+            evaluate("-$a * -2 % 4", &variables).unwrap(),
+            Value::Int64(2)
+        );
+        assert_eq!(
+            evaluate("$t * 2 - 1", &variables).unwrap(),
+            Value::Float64(42.0)
+        );
+        assert_eq!(
+            evaluate("max($a, $b, 4)", &variables).unwrap(),
+            Value::Int64(5)
+        );
+        assert_eq!(
+            evaluate("min([$t, 30]) + abs(-1)", &variables).unwrap(),
+            Value::Float64(22.5)
+        );
+        assert_eq!(evaluate("round($t)", &variables).unwrap(), Value::Int64(22));
+        assert_eq!(
+            evaluate("round(2 / 3, 2)", &variables).unwrap(),
+            Value::Float64(0.67)
+        );
+        assert_eq!(
+            evaluate("avg($a, $b, $t)", &variables).unwrap(),
+            Value::Float64(29.5 / 3.0)
+        );
+        assert_eq!(
+            evaluate("\"a is \" + $a", &variables).unwrap(),
+            Value::String("a is 3".to_string())
+        );
+
+        assert_eq!(
+            evaluate("$a -2147483648", &variables).unwrap(),
+            Value::Int64(-2147483645)
+        );
+        assert_eq!(
+            evaluate("$a -9223372036854775808", &variables).unwrap(),
+            Value::Float64(3.0 - 9223372036854775808.0)
+        );
+
+        assert!(evaluate("$a / 0", &variables).is_err());
+        assert!(evaluate("$missing + 1", &variables).is_err());
+        assert!(evaluate("($a + 1", &variables).is_err());
+        assert!(evaluate("$a +", &variables).is_err());
+        assert!(evaluate("sqrt($a)", &variables).is_err());
+    }
+}
@@ -1,7 +1,31 @@
 use crate::events::MESSAGE_SENT_EVENT;
-use serde_json::json;
+use crate::protocol::DashboardMessage;
+use anyhow::{Error, Result};
+use serde::Serialize;
 use socketioxide::extract::SocketRef;
-use socketioxide::SendError;
+use socketioxide::{SendError, SocketIo};
+use std::fmt;
+
+/// A request that was rejected for its parameters or payload, as opposed to
+/// something that failed while handling it.
+#[derive(Debug)]
+pub struct InvalidInput {
+    message: String,
+}
+
+pub fn invalid_input(message: &str) -> Error {
+    Error::new(InvalidInput {
+        message: message.to_string(),
+    })
+}
+
+impl fmt::Display for InvalidInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for InvalidInput {}
 
 pub enum DashboardMessageType {
     Info,
@@ -28,10 +52,110 @@ pub fn send_message_to_dashboard(
 ) -> Result<(), SendError> {
     socket.emit(
         MESSAGE_SENT_EVENT,
-        json!({
-            "message": message,
-            "type": message_type.get_class(),
-        }),
+        DashboardMessage::new(message, &message_type),
     )
 }
 
+/// Where script output and status updates go: the socket that asked for the
+/// run, or every connected dashboard when nobody did (scheduled runs).
+#[derive(Clone)]
+pub enum DashboardTarget {
+    Socket(SocketIo, String),
+    All(SocketIo),
+}
+
+impl DashboardTarget {
+    pub fn socket(io: &SocketIo, socket: &SocketRef) -> Self {
+        DashboardTarget::Socket(io.clone(), socket.id.to_string())
+    }
+
+    /// Socket that asked for the run, `None` when it goes to everyone.
+    pub fn get_socket_id(&self) -> Option<&str> {
+        match self {
+            DashboardTarget::Socket(_, socket_id) => Some(socket_id),
+            DashboardTarget::All(_) => None,
+        }
+    }
+
+    fn get_io(&self) -> &SocketIo {
+        match self {
+            DashboardTarget::Socket(io, _) => io,
+            DashboardTarget::All(io) => io,
+        }
+    }
+
+    pub fn send_message(&self, message: String, message_type: DashboardMessageType) -> Result<()> {
+        self.emit(
+            MESSAGE_SENT_EVENT,
+            DashboardMessage::new(message, &message_type),
+        )
+    }
+
+    /// Emits to the requesting socket only, or to everyone for `All`.
+    pub fn emit(&self, event: &'static str, data: impl Serialize) -> Result<()> {
+        let ns = match self.get_io().of("/") {
+            Some(ns) => ns,
+            None => return Err(Error::msg("Namespace not found")),
+        };
+
+        match self {
+            DashboardTarget::Socket(_, socket_id) => {
+                let sockets = ns
+                    .sockets()
+                    .map_err(|_| Error::msg("Error listing sockets"))?;
+
+                match sockets.iter().find(|s| &s.id.to_string() == socket_id) {
+                    Some(socket) => socket
+                        .emit(event, data)
+                        .map_err(|e| Error::msg(e.to_string())),
+                    None => Err(Error::msg("Socket disconnected")),
+                }
+            }
+            DashboardTarget::All(_) => ns.emit(event, data).map_err(|e| Error::msg(e.to_string())),
+        }
+    }
+
+    /// Same as `emit`, with binary attachments sent alongside the data.
+    pub fn emit_binary(
+        &self,
+        event: &'static str,
+        data: impl Serialize,
+        binary: Vec<Vec<u8>>,
+    ) -> Result<()> {
+        let ns = match self.get_io().of("/") {
+            Some(ns) => ns,
+            None => return Err(Error::msg("Namespace not found")),
+        };
+
+        match self {
+            DashboardTarget::Socket(_, socket_id) => {
+                let sockets = ns
+                    .sockets()
+                    .map_err(|_| Error::msg("Error listing sockets"))?;
+
+                match sockets.iter().find(|s| &s.id.to_string() == socket_id) {
+                    Some(socket) => socket
+                        .bin(binary)
+                        .emit(event, data)
+                        .map_err(|e| Error::msg(e.to_string())),
+                    None => Err(Error::msg("Socket disconnected")),
+                }
+            }
+            DashboardTarget::All(_) => ns
+                .bin(binary)
+                .emit(event, data)
+                .map_err(|e| Error::msg(e.to_string())),
+        }
+    }
+
+    pub fn emit_to_all(&self, event: &'static str, data: impl Serialize) {
+        if let Some(ns) = self.get_io().of("/") {
+            match ns.emit(event, data) {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error emitting {} event: {:?}", event, e);
+                }
+            }
+        }
+    }
+}
@@ -12,6 +12,10 @@ pub mod sensor_methods;
 
 pub mod sensor_handlers;
 
+pub mod sensor_type_methods;
+
+pub mod rollup_methods;
+
 #[macro_use]
 extern crate alloc;
 
@@ -25,14 +29,27 @@ mod observer;
 pub mod server;
 
 pub mod actuator_handlers;
+pub mod api_docs;
+pub mod api_handlers;
 pub mod actuator_methods;
 pub mod condition_parser;
 pub mod events;
+pub mod export_handlers;
+pub mod export_methods;
+pub mod expression_parser;
+pub mod script_debugger;
 pub mod script_parser;
 pub mod script_runner;
+pub mod script_simulation;
+pub mod script_time;
 pub mod sensor_types;
 
 pub mod auth;
 pub mod helper;
+
+pub mod protocol;
+pub mod rule_methods;
+pub mod script_lexer;
 pub mod script_methods;
+pub mod variable_methods;
 
@@ -1,6 +1,8 @@
 use anyhow::Result;
 use dotenv::dotenv;
+use homesoil::auth::{ensure_initial_user, get_auth_config};
 use homesoil::db::connect;
+use homesoil::script_runner::run_script_scheduler;
 use homesoil::servers::{
     check_for_old_sensor_reads_records, run_coap_server, run_sensor_health_check, run_socket_server,
 };
@@ -16,6 +18,19 @@ async fn main() -> Result<()> {
         }
     }
 
+    match get_auth_config() {
+        Ok(_) => {}
+        Err(e) => {
+            panic!("Error reading auth config: {}", e);
+        }
+    }
+
+    match ensure_initial_user() {
+        Ok(Some(user)) => println!("Created user {}", user.get_username()),
+        Ok(None) => {}
+        Err(e) => println!("Error creating initial user: {}", e),
+    }
+
     let mut current_ip_address = match local_ip() {
         Ok(ip) => ip.to_string(),
         Err(_) => {
@@ -37,6 +52,8 @@ async fn main() -> Result<()> {
 
     run_sensor_health_check(&io).await;
 
+    run_script_scheduler(&io).await;
+
     run_coap_server(String::leak(current_ip_address_coap), &io).await;
 
     check_for_old_sensor_reads_records().await;
@@ -1,5 +1,9 @@
+use crate::sensor_methods::{parse_sensor_value, raw_sensor_value};
 use diesel::{Identifiable, Insertable, Queryable, QueryableByName, Selectable};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::ToSchema;
 
 //SENSORS
 
@@ -14,6 +18,8 @@ use serde::{Deserialize, Serialize};
     Identifiable,
     QueryableByName,
     Insertable,
+    ToSchema,
+    JsonSchema,
 )]
 #[diesel(table_name = crate::schema::sensors)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -117,6 +123,8 @@ impl Sensor {
     PartialEq,
     Identifiable,
     QueryableByName,
+    ToSchema,
+    JsonSchema,
 )]
 #[diesel(table_name = crate::schema::sensor_reads)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -124,17 +132,23 @@ impl Sensor {
 pub struct SensorRead {
     id: i32,
     sensor_id: i32,
-    sensor_value: String,
+    sensor_value: Option<f64>,
+    unit: Option<String>,
+    raw_value: String,
     created_at: chrono::NaiveDateTime,
     updated_at: Option<chrono::NaiveDateTime>,
 }
 
 impl SensorRead {
-    pub fn new(id: i32, sensor_id: i32, sensor_value: &str) -> Self {
+    pub fn new(id: i32, sensor_id: i32, raw_value: &str) -> Self {
+        let (sensor_value, unit) = parse_sensor_value(raw_value);
+
         Self {
             id,
             sensor_id,
-            sensor_value: sensor_value.to_string(),
+            sensor_value,
+            unit,
+            raw_value: raw_value.to_string(),
             created_at: chrono::Local::now().naive_local(),
             updated_at: None,
         }
@@ -148,8 +162,18 @@ impl SensorRead {
         self.sensor_id
     }
 
-    pub fn get_sensor_value(&self) -> &str {
-        &self.sensor_value
+    /// The reading as a number, `None` when the device sent something else.
+    pub fn get_sensor_value(&self) -> Option<f64> {
+        self.sensor_value
+    }
+
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    /// The payload exactly as the device sent it.
+    pub fn get_raw_value(&self) -> &str {
+        &self.raw_value
     }
 
     pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
@@ -181,6 +205,8 @@ impl SensorRead {
     PartialEq,
     Identifiable,
     QueryableByName,
+    ToSchema,
+    JsonSchema,
 )]
 #[diesel(table_name = crate::schema::actuators)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -342,6 +368,14 @@ impl NewSensor {
         self.ip_address = ip_address;
     }
 
+    pub fn get_port(&self) -> i16 {
+        self.port
+    }
+
+    pub fn set_port(&mut self, port: i16) {
+        self.port = port;
+    }
+
     pub fn set_online(&mut self, online: bool) {
         self.online = online;
     }
@@ -351,20 +385,51 @@ impl NewSensor {
     }
 }
 
+/// Registration of a device that measures several things at once, such as a
+/// BME280. Every entry of `sensor_types` becomes its own sensor.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SensorChannelsRegister {
+    ip_address: String,
+    port: i16,
+    sensor_types: Vec<String>,
+}
+
+impl SensorChannelsRegister {
+    pub fn get_ip_address(&self) -> &str {
+        &self.ip_address
+    }
+
+    pub fn get_port(&self) -> i16 {
+        self.port
+    }
+
+    pub fn get_sensor_types(&self) -> &Vec<String> {
+        &self.sensor_types
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Insertable)]
 #[diesel(table_name = crate::schema::sensor_reads)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct NewSensorRead {
     sensor_id: i32,
-    sensor_value: String,
+    sensor_value: Option<f64>,
+    unit: Option<String>,
+    raw_value: String,
     created_at: Option<chrono::NaiveDateTime>,
 }
 
 impl NewSensorRead {
-    pub fn new(sensor_id: i32, sensor_value: &str) -> Self {
+    /// Splits a payload such as `"21.5 °C"` into its number and unit; an
+    /// explicit `unit` wins over the one found in the payload.
+    pub fn new(sensor_id: i32, raw_value: &str, unit: Option<String>) -> Self {
+        let (sensor_value, parsed_unit) = parse_sensor_value(raw_value);
+
         Self {
             sensor_id,
-            sensor_value: sensor_value.to_string(),
+            sensor_value,
+            unit: unit.or(parsed_unit),
+            raw_value: raw_value.to_string(),
             created_at: None,
         }
     }
@@ -373,8 +438,20 @@ impl NewSensorRead {
         self.sensor_id
     }
 
-    pub fn get_sensor_value(&self) -> &str {
-        &self.sensor_value
+    pub fn get_sensor_value(&self) -> Option<f64> {
+        self.sensor_value
+    }
+
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    pub fn get_raw_value(&self) -> &str {
+        &self.raw_value
+    }
+
+    pub fn set_unit(&mut self, unit: Option<String>) {
+        self.unit = unit;
     }
 
     pub fn get_created_at(&self) -> &Option<chrono::NaiveDateTime> {
@@ -386,7 +463,60 @@ impl NewSensorRead {
     }
 }
 
+/// Body of a CoAP `/sensor` read. Devices may send the value as a JSON
+/// number or as text, optionally followed by its unit.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SensorReadPayload {
+    sensor_id: i32,
+    sensor_value: serde_json::Value,
+    unit: Option<String>,
+}
+
+impl SensorReadPayload {
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_raw_value(&self) -> String {
+        raw_sensor_value(&self.sensor_value)
+    }
+
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    pub fn into_new_sensor_read(self) -> NewSensorRead {
+        NewSensorRead::new(self.sensor_id, &self.get_raw_value(), self.unit)
+    }
+}
+
+/// Several readings from one device, keyed by the sensor type of each
+/// channel, e.g. `{"temperature": 21.5, "humidity": "40 %"}`.
 #[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SensorChannelsRead {
+    ip_address: String,
+    channels: BTreeMap<String, serde_json::Value>,
+    units: Option<HashMap<String, String>>,
+}
+
+impl SensorChannelsRead {
+    pub fn get_ip_address(&self) -> &str {
+        &self.ip_address
+    }
+
+    pub fn get_channels(&self) -> &BTreeMap<String, serde_json::Value> {
+        &self.channels
+    }
+
+    pub fn get_unit(&self, channel: &str) -> Option<String> {
+        self.units
+            .as_ref()
+            .and_then(|units| units.get(channel))
+            .cloned()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct UpdateSensorName {
     id: i32,
     name: String,
@@ -419,7 +549,7 @@ impl UpdateSensorName {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct SensorUnregister {
     id: i32,
 }
@@ -434,11 +564,18 @@ impl SensorUnregister {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// History request from the dashboard. Dates may be RFC 3339 with a time
+/// zone or local `%Y-%m-%d %H:%M:%S`. Without a `bucket` the resolution is
+/// picked from the length of the range.
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct GetSensorReadings {
     id: i32,
     from_date: String,
     to_date: String,
+    bucket: Option<String>,
+    aggregate: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
 }
 
 impl GetSensorReadings {
@@ -447,6 +584,10 @@ impl GetSensorReadings {
             id,
             from_date,
             to_date,
+            bucket: None,
+            aggregate: None,
+            cursor: None,
+            limit: None,
         }
     }
 
@@ -461,6 +602,166 @@ impl GetSensorReadings {
     pub fn get_to_date(&self) -> &String {
         &self.to_date
     }
+
+    pub fn get_bucket(&self) -> &Option<String> {
+        &self.bucket
+    }
+
+    pub fn get_aggregate(&self) -> &Option<String> {
+        &self.aggregate
+    }
+
+    /// `next_cursor` of the previous page.
+    pub fn get_cursor(&self) -> &Option<String> {
+        &self.cursor
+    }
+
+    pub fn get_limit(&self) -> Option<i64> {
+        self.limit
+    }
+
+    pub fn set_bucket(&mut self, bucket: Option<String>) {
+        self.bucket = bucket;
+    }
+
+    pub fn set_aggregate(&mut self, aggregate: Option<String>) {
+        self.aggregate = aggregate;
+    }
+
+    pub fn set_cursor(&mut self, cursor: Option<String>) {
+        self.cursor = cursor;
+    }
+
+    pub fn set_limit(&mut self, limit: Option<i64>) {
+        self.limit = limit;
+    }
+}
+
+/// Numeric readings of one time bucket. `value` is the requested aggregate,
+/// the other fields are always filled so charts can draw a min/max band.
+#[derive(
+    QueryableByName, Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema, JsonSchema,
+)]
+pub struct SensorReadingBucket {
+    #[diesel(sql_type = diesel::sql_types::Timestamp)]
+    bucket_start: chrono::NaiveDateTime,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Double>)]
+    value: Option<f64>,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    min_value: f64,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    max_value: f64,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    avg_value: f64,
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    read_count: i32,
+}
+
+impl SensorReadingBucket {
+    pub fn from_rollup(rollup: &SensorReadRollup, value: Option<f64>) -> Self {
+        Self {
+            bucket_start: *rollup.get_bucket_start(),
+            value,
+            min_value: rollup.get_min_value(),
+            max_value: rollup.get_max_value(),
+            avg_value: rollup.get_avg_value(),
+            read_count: rollup.get_read_count(),
+        }
+    }
+
+    pub fn get_bucket_start(&self) -> &chrono::NaiveDateTime {
+        &self.bucket_start
+    }
+
+    pub fn get_value(&self) -> Option<f64> {
+        self.value
+    }
+
+    pub fn get_min_value(&self) -> f64 {
+        self.min_value
+    }
+
+    pub fn get_max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    pub fn get_avg_value(&self) -> f64 {
+        self.avg_value
+    }
+
+    pub fn get_read_count(&self) -> i32 {
+        self.read_count
+    }
+}
+
+/// History of a sensor as stored reads or as aggregated buckets.
+#[derive(Serialize, Debug, Clone, ToSchema, JsonSchema)]
+#[serde(untagged)]
+pub enum SensorReadings {
+    Raw(Vec<SensorRead>),
+    Buckets(Vec<SensorReadingBucket>),
+}
+
+impl SensorReadings {
+    pub fn len(&self) -> usize {
+        match self {
+            SensorReadings::Raw(sensor_reads) => sensor_reads.len(),
+            SensorReadings::Buckets(buckets) => buckets.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// One page of a history request and where the next one starts.
+#[derive(Serialize, Debug, Clone, ToSchema, JsonSchema)]
+pub struct SensorReadingsPage {
+    bucket: String,
+    aggregate: String,
+    tier: String,
+    sensor_reads: SensorReadings,
+    next_cursor: Option<String>,
+}
+
+impl SensorReadingsPage {
+    pub fn new(
+        bucket: &str,
+        aggregate: &str,
+        tier: &str,
+        sensor_reads: SensorReadings,
+        next_cursor: Option<String>,
+    ) -> Self {
+        Self {
+            bucket: bucket.to_string(),
+            aggregate: aggregate.to_string(),
+            tier: tier.to_string(),
+            sensor_reads,
+            next_cursor,
+        }
+    }
+
+    pub fn get_bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn get_aggregate(&self) -> &str {
+        &self.aggregate
+    }
+
+    /// Where the data came from: `raw` or a rollup tier.
+    pub fn get_tier(&self) -> &str {
+        &self.tier
+    }
+
+    pub fn get_sensor_reads(&self) -> &SensorReadings {
+        &self.sensor_reads
+    }
+
+    pub fn get_next_cursor(&self) -> &Option<String> {
+        &self.next_cursor
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Insertable)]
@@ -546,7 +847,7 @@ impl NewActuator {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct UpdateActuatorName {
     id: i32,
     name: String,
@@ -641,6 +942,8 @@ impl ActuatorUnregister {
     PartialEq,
     Identifiable,
     QueryableByName,
+    ToSchema,
+    JsonSchema,
 )]
 #[diesel(table_name = crate::schema::scripts)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -696,7 +999,7 @@ impl Script {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Insertable)]
+#[derive(Deserialize, Serialize, Debug, Clone, Insertable, JsonSchema)]
 #[diesel(table_name = crate::schema::scripts)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct NewScript {
@@ -739,6 +1042,14 @@ impl NewScript {
     pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
         &self.updated_at
     }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = title;
+    }
+
+    pub fn set_schedule(&mut self, schedule: Option<String>) {
+        self.schedule = schedule;
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -756,7 +1067,7 @@ impl DeleteScript {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct UpdateScript {
     id: i32,
     code: String,
@@ -882,3 +1193,986 @@ impl RemoveSchedule {
         self.updated_at = Some(updated_at);
     }
 }
+
+//SCRIPT RUNS
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+    ToSchema,
+    JsonSchema,
+)]
+#[diesel(table_name = crate::schema::script_runs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(belongs_to(Script))]
+pub struct ScriptRun {
+    id: i32,
+    script_id: i32,
+    trigger_source: String,
+    outcome: String,
+    error: Option<String>,
+    output: String,
+    started_at: chrono::NaiveDateTime,
+    finished_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ScriptRun {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_trigger_source(&self) -> &str {
+        &self.trigger_source
+    }
+
+    pub fn get_outcome(&self) -> &str {
+        &self.outcome
+    }
+
+    pub fn get_error(&self) -> &Option<String> {
+        &self.error
+    }
+
+    pub fn get_output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn get_started_at(&self) -> &chrono::NaiveDateTime {
+        &self.started_at
+    }
+
+    pub fn get_finished_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.finished_at
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::script_runs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewScriptRun {
+    script_id: i32,
+    trigger_source: String,
+    outcome: String,
+    started_at: chrono::NaiveDateTime,
+}
+
+impl NewScriptRun {
+    pub fn new(script_id: i32, trigger_source: &str, outcome: &str) -> Self {
+        Self {
+            script_id,
+            trigger_source: trigger_source.to_string(),
+            outcome: outcome.to_string(),
+            started_at: chrono::Local::now().naive_local(),
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_trigger_source(&self) -> &str {
+        &self.trigger_source
+    }
+
+    pub fn get_started_at(&self) -> &chrono::NaiveDateTime {
+        &self.started_at
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+pub struct GetScriptRuns {
+    script_id: i32,
+    before_id: Option<i32>,
+    limit: Option<i64>,
+}
+
+impl GetScriptRuns {
+    pub fn new(script_id: i32, before_id: Option<i32>, limit: Option<i64>) -> Self {
+        Self {
+            script_id,
+            before_id,
+            limit,
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_before_id(&self) -> Option<i32> {
+        self.before_id
+    }
+
+    pub fn get_limit(&self) -> Option<i64> {
+        self.limit
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+pub struct DryRunScript {
+    script_id: i32,
+    sensor_values: Option<HashMap<i32, String>>,
+}
+
+impl DryRunScript {
+    pub fn new(script_id: i32, sensor_values: Option<HashMap<i32, String>>) -> Self {
+        Self {
+            script_id,
+            sensor_values,
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_sensor_values(&self) -> HashMap<i32, String> {
+        self.sensor_values.clone().unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+pub struct DebugScript {
+    script_id: i32,
+    breakpoints: Option<Vec<usize>>,
+}
+
+impl DebugScript {
+    pub fn new(script_id: i32, breakpoints: Option<Vec<usize>>) -> Self {
+        Self {
+            script_id,
+            breakpoints,
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_breakpoints(&self) -> HashSet<usize> {
+        self.breakpoints.iter().flatten().copied().collect()
+    }
+}
+
+//SENSOR READ ROLLUPS
+
+/// Summary of a sensor's numeric readings over one bucket of a rollup tier.
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+)]
+#[diesel(table_name = crate::schema::sensor_read_rollups)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(belongs_to(Sensor))]
+pub struct SensorReadRollup {
+    id: i32,
+    sensor_id: i32,
+    tier: String,
+    bucket_start: chrono::NaiveDateTime,
+    min_value: f64,
+    max_value: f64,
+    avg_value: f64,
+    read_count: i32,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl SensorReadRollup {
+    /// `values` are the minimum, maximum and average of the bucket.
+    pub fn new(
+        sensor_id: i32,
+        tier: &str,
+        bucket_start: chrono::NaiveDateTime,
+        values: (f64, f64, f64),
+        read_count: i32,
+    ) -> Self {
+        let (min_value, max_value, avg_value) = values;
+
+        Self {
+            id: 0,
+            sensor_id,
+            tier: tier.to_string(),
+            bucket_start,
+            min_value,
+            max_value,
+            avg_value,
+            read_count,
+            created_at: chrono::Local::now().naive_local(),
+        }
+    }
+
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_tier(&self) -> &str {
+        &self.tier
+    }
+
+    pub fn get_bucket_start(&self) -> &chrono::NaiveDateTime {
+        &self.bucket_start
+    }
+
+    pub fn get_min_value(&self) -> f64 {
+        self.min_value
+    }
+
+    pub fn get_max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    pub fn get_avg_value(&self) -> f64 {
+        self.avg_value
+    }
+
+    pub fn get_read_count(&self) -> i32 {
+        self.read_count
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+}
+
+//SENSOR READ EXPORTS
+
+/// Export of sensor history. An empty or missing `sensor_ids` exports every
+/// sensor; `format` is `csv` (default), `jsonl` or `parquet`.
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorReadsExport {
+    sensor_ids: Option<Vec<i32>>,
+    from_date: String,
+    to_date: String,
+    format: Option<String>,
+    export_id: Option<String>,
+}
+
+impl SensorReadsExport {
+    pub fn new(sensor_ids: Vec<i32>, from_date: &str, to_date: &str) -> Self {
+        Self {
+            sensor_ids: Some(sensor_ids),
+            from_date: from_date.to_string(),
+            to_date: to_date.to_string(),
+            format: None,
+            export_id: None,
+        }
+    }
+
+    pub fn get_sensor_ids(&self) -> Vec<i32> {
+        self.sensor_ids.clone().unwrap_or_default()
+    }
+
+    pub fn get_from_date(&self) -> &str {
+        &self.from_date
+    }
+
+    pub fn get_to_date(&self) -> &str {
+        &self.to_date
+    }
+
+    pub fn get_format(&self) -> &Option<String> {
+        &self.format
+    }
+
+    /// Lets the dashboard match chunks to the export it asked for.
+    pub fn get_export_id(&self) -> &Option<String> {
+        &self.export_id
+    }
+
+    pub fn set_format(&mut self, format: Option<String>) {
+        self.format = format;
+    }
+
+    pub fn set_export_id(&mut self, export_id: Option<String>) {
+        self.export_id = export_id;
+    }
+}
+
+/// A stored read with the name and type of its sensor, as exported.
+#[derive(Queryable, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SensorReadExportRow {
+    id: i32,
+    sensor_id: i32,
+    sensor_name: Option<String>,
+    sensor_type: String,
+    sensor_value: Option<f64>,
+    unit: Option<String>,
+    raw_value: String,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl SensorReadExportRow {
+    pub fn new(sensor_read: &SensorRead, sensor: &Sensor) -> Self {
+        Self {
+            id: sensor_read.get_id(),
+            sensor_id: sensor.get_id(),
+            sensor_name: sensor.get_name().clone(),
+            sensor_type: sensor.get_sensor_type().to_string(),
+            sensor_value: sensor_read.get_sensor_value(),
+            unit: sensor_read.get_unit().clone(),
+            raw_value: sensor_read.get_raw_value().to_string(),
+            created_at: *sensor_read.get_created_at(),
+        }
+    }
+
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_sensor_name(&self) -> &Option<String> {
+        &self.sensor_name
+    }
+
+    pub fn get_sensor_type(&self) -> &str {
+        &self.sensor_type
+    }
+
+    pub fn get_sensor_value(&self) -> Option<f64> {
+        self.sensor_value
+    }
+
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    pub fn get_raw_value(&self) -> &str {
+        &self.raw_value
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+}
+
+//SENSOR TYPES
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+    JsonSchema,
+)]
+#[diesel(table_name = crate::schema::sensor_types)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct SensorType {
+    id: i32,
+    name: String,
+    display_name: String,
+    unit: Option<String>,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    decimal_places: Option<i32>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl SensorType {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name given to newly registered sensors of this type.
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    /// Unit stored with readings that do not state their own.
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    pub fn get_min_value(&self) -> Option<f64> {
+        self.min_value
+    }
+
+    pub fn get_max_value(&self) -> Option<f64> {
+        self.max_value
+    }
+
+    /// How many decimals the dashboard should show.
+    pub fn get_decimal_places(&self) -> Option<i32> {
+        self.decimal_places
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+
+    pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.updated_at
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Insertable, JsonSchema)]
+#[diesel(table_name = crate::schema::sensor_types)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewSensorType {
+    name: String,
+    display_name: String,
+    unit: Option<String>,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    decimal_places: Option<i32>,
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl NewSensorType {
+    pub fn new(name: &str, display_name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            display_name: display_name.to_string(),
+            unit: None,
+            min_value: None,
+            max_value: None,
+            decimal_places: None,
+            created_at: None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn get_unit(&self) -> &Option<String> {
+        &self.unit
+    }
+
+    pub fn get_min_value(&self) -> Option<f64> {
+        self.min_value
+    }
+
+    pub fn get_max_value(&self) -> Option<f64> {
+        self.max_value
+    }
+
+    pub fn get_decimal_places(&self) -> Option<i32> {
+        self.decimal_places
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn set_unit(&mut self, unit: Option<String>) {
+        self.unit = unit;
+    }
+
+    pub fn set_range(&mut self, min_value: Option<f64>, max_value: Option<f64>) {
+        self.min_value = min_value;
+        self.max_value = max_value;
+    }
+
+    pub fn set_decimal_places(&mut self, decimal_places: Option<i32>) {
+        self.decimal_places = decimal_places;
+    }
+
+    pub fn set_created_at(&mut self, created_at: chrono::NaiveDateTime) {
+        self.created_at = Some(created_at);
+    }
+}
+
+//SCRIPT VARIABLES
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+    JsonSchema,
+)]
+#[diesel(table_name = crate::schema::script_variables)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct ScriptVariable {
+    id: i32,
+    name: String,
+    value: String,
+    created_at: chrono::NaiveDateTime,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ScriptVariable {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value as JSON text, e.g. `3`, `"on"` or `[1, 2]`.
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+
+    pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.updated_at
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Insertable, JsonSchema)]
+#[diesel(table_name = crate::schema::script_variables)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewScriptVariable {
+    name: String,
+    value: String,
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl NewScriptVariable {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            created_at: None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn set_created_at(&mut self, created_at: chrono::NaiveDateTime) {
+        self.created_at = Some(created_at);
+    }
+}
+
+//SCRIPT RULES
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+    JsonSchema,
+)]
+#[diesel(table_name = crate::schema::script_rules)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(belongs_to(Script))]
+#[diesel(belongs_to(Sensor))]
+pub struct ScriptRule {
+    id: i32,
+    sensor_id: i32,
+    script_id: i32,
+    condition: String,
+    debounce_reads: i32,
+    cooldown_seconds: i32,
+    enabled: bool,
+    match_count: i32,
+    last_triggered_at: Option<chrono::NaiveDateTime>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ScriptRule {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_condition(&self) -> &str {
+        &self.condition
+    }
+
+    pub fn get_debounce_reads(&self) -> i32 {
+        self.debounce_reads
+    }
+
+    pub fn get_cooldown_seconds(&self) -> i32 {
+        self.cooldown_seconds
+    }
+
+    pub fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_match_count(&self) -> i32 {
+        self.match_count
+    }
+
+    pub fn get_last_triggered_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.last_triggered_at
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+
+    pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.updated_at
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Insertable, JsonSchema)]
+#[diesel(table_name = crate::schema::script_rules)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewScriptRule {
+    sensor_id: i32,
+    script_id: i32,
+    condition: String,
+    debounce_reads: Option<i32>,
+    cooldown_seconds: Option<i32>,
+    enabled: Option<bool>,
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl NewScriptRule {
+    pub fn new(sensor_id: i32, script_id: i32, condition: &str) -> Self {
+        Self {
+            sensor_id,
+            script_id,
+            condition: condition.to_string(),
+            debounce_reads: None,
+            cooldown_seconds: None,
+            enabled: None,
+            created_at: None,
+        }
+    }
+
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_condition(&self) -> &str {
+        &self.condition
+    }
+
+    pub fn get_debounce_reads(&self) -> Option<i32> {
+        self.debounce_reads
+    }
+
+    pub fn get_cooldown_seconds(&self) -> Option<i32> {
+        self.cooldown_seconds
+    }
+
+    pub fn set_created_at(&mut self, created_at: chrono::NaiveDateTime) {
+        self.created_at = Some(created_at);
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+pub struct UpdateScriptRule {
+    id: i32,
+    sensor_id: i32,
+    script_id: i32,
+    condition: String,
+    debounce_reads: i32,
+    cooldown_seconds: i32,
+    enabled: bool,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl UpdateScriptRule {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_condition(&self) -> &str {
+        &self.condition
+    }
+
+    pub fn get_debounce_reads(&self) -> i32 {
+        self.debounce_reads
+    }
+
+    pub fn get_cooldown_seconds(&self) -> i32 {
+        self.cooldown_seconds
+    }
+
+    pub fn get_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.updated_at
+    }
+
+    pub fn set_updated_at(&mut self, updated_at: chrono::NaiveDateTime) {
+        self.updated_at = Some(updated_at);
+    }
+}
+
+//USERS
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Selectable,
+    Serialize,
+    PartialEq,
+    Identifiable,
+    QueryableByName,
+    ToSchema,
+    JsonSchema,
+)]
+#[diesel(table_name = crate::schema::users)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct User {
+    id: i32,
+    username: String,
+    #[serde(skip)]
+    #[schemars(skip)]
+    password_hash: String,
+    created_at: chrono::NaiveDateTime,
+    updated_at: Option<chrono::NaiveDateTime>,
+    is_admin: bool,
+}
+
+impl User {
+    #[cfg(test)]
+    pub fn new(username: &str, is_admin: bool) -> Self {
+        Self {
+            id: 0,
+            username: username.to_string(),
+            password_hash: String::new(),
+            created_at: chrono::Local::now().naive_local(),
+            updated_at: None,
+            is_admin,
+        }
+    }
+
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_username(&self) -> &str {
+        &self.username
+    }
+
+    /// Argon2 hash in PHC string format, never sent to clients.
+    pub fn get_password_hash(&self) -> &str {
+        &self.password_hash
+    }
+
+    /// Admins are the only users who can add and remove users.
+    pub fn get_is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+
+    pub fn get_updated_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.updated_at
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::users)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewUser {
+    username: String,
+    password_hash: String,
+    is_admin: bool,
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl NewUser {
+    pub fn new(username: &str, password_hash: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            is_admin: false,
+            created_at: None,
+        }
+    }
+
+    pub fn set_is_admin(&mut self, is_admin: bool) {
+        self.is_admin = is_admin;
+    }
+
+    pub fn set_created_at(&mut self, created_at: chrono::NaiveDateTime) {
+        self.created_at = Some(created_at);
+    }
+}
+
+#[derive(
+    Debug, Clone, Queryable, Selectable, Serialize, PartialEq, Identifiable, QueryableByName,
+)]
+#[diesel(table_name = crate::schema::user_sessions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(belongs_to(User))]
+pub struct UserSession {
+    id: i32,
+    user_id: i32,
+    token_id: String,
+    expires_at: chrono::NaiveDateTime,
+    revoked_at: Option<chrono::NaiveDateTime>,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl UserSession {
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn get_user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    /// The `jti` claim of the session token.
+    pub fn get_token_id(&self) -> &str {
+        &self.token_id
+    }
+
+    pub fn get_expires_at(&self) -> &chrono::NaiveDateTime {
+        &self.expires_at
+    }
+
+    pub fn get_revoked_at(&self) -> &Option<chrono::NaiveDateTime> {
+        &self.revoked_at
+    }
+
+    pub fn get_created_at(&self) -> &chrono::NaiveDateTime {
+        &self.created_at
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::user_sessions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct NewUserSession {
+    user_id: i32,
+    token_id: String,
+    expires_at: chrono::NaiveDateTime,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl NewUserSession {
+    pub fn new(
+        user_id: i32,
+        token_id: &str,
+        created_at: chrono::NaiveDateTime,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            user_id,
+            token_id: token_id.to_string(),
+            expires_at,
+            created_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, JsonSchema)]
+pub struct UserCredentials {
+    username: String,
+    password: String,
+}
+
+impl UserCredentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn get_username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn get_password(&self) -> &str {
+        &self.password
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, JsonSchema)]
+pub struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+impl ChangePassword {
+    pub fn new(current_password: &str, new_password: &str) -> Self {
+        Self {
+            current_password: current_password.to_string(),
+            new_password: new_password.to_string(),
+        }
+    }
+
+    pub fn get_current_password(&self) -> &str {
+        &self.current_password
+    }
+
+    pub fn get_new_password(&self) -> &str {
+        &self.new_password
+    }
+}
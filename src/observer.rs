@@ -71,7 +71,7 @@ impl Observer {
     }
 
     /// poll the observer's timer.
-    pub fn select_next_some(&mut self) -> SelectNextSome<Fuse<IntervalStream>> {
+    pub fn select_next_some(&mut self) -> SelectNextSome<'_, Fuse<IntervalStream>> {
         self.timer.select_next_some()
     }
 
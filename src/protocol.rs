@@ -0,0 +1,855 @@
+use crate::events::*;
+use crate::helper::DashboardMessageType;
+use crate::models::{
+    Actuator, ChangePassword, DebugScript, DryRunScript, GetScriptRuns, GetSensorReadings,
+    NewScript, NewScriptRule, NewScriptVariable, NewSensorType, Script, ScriptRule, ScriptRun,
+    ScriptVariable, Sensor, SensorRead, SensorReadingsPage, SensorReadsExport, SensorType,
+    SensorUnregister, UpdateActuatorName, UpdateScript, UpdateScriptRule, UpdateSensorName, User,
+    UserCredentials,
+};
+use crate::script_lexer::ScriptDiagnostic;
+use crate::script_parser::Variables;
+use crate::script_simulation::ScriptDryRun;
+use anyhow::{Error, Result};
+use axum::Json;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+pub const SOCKET_EVENTS_SCHEMA_PATH: &str = "/api/socket-events.schema.json";
+
+/// Reads the data of a dashboard request. Older dashboards send objects as a
+/// string of JSON and ids as bare numbers, so both are accepted.
+pub fn parse_request<T: DeserializeOwned>(payload: Value) -> Result<T> {
+    let payload = match payload {
+        Value::String(text) => match serde_json::from_value::<T>(Value::String(text.clone())) {
+            Ok(request) => return Ok(request),
+            Err(_) => serde_json::from_str::<Value>(&text).unwrap_or(Value::String(text)),
+        },
+        payload => payload,
+    };
+
+    serde_json::from_value::<T>(payload).map_err(|e| Error::msg(format!("Invalid request: {}", e)))
+}
+
+/// What every dashboard request is acknowledged with, when the client asked
+/// for an acknowledgement.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AckResult<T> {
+    ok: bool,
+    error: Option<String>,
+    data: Option<T>,
+}
+
+impl<T> AckResult<T> {
+    pub fn new(res: Result<T>) -> Self {
+        match res {
+            Ok(data) => AckResult {
+                ok: true,
+                error: None,
+                data: Some(data),
+            },
+            Err(e) => AckResult {
+                ok: false,
+                error: Some(format!("{:#}", e)),
+                data: None,
+            },
+        }
+    }
+
+    pub fn get_ok(&self) -> bool {
+        self.ok
+    }
+
+    pub fn get_error(&self) -> &Option<String> {
+        &self.error
+    }
+
+    pub fn get_data(&self) -> &Option<T> {
+        &self.data
+    }
+}
+
+//GENERIC
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct DashboardMessage {
+    message: String,
+    /// `info`, `success`, `warning` or `error`.
+    #[serde(rename = "type")]
+    message_type: String,
+}
+
+impl DashboardMessage {
+    pub fn new(message: String, message_type: &DashboardMessageType) -> Self {
+        DashboardMessage {
+            message,
+            message_type: message_type.get_class(),
+        }
+    }
+}
+
+//SENSORS
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllSensors {
+    sensors: Vec<Sensor>,
+}
+
+impl AllSensors {
+    pub fn new(sensors: Vec<Sensor>) -> Self {
+        AllSensors { sensors }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllLastSensorReadings {
+    sensor_reads: Vec<SensorRead>,
+}
+
+impl AllLastSensorReadings {
+    pub fn new(sensor_reads: Vec<SensorRead>) -> Self {
+        AllLastSensorReadings { sensor_reads }
+    }
+}
+
+/// Answer to a history request.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorReadingsResponse {
+    sensor_id: i32,
+    #[serde(flatten)]
+    page: SensorReadingsPage,
+}
+
+impl SensorReadingsResponse {
+    pub fn new(sensor_id: i32, page: SensorReadingsPage) -> Self {
+        SensorReadingsResponse { sensor_id, page }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorRegistered {
+    sensor_id: i32,
+    sensor_name: Option<String>,
+    sensor_ip_address: String,
+    sensor_port: i16,
+    sensor_type: String,
+    online: bool,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl SensorRegistered {
+    pub fn new(sensor: &Sensor) -> Self {
+        SensorRegistered {
+            sensor_id: sensor.get_id(),
+            sensor_name: sensor.get_name().clone(),
+            sensor_ip_address: sensor.get_ip_address().to_string(),
+            sensor_port: sensor.get_port(),
+            sensor_type: sensor.get_sensor_type().to_string(),
+            online: sensor.get_online(),
+            created_at: *sensor.get_created_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorUnregistered {
+    sensor_id: i32,
+}
+
+impl SensorUnregistered {
+    pub fn new(sensor_id: i32) -> Self {
+        SensorUnregistered { sensor_id }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorReadReceived {
+    id: i32,
+    sensor_id: i32,
+    sensor_value: Option<f64>,
+    unit: Option<String>,
+    raw_value: String,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl SensorReadReceived {
+    pub fn new(sensor_read: &SensorRead) -> Self {
+        SensorReadReceived {
+            id: sensor_read.get_id(),
+            sensor_id: sensor_read.get_sensor_id(),
+            sensor_value: sensor_read.get_sensor_value(),
+            unit: sensor_read.get_unit().clone(),
+            raw_value: sensor_read.get_raw_value().to_string(),
+            created_at: *sensor_read.get_created_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorRenamed {
+    sensor_id: i32,
+    sensor_name: Option<String>,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl SensorRenamed {
+    pub fn new(sensor: &Sensor) -> Self {
+        SensorRenamed {
+            sensor_id: sensor.get_id(),
+            sensor_name: sensor.get_name().clone(),
+            updated_at: *sensor.get_updated_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorOnlineChanged {
+    sensor_id: i32,
+    online: bool,
+    updated_at: chrono::NaiveDateTime,
+}
+
+impl SensorOnlineChanged {
+    pub fn new(sensor_id: i32, online: bool, updated_at: chrono::NaiveDateTime) -> Self {
+        SensorOnlineChanged {
+            sensor_id,
+            online,
+            updated_at,
+        }
+    }
+}
+
+//SENSOR READ EXPORTS
+
+/// Describes the binary attachment of the event, one piece of the file.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorReadsExportChunk {
+    export_id: Option<String>,
+    format: String,
+    index: usize,
+}
+
+impl SensorReadsExportChunk {
+    pub fn new(export_id: Option<String>, format: &str, index: usize) -> Self {
+        SensorReadsExportChunk {
+            export_id,
+            format: format.to_string(),
+            index,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorReadsExportFinished {
+    export_id: Option<String>,
+    rows: usize,
+    error: Option<String>,
+}
+
+impl SensorReadsExportFinished {
+    pub fn new(export_id: Option<String>, rows: usize, error: Option<String>) -> Self {
+        SensorReadsExportFinished {
+            export_id,
+            rows,
+            error,
+        }
+    }
+}
+
+//SENSOR TYPES
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllSensorTypes {
+    sensor_types: Vec<SensorType>,
+}
+
+impl AllSensorTypes {
+    pub fn new(sensor_types: Vec<SensorType>) -> Self {
+        AllSensorTypes { sensor_types }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorTypeSaved {
+    sensor_type: SensorType,
+}
+
+impl SensorTypeSaved {
+    pub fn new(sensor_type: SensorType) -> Self {
+        SensorTypeSaved { sensor_type }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct SensorTypeDeleted {
+    name: String,
+}
+
+impl SensorTypeDeleted {
+    pub fn new(name: String) -> Self {
+        SensorTypeDeleted { name }
+    }
+}
+
+//ACTUATORS
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllActuators {
+    actuators: Vec<Actuator>,
+}
+
+impl AllActuators {
+    pub fn new(actuators: Vec<Actuator>) -> Self {
+        AllActuators { actuators }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ActuatorRegistered {
+    actuator_id: i32,
+    actuator_name: Option<String>,
+    actuator_ip_address: String,
+    actuator_port: i16,
+    actuator_pulse: bool,
+    online: bool,
+    created_at: chrono::NaiveDateTime,
+}
+
+impl ActuatorRegistered {
+    pub fn new(actuator: &Actuator) -> Self {
+        ActuatorRegistered {
+            actuator_id: actuator.get_id(),
+            actuator_name: actuator.get_name().clone(),
+            actuator_ip_address: actuator.get_ip_address().to_string(),
+            actuator_port: actuator.get_port(),
+            actuator_pulse: actuator.get_pulse(),
+            online: actuator.get_online(),
+            created_at: *actuator.get_created_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ActuatorUnregistered {
+    actuator_id: i32,
+}
+
+impl ActuatorUnregistered {
+    pub fn new(actuator_id: i32) -> Self {
+        ActuatorUnregistered { actuator_id }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ActuatorRenamed {
+    actuator_id: i32,
+    actuator_name: Option<String>,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ActuatorRenamed {
+    pub fn new(actuator: &Actuator) -> Self {
+        ActuatorRenamed {
+            actuator_id: actuator.get_id(),
+            actuator_name: actuator.get_name().clone(),
+            updated_at: *actuator.get_updated_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ActuatorStateChanged {
+    actuator_id: i32,
+    actuator_state: bool,
+    updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ActuatorStateChanged {
+    pub fn new(actuator: &Actuator) -> Self {
+        ActuatorStateChanged {
+            actuator_id: actuator.get_id(),
+            actuator_state: actuator.get_state(),
+            updated_at: *actuator.get_updated_at(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ActuatorOnlineChanged {
+    actuator_id: i32,
+    online: bool,
+    updated_at: chrono::NaiveDateTime,
+}
+
+impl ActuatorOnlineChanged {
+    pub fn new(actuator_id: i32, online: bool, updated_at: chrono::NaiveDateTime) -> Self {
+        ActuatorOnlineChanged {
+            actuator_id,
+            online,
+            updated_at,
+        }
+    }
+}
+
+//SCRIPTS
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllScripts {
+    scripts_array: Vec<Script>,
+}
+
+impl AllScripts {
+    pub fn new(scripts_array: Vec<Script>) -> Self {
+        AllScripts { scripts_array }
+    }
+}
+
+/// A script that was saved, modified, (un)scheduled or deleted.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptChanged {
+    script: Script,
+}
+
+impl ScriptChanged {
+    pub fn new(script: Script) -> Self {
+        ScriptChanged { script }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptStatusChanged {
+    script_id: i32,
+    status: i32,
+}
+
+impl ScriptStatusChanged {
+    pub fn new(script_id: i32, status: i32) -> Self {
+        ScriptStatusChanged { script_id, status }
+    }
+}
+
+/// One page of a script's run history, newest first.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptRunsPage {
+    script_id: i32,
+    script_runs: Vec<ScriptRun>,
+    next_before_id: Option<i32>,
+}
+
+impl ScriptRunsPage {
+    pub fn new(script_id: i32, script_runs: Vec<ScriptRun>) -> Self {
+        let next_before_id = script_runs.last().map(|script_run| script_run.get_id());
+
+        ScriptRunsPage {
+            script_id,
+            script_runs,
+            next_before_id,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptDiagnostics {
+    diagnostics: Vec<ScriptDiagnostic>,
+}
+
+impl ScriptDiagnostics {
+    pub fn new(diagnostics: Vec<ScriptDiagnostic>) -> Self {
+        ScriptDiagnostics { diagnostics }
+    }
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct ScriptDryRunFinished {
+    dry_run: ScriptDryRun,
+}
+
+impl ScriptDryRunFinished {
+    pub fn new(dry_run: ScriptDryRun) -> Self {
+        ScriptDryRunFinished { dry_run }
+    }
+}
+
+/// Where a debugged script is paused, with its variables at that point.
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptDebugState {
+    script_id: i32,
+    line: usize,
+    column: usize,
+    variables: Variables,
+}
+
+impl ScriptDebugState {
+    pub fn new(script_id: i32, line: usize, column: usize, variables: Variables) -> Self {
+        ScriptDebugState {
+            script_id,
+            line,
+            column,
+            variables,
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+
+    pub fn get_variables(&self) -> &Variables {
+        &self.variables
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptDebugFinished {
+    script_id: i32,
+    error: Option<String>,
+}
+
+impl ScriptDebugFinished {
+    pub fn new(script_id: i32, error: Option<String>) -> Self {
+        ScriptDebugFinished { script_id, error }
+    }
+}
+
+//SCRIPT RULES
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllScriptRules {
+    script_rules: Vec<ScriptRule>,
+}
+
+impl AllScriptRules {
+    pub fn new(script_rules: Vec<ScriptRule>) -> Self {
+        AllScriptRules { script_rules }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptRuleChanged {
+    script_rule: ScriptRule,
+}
+
+impl ScriptRuleChanged {
+    pub fn new(script_rule: ScriptRule) -> Self {
+        ScriptRuleChanged { script_rule }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptRuleDeleted {
+    script_rule_id: i32,
+}
+
+impl ScriptRuleDeleted {
+    pub fn new(script_rule_id: i32) -> Self {
+        ScriptRuleDeleted { script_rule_id }
+    }
+}
+
+//SCRIPT VARIABLES
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllScriptVariables {
+    script_variables: Vec<ScriptVariable>,
+}
+
+impl AllScriptVariables {
+    pub fn new(script_variables: Vec<ScriptVariable>) -> Self {
+        AllScriptVariables { script_variables }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptVariableChanged {
+    script_variable: ScriptVariable,
+}
+
+impl ScriptVariableChanged {
+    pub fn new(script_variable: ScriptVariable) -> Self {
+        ScriptVariableChanged { script_variable }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct ScriptVariableDeleted {
+    name: String,
+}
+
+impl ScriptVariableDeleted {
+    pub fn new(name: String) -> Self {
+        ScriptVariableDeleted { name }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct AllUsers {
+    users: Vec<User>,
+}
+
+impl AllUsers {
+    pub fn new(users: Vec<User>) -> Self {
+        AllUsers { users }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct UserSaved {
+    user: User,
+}
+
+impl UserSaved {
+    pub fn new(user: User) -> Self {
+        UserSaved { user }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, JsonSchema)]
+pub struct UserDeleted {
+    id: i32,
+}
+
+impl UserDeleted {
+    pub fn new(id: i32) -> Self {
+        UserDeleted { id }
+    }
+}
+
+/// Collects the schema of every event, sharing the definitions of the
+/// models they carry.
+struct EventSchemas {
+    generator: SchemaGenerator,
+    events: Map<String, Value>,
+}
+
+impl EventSchemas {
+    fn new() -> Self {
+        EventSchemas {
+            generator: SchemaSettings::draft07().into_generator(),
+            events: Map::new(),
+        }
+    }
+
+    /// An event the dashboard sends with a `R`, acknowledged with an
+    /// `AckResult` of `D`.
+    fn request<R: JsonSchema, D: JsonSchema>(mut self, event: &str) -> Self {
+        let request = self.generator.subschema_for::<R>();
+        let ack = self.generator.subschema_for::<AckResult<D>>();
+
+        self.events.insert(
+            event.to_string(),
+            json!({
+                "direction": "client-to-server",
+                "request": request,
+                "ack": ack,
+            }),
+        );
+        self
+    }
+
+    /// An event the server sends with a `P`.
+    fn push<P: JsonSchema>(mut self, event: &str) -> Self {
+        let payload = self.generator.subschema_for::<P>();
+
+        self.events.insert(
+            event.to_string(),
+            json!({
+                "direction": "server-to-client",
+                "payload": payload,
+            }),
+        );
+        self
+    }
+
+    fn into_schema(mut self) -> Value {
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "homesoil Socket.IO events",
+            "description": "Every event of the dashboard socket. Requests are acknowledged with { ok, error, data } when the client asks for an ack; objects may also be sent as a string of JSON. Sockets connect with the session token of POST /api/v1/auth/login as { token } in the handshake auth.",
+            "events": self.events,
+            "definitions": self.generator.take_definitions(),
+        })
+    }
+}
+
+/// The JSON Schema of every Socket.IO event, by event name.
+pub fn socket_events_schema() -> Value {
+    EventSchemas::new()
+        //GENERIC
+        .push::<DashboardMessage>(MESSAGE_SENT_EVENT)
+        //SENSORS
+        .push::<AllSensors>(ALL_SENSORS_EVENT)
+        .push::<AllLastSensorReadings>(ALL_LAST_SENSOR_READINGS_EVENT)
+        .request::<GetSensorReadings, SensorReadingsResponse>(GET_SENSOR_READINGS_EVENT)
+        .push::<SensorReadingsResponse>(ALL_SENSOR_READINGS_EVENT)
+        .push::<SensorRegistered>(SENSOR_REGISTER_EVENT)
+        .push::<SensorUnregistered>(SENSOR_UNREGISTER_EVENT)
+        .push::<SensorReadReceived>(SENSOR_READ_EVENT)
+        .push::<SensorRenamed>(SENSOR_NAME_CHANGE_EVENT)
+        .push::<SensorOnlineChanged>(SENSOR_CHANGE_ONLINE_EVENT)
+        .request::<UpdateSensorName, SensorRenamed>(RENAME_SENSOR_EVENT)
+        .request::<SensorUnregister, SensorUnregistered>(REMOVE_SENSOR_EVENT)
+        //SENSOR READ EXPORTS
+        .request::<SensorReadsExport, ()>(EXPORT_SENSOR_READS_EVENT)
+        .push::<SensorReadsExportChunk>(SENSOR_READS_EXPORT_CHUNK_EVENT)
+        .push::<SensorReadsExportFinished>(SENSOR_READS_EXPORT_FINISHED_EVENT)
+        //SENSOR TYPES
+        .request::<(), AllSensorTypes>(GET_ALL_SENSOR_TYPES_EVENT)
+        .request::<NewSensorType, SensorTypeSaved>(SAVE_SENSOR_TYPE_EVENT)
+        .request::<String, SensorTypeDeleted>(REMOVE_SENSOR_TYPE_EVENT)
+        .push::<AllSensorTypes>(ALL_SENSOR_TYPES_EVENT)
+        .push::<SensorTypeSaved>(SENSOR_TYPE_SAVED_EVENT)
+        .push::<SensorTypeDeleted>(SENSOR_TYPE_DELETED_EVENT)
+        //ACTUATORS
+        .push::<AllActuators>(ALL_ACTUATORS_EVENT)
+        .push::<ActuatorRegistered>(ACTUATOR_REGISTER_EVENT)
+        .push::<ActuatorUnregistered>(ACTUATOR_UNREGISTER_EVENT)
+        .request::<i32, ActuatorStateChanged>(TOGGLE_ACTUATOR_EVENT)
+        .request::<i32, ActuatorStateChanged>(PULSE_ACTUATOR_EVENT)
+        .push::<ActuatorRenamed>(ACTUATOR_NAME_CHANGE_EVENT)
+        .push::<ActuatorStateChanged>(ACTUATOR_STATE_CHANGE_EVENT)
+        .push::<ActuatorOnlineChanged>(ACTUATOR_CHANGE_ONLINE_EVENT)
+        .request::<UpdateActuatorName, ActuatorRenamed>(RENAME_ACTUATOR_EVENT)
+        .request::<SensorUnregister, ActuatorUnregistered>(REMOVE_ACTUATOR_EVENT)
+        //SCRIPTS
+        .request::<(), AllScripts>(GET_ALL_SCRIPTS_EVENT)
+        .request::<i32, ()>(RUN_SCRIPT_EVENT)
+        .request::<i32, ()>(STOP_SCRIPT_EVENT)
+        .request::<DryRunScript, ()>(DRY_RUN_SCRIPT_EVENT)
+        .request::<DebugScript, ()>(DEBUG_SCRIPT_EVENT)
+        .request::<i32, ()>(DEBUG_STEP_EVENT)
+        .request::<i32, ()>(DEBUG_CONTINUE_EVENT)
+        .request::<i32, ScriptDebugState>(DEBUG_VARIABLES_EVENT)
+        .request::<NewScript, ScriptChanged>(ADD_SCRIPT_EVENT)
+        .request::<i32, ScriptChanged>(REMOVE_SCRIPT_EVENT)
+        .request::<UpdateScript, ScriptChanged>(MODIFY_SCRIPT_EVENT)
+        .request::<UpdateScript, ScriptChanged>(ADD_SCRIPT_SCHEDULE_EVENT)
+        .request::<UpdateScript, ScriptChanged>(REMOVE_SCRIPT_SCHEDULE_EVENT)
+        .push::<AllScripts>(ALL_SCRIPTS_EVENT)
+        .push::<ScriptChanged>(SCRIPT_SAVED_EVENT)
+        .push::<ScriptChanged>(SCRIPT_DELETED_EVENT)
+        .push::<ScriptChanged>(SCRIPT_MODIFIED_EVENT)
+        .push::<ScriptStatusChanged>(SCRIPT_STATUS_CHANGE_EVENT)
+        .push::<ScriptChanged>(SCRIPT_SCHEDULE_ADDED_EVENT)
+        .push::<ScriptChanged>(SCRIPT_SCHEDULE_REMOVED_EVENT)
+        .request::<GetScriptRuns, ScriptRunsPage>(GET_SCRIPT_RUNS_EVENT)
+        .push::<ScriptRunsPage>(SCRIPT_RUNS_EVENT)
+        .push::<ScriptDiagnostics>(SCRIPT_DIAGNOSTICS_EVENT)
+        .push::<ScriptDryRunFinished>(SCRIPT_DRY_RUN_EVENT)
+        .push::<ScriptDebugState>(SCRIPT_DEBUG_PAUSED_EVENT)
+        .push::<ScriptDebugState>(SCRIPT_DEBUG_VARIABLES_EVENT)
+        .push::<ScriptDebugFinished>(SCRIPT_DEBUG_FINISHED_EVENT)
+        //SCRIPT RULES
+        .request::<(), AllScriptRules>(GET_ALL_SCRIPT_RULES_EVENT)
+        .request::<NewScriptRule, ScriptRuleChanged>(ADD_SCRIPT_RULE_EVENT)
+        .request::<UpdateScriptRule, ScriptRuleChanged>(MODIFY_SCRIPT_RULE_EVENT)
+        .request::<i32, ScriptRuleDeleted>(REMOVE_SCRIPT_RULE_EVENT)
+        .push::<AllScriptRules>(ALL_SCRIPT_RULES_EVENT)
+        .push::<ScriptRuleChanged>(SCRIPT_RULE_SAVED_EVENT)
+        .push::<ScriptRuleChanged>(SCRIPT_RULE_MODIFIED_EVENT)
+        .push::<ScriptRuleDeleted>(SCRIPT_RULE_DELETED_EVENT)
+        //SCRIPT VARIABLES
+        .request::<(), AllScriptVariables>(GET_ALL_SCRIPT_VARIABLES_EVENT)
+        .request::<NewScriptVariable, ScriptVariableChanged>(SET_SCRIPT_VARIABLE_EVENT)
+        .request::<String, ScriptVariableDeleted>(REMOVE_SCRIPT_VARIABLE_EVENT)
+        .push::<AllScriptVariables>(ALL_SCRIPT_VARIABLES_EVENT)
+        .push::<ScriptVariableChanged>(SCRIPT_VARIABLE_CHANGED_EVENT)
+        .push::<ScriptVariableDeleted>(SCRIPT_VARIABLE_DELETED_EVENT)
+        //USERS
+        .request::<(), ()>(LOGOUT_EVENT)
+        .request::<(), AllUsers>(GET_ALL_USERS_EVENT)
+        .request::<UserCredentials, UserSaved>(ADD_USER_EVENT)
+        .request::<i32, UserDeleted>(REMOVE_USER_EVENT)
+        .request::<ChangePassword, UserSaved>(CHANGE_PASSWORD_EVENT)
+        .push::<AllUsers>(ALL_USERS_EVENT)
+        .push::<UserSaved>(USER_SAVED_EVENT)
+        .push::<UserDeleted>(USER_DELETED_EVENT)
+        .into_schema()
+}
+
+/// Serves the schema, without a token like the OpenAPI document.
+pub async fn socket_events_schema_handler() -> Json<Value> {
+    Json(socket_events_schema())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_parse_request_and_ack() {
+        let from_string =
+            parse_request::<UpdateSensorName>(json!("{\"id\": 3, \"name\": \"Greenhouse\"}"));
+        assert_eq!(from_string.unwrap().get_name(), "Greenhouse");
+
+        let from_object = parse_request::<UpdateSensorName>(json!({"id": 3, "name": "Shed"}));
+        assert_eq!(from_object.unwrap().get_id(), 3);
+
+        assert_eq!(parse_request::<i32>(json!(7)).unwrap(), 7);
+        assert_eq!(parse_request::<i32>(json!("7")).unwrap(), 7);
+        assert_eq!(parse_request::<String>(json!("42")).unwrap(), "42");
+
+        let invalid = parse_request::<UpdateSensorName>(json!("{\"id\": 3}"));
+        let ack = serde_json::to_value(AckResult::new(invalid.map(|_| ()))).unwrap();
+        assert_eq!(ack["ok"], false);
+        assert_eq!(ack["data"], Value::Null);
+        assert!(ack["error"]
+            .as_str()
+            .unwrap()
+            .contains("missing field `name`"));
+
+        let ack = serde_json::to_value(AckResult::new(Ok(ScriptRuleDeleted::new(5)))).unwrap();
+        assert_eq!(
+            ack,
+            json!({"ok": true, "error": null, "data": {"script_rule_id": 5}})
+        );
+    }
+
+    /// Every event constant must be described, and every event with a
+    /// handler must be described as a request.
+    #[test]
+    fn test_schema_covers_every_event() {
+        let schema = socket_events_schema();
+        let events = schema["events"].as_object().unwrap();
+        let source = include_str!("events.rs");
+
+        let constants = Regex::new(r#"pub const (\w+_EVENT): &str = "([^"]+)";"#).unwrap();
+        let mut names = std::collections::HashMap::new();
+        for captures in constants.captures_iter(source) {
+            assert!(
+                events.contains_key(&captures[2]),
+                "{} is not described",
+                &captures[2]
+            );
+            names.insert(captures[1].to_string(), captures[2].to_string());
+        }
+        assert_eq!(names.len(), events.len());
+
+        let handlers = Regex::new(r"socket\.on\(\s*(\w+_EVENT)").unwrap();
+        for captures in handlers.captures_iter(source) {
+            let event = &events[&names[&captures[1]]];
+            assert_eq!(event["direction"], "client-to-server", "{}", &captures[1]);
+        }
+
+        assert_eq!(
+            events[RENAME_SENSOR_EVENT]["ack"]["$ref"],
+            "#/definitions/AckResult_for_SensorRenamed"
+        );
+        let definitions = schema["definitions"].as_object().unwrap();
+        assert!(definitions["UpdateSensorName"]["required"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("name")));
+        assert!(definitions.contains_key("ScriptDiagnostic"));
+    }
+}
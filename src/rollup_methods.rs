@@ -0,0 +1,250 @@
+use crate::db::connect;
+use crate::schema::sensor_read_rollups;
+use anyhow::{Error, Result};
+use chrono::{Duration, NaiveDateTime};
+use diesel::dsl::max;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Text, Timestamp};
+use std::env;
+
+/// Resolutions sensor history is kept at, finest first. Every rollup tier is
+/// built from the one before it, so raw reads only have to outlive a
+/// 5-minute bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollupTier {
+    Raw,
+    FiveMinutes,
+    Hourly,
+    Daily,
+}
+
+pub const ROLLUP_TIERS: [RollupTier; 4] = [
+    RollupTier::Raw,
+    RollupTier::FiveMinutes,
+    RollupTier::Hourly,
+    RollupTier::Daily,
+];
+
+/// Retention below this is raised to it, so a tier is never pruned before
+/// the next one has summarized it.
+const MIN_RETENTION_DAYS: i64 = 2;
+
+impl RollupTier {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            RollupTier::Raw => "raw",
+            RollupTier::FiveMinutes => "5m",
+            RollupTier::Hourly => "1h",
+            RollupTier::Daily => "1d",
+        }
+    }
+
+    pub fn get_bucket_seconds(&self) -> i32 {
+        match self {
+            RollupTier::Raw => 0,
+            RollupTier::FiveMinutes => 300,
+            RollupTier::Hourly => 3600,
+            RollupTier::Daily => 86400,
+        }
+    }
+
+    /// Tier the rollup is built from, `None` for raw reads.
+    fn get_source(&self) -> Option<RollupTier> {
+        match self {
+            RollupTier::Raw => None,
+            RollupTier::FiveMinutes => Some(RollupTier::Raw),
+            RollupTier::Hourly => Some(RollupTier::FiveMinutes),
+            RollupTier::Daily => Some(RollupTier::Hourly),
+        }
+    }
+
+    /// Longest time range answered from this tier.
+    fn get_max_span(&self) -> Option<Duration> {
+        match self {
+            RollupTier::Raw => Some(Duration::days(1)),
+            RollupTier::FiveMinutes => Some(Duration::days(7)),
+            RollupTier::Hourly => Some(Duration::days(90)),
+            RollupTier::Daily => None,
+        }
+    }
+
+    fn get_retention_variable(&self) -> &'static str {
+        match self {
+            RollupTier::Raw => "SENSOR_READS_RETENTION_DAYS",
+            RollupTier::FiveMinutes => "SENSOR_ROLLUP_5M_RETENTION_DAYS",
+            RollupTier::Hourly => "SENSOR_ROLLUP_1H_RETENTION_DAYS",
+            RollupTier::Daily => "SENSOR_ROLLUP_1D_RETENTION_DAYS",
+        }
+    }
+
+    fn get_default_retention_days(&self) -> i64 {
+        match self {
+            RollupTier::Raw => 30,
+            RollupTier::FiveMinutes => 90,
+            RollupTier::Hourly => 730,
+            RollupTier::Daily => 0,
+        }
+    }
+
+    /// How long the tier is kept, from its environment variable in days.
+    /// `0` keeps it forever.
+    pub fn get_retention(&self) -> Option<Duration> {
+        let days = env::var(self.get_retention_variable())
+            .ok()
+            .and_then(|days| days.trim().parse::<i64>().ok())
+            .unwrap_or(self.get_default_retention_days());
+
+        match days {
+            days if days <= 0 => None,
+            days => Some(Duration::days(days.max(MIN_RETENTION_DAYS))),
+        }
+    }
+}
+
+/// The finest tier that covers `from`..`to` in few enough points and still
+/// holds data from `from`.
+pub fn choose_tier(from: NaiveDateTime, to: NaiveDateTime, now: NaiveDateTime) -> RollupTier {
+    let span = to - from;
+
+    ROLLUP_TIERS
+        .iter()
+        .find(|tier| {
+            let span_fits = tier.get_max_span().is_none_or(|max_span| span <= max_span);
+            let retained = tier
+                .get_retention()
+                .is_none_or(|retention| from >= now - retention);
+
+            span_fits && retained
+        })
+        .copied()
+        .unwrap_or(RollupTier::Daily)
+}
+
+/// Rounds a stored datetime down to the start of its bucket. Takes the
+/// bucket length in seconds as two binds.
+pub fn bucket_expression(column: &str) -> String {
+    format!(
+        "datetime((CAST(strftime('%s', {}) AS INTEGER) / ?) * ?, 'unixepoch')",
+        column
+    )
+}
+
+fn upsert_rollups(select: &str) -> String {
+    format!(
+        "INSERT INTO sensor_read_rollups (sensor_id, tier, bucket_start, min_value, max_value, avg_value, read_count)
+        {}
+        ON CONFLICT (sensor_id, tier, bucket_start) DO UPDATE SET
+            min_value = excluded.min_value,
+            max_value = excluded.max_value,
+            avg_value = excluded.avg_value,
+            read_count = excluded.read_count",
+        select
+    )
+}
+
+fn roll_up_tier(conn: &mut SqliteConnection, tier: RollupTier) -> Result<usize> {
+    let source = match tier.get_source() {
+        Some(source) => source,
+        None => return Ok(0),
+    };
+
+    // The newest bucket may have been partial last time, so it is rebuilt
+    // along with everything after it.
+    let watermark = sensor_read_rollups::table
+        .filter(sensor_read_rollups::tier.eq(tier.get_name()))
+        .select(max(sensor_read_rollups::bucket_start))
+        .first::<Option<NaiveDateTime>>(conn)?
+        .unwrap_or_default();
+
+    let res = match source {
+        RollupTier::Raw => sql_query(upsert_rollups(&format!(
+            "SELECT sensor_id, ?, {} AS bucket, MIN(sensor_value), MAX(sensor_value), AVG(sensor_value), COUNT(sensor_value)
+            FROM sensor_reads
+            WHERE sensor_value IS NOT NULL AND created_at >= ?
+            GROUP BY sensor_id, bucket",
+            bucket_expression("created_at")
+        )))
+        .bind::<Text, _>(tier.get_name())
+        .bind::<Integer, _>(tier.get_bucket_seconds())
+        .bind::<Integer, _>(tier.get_bucket_seconds())
+        .bind::<Timestamp, _>(watermark)
+        .execute(conn),
+        _ => sql_query(upsert_rollups(&format!(
+            "SELECT sensor_id, ?, {} AS bucket, MIN(min_value), MAX(max_value), SUM(avg_value * read_count) / SUM(read_count), SUM(read_count)
+            FROM sensor_read_rollups
+            WHERE tier = ? AND bucket_start >= ?
+            GROUP BY sensor_id, bucket",
+            bucket_expression("bucket_start")
+        )))
+        .bind::<Text, _>(tier.get_name())
+        .bind::<Integer, _>(tier.get_bucket_seconds())
+        .bind::<Integer, _>(tier.get_bucket_seconds())
+        .bind::<Text, _>(source.get_name())
+        .bind::<Timestamp, _>(watermark)
+        .execute(conn),
+    };
+
+    res.map_err(Error::from)
+}
+
+/// Brings every rollup tier up to date, finest first so each tier sees the
+/// buckets just written below it. Returns the number of buckets written.
+pub fn roll_up_sensor_reads() -> Result<usize> {
+    let conn = &mut connect()?;
+
+    conn.transaction(|conn| {
+        ROLLUP_TIERS
+            .iter()
+            .map(|tier| roll_up_tier(conn, *tier))
+            .sum::<Result<usize>>()
+    })
+}
+
+/// Deletes rollup buckets older than their tier's retention.
+pub fn delete_expired_rollups() -> Result<usize> {
+    let conn = &mut connect()?;
+
+    let now = chrono::Local::now().naive_local();
+    let mut deleted = 0;
+
+    for tier in ROLLUP_TIERS.iter().filter(|tier| **tier != RollupTier::Raw) {
+        if let Some(retention) = tier.get_retention() {
+            deleted += diesel::delete(
+                sensor_read_rollups::table
+                    .filter(sensor_read_rollups::tier.eq(tier.get_name()))
+                    .filter(sensor_read_rollups::bucket_start.lt(now - retention)),
+            )
+            .execute(conn)?;
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_choose_tier() {
+        let now =
+            NaiveDateTime::parse_from_str("2024-02-16 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let tier = |hours_ago: i64, hours: i64| {
+            let from = now - Duration::hours(hours_ago);
+            choose_tier(from, from + Duration::hours(hours), now)
+        };
+
+        assert_eq!(tier(6, 6), RollupTier::Raw);
+        assert_eq!(tier(72, 48), RollupTier::FiveMinutes);
+        assert_eq!(tier(30 * 24, 30 * 24), RollupTier::Hourly);
+        assert_eq!(tier(365 * 24, 365 * 24), RollupTier::Daily);
+
+        // A short range whose raw reads are gone is answered from the
+        // finest tier still holding it.
+        assert_eq!(tier(60 * 24, 2), RollupTier::FiveMinutes);
+        assert_eq!(tier(120 * 24, 2), RollupTier::Hourly);
+        assert_eq!(tier(1000 * 24, 2), RollupTier::Daily);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::condition_parser::parse_condition;
+use crate::db::connect;
+use crate::models::{NewScriptRule, ScriptRule, UpdateScriptRule};
+use crate::schema::script_rules;
+use crate::script_parser::parse_arguments;
+use anyhow::{Error, Result};
+use chrono::Local;
+use diesel::prelude::*;
+use serde_json::from_str;
+use std::collections::HashMap;
+
+/// Column defaults of `script_rules`, used when a new rule leaves them out.
+const DEFAULT_DEBOUNCE_READS: i32 = 1;
+const DEFAULT_COOLDOWN_SECONDS: i32 = 60;
+
+fn validate_rule(condition: &str, debounce_reads: i32, cooldown_seconds: i32) -> Result<()> {
+    if condition.trim().is_empty() {
+        return Err(Error::msg("Rule condition is required"));
+    }
+
+    parse_condition(parse_arguments(condition)?, &HashMap::new(), &Local::now())?;
+
+    if debounce_reads < 1 {
+        return Err(Error::msg("Debounce reads must be at least 1"));
+    }
+
+    if cooldown_seconds < 0 {
+        return Err(Error::msg("Cooldown seconds cannot be negative"));
+    }
+
+    Ok(())
+}
+
+pub fn get_script_rule(id: i32) -> Result<ScriptRule> {
+    let conn = &mut connect()?;
+
+    let rule = script_rules::table.find(id).first(conn)?;
+
+    Ok(rule)
+}
+
+pub fn get_script_rules() -> Result<Vec<ScriptRule>> {
+    let conn = &mut connect()?;
+
+    let rules = script_rules::table.load::<ScriptRule>(conn)?;
+
+    Ok(rules)
+}
+
+pub fn get_enabled_script_rules_for_sensor(sensor_id: i32) -> Result<Vec<ScriptRule>> {
+    let conn = &mut connect()?;
+
+    let rules = script_rules::table
+        .filter(script_rules::sensor_id.eq(sensor_id))
+        .filter(script_rules::enabled.eq(true))
+        .load::<ScriptRule>(conn)?;
+
+    Ok(rules)
+}
+
+pub fn save_new_script_rule(payload: String) -> Result<ScriptRule> {
+    let conn = &mut connect()?;
+
+    let mut rule = from_str::<NewScriptRule>(&payload)?;
+
+    validate_rule(
+        rule.get_condition(),
+        rule.get_debounce_reads().unwrap_or(DEFAULT_DEBOUNCE_READS),
+        rule.get_cooldown_seconds()
+            .unwrap_or(DEFAULT_COOLDOWN_SECONDS),
+    )?;
+
+    rule.set_created_at(chrono::Local::now().naive_local());
+
+    let new_rule = diesel::insert_into(script_rules::table)
+        .values(&rule)
+        .get_result(conn)?;
+
+    Ok(new_rule)
+}
+
+pub fn update_script_rule(payload: String) -> Result<ScriptRule> {
+    let conn = &mut connect()?;
+
+    let mut rule = from_str::<UpdateScriptRule>(&payload)?;
+
+    validate_rule(
+        rule.get_condition(),
+        rule.get_debounce_reads(),
+        rule.get_cooldown_seconds(),
+    )?;
+
+    rule.set_updated_at(chrono::Local::now().naive_local());
+
+    diesel::update(script_rules::table.find(rule.get_id()))
+        .set((
+            script_rules::sensor_id.eq(rule.get_sensor_id()),
+            script_rules::script_id.eq(rule.get_script_id()),
+            script_rules::condition.eq(rule.get_condition()),
+            script_rules::debounce_reads.eq(rule.get_debounce_reads()),
+            script_rules::cooldown_seconds.eq(rule.get_cooldown_seconds()),
+            script_rules::enabled.eq(rule.get_enabled()),
+            script_rules::match_count.eq(0),
+            script_rules::updated_at.eq(rule.get_updated_at()),
+        ))
+        .execute(conn)?;
+
+    let updated_rule = script_rules::table.find(rule.get_id()).first(conn)?;
+
+    Ok(updated_rule)
+}
+
+pub fn delete_script_rule(id: i32) -> Result<()> {
+    let conn = &mut connect()?;
+
+    diesel::delete(script_rules::table.find(id)).execute(conn)?;
+
+    Ok(())
+}
+
+pub fn update_script_rule_match(
+    id: i32,
+    match_count: i32,
+    last_triggered_at: Option<chrono::NaiveDateTime>,
+) -> Result<()> {
+    let conn = &mut connect()?;
+
+    match last_triggered_at {
+        Some(last_triggered_at) => diesel::update(script_rules::table.find(id))
+            .set((
+                script_rules::match_count.eq(match_count),
+                script_rules::last_triggered_at.eq(last_triggered_at),
+            ))
+            .execute(conn)?,
+        None => diesel::update(script_rules::table.find(id))
+            .set(script_rules::match_count.eq(match_count))
+            .execute(conn)?,
+    };
+
+    Ok(())
+}
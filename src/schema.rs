@@ -14,6 +14,45 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    script_rules (id) {
+        id -> Integer,
+        sensor_id -> Integer,
+        script_id -> Integer,
+        condition -> Text,
+        debounce_reads -> Integer,
+        cooldown_seconds -> Integer,
+        enabled -> Bool,
+        match_count -> Integer,
+        last_triggered_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    script_runs (id) {
+        id -> Integer,
+        script_id -> Integer,
+        trigger_source -> Text,
+        outcome -> Text,
+        error -> Nullable<Text>,
+        output -> Text,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    script_variables (id) {
+        id -> Integer,
+        name -> Text,
+        value -> Text,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     scripts (id) {
         id -> Integer,
@@ -26,11 +65,41 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sensor_read_rollups (id) {
+        id -> Integer,
+        sensor_id -> Integer,
+        tier -> Text,
+        bucket_start -> Timestamp,
+        min_value -> Double,
+        max_value -> Double,
+        avg_value -> Double,
+        read_count -> Integer,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     sensor_reads (id) {
         id -> Integer,
         sensor_id -> Integer,
-        sensor_value -> Text,
+        sensor_value -> Nullable<Double>,
+        unit -> Nullable<Text>,
+        raw_value -> Text,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    sensor_types (id) {
+        id -> Integer,
+        name -> Text,
+        display_name -> Text,
+        unit -> Nullable<Text>,
+        min_value -> Nullable<Double>,
+        max_value -> Nullable<Double>,
+        decimal_places -> Nullable<Integer>,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
     }
@@ -49,11 +118,45 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_sessions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        token_id -> Text,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Integer,
+        username -> Text,
+        password_hash -> Text,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+        is_admin -> Bool,
+    }
+}
+
+diesel::joinable!(script_rules -> scripts (script_id));
+diesel::joinable!(script_rules -> sensors (sensor_id));
+diesel::joinable!(script_runs -> scripts (script_id));
+diesel::joinable!(sensor_read_rollups -> sensors (sensor_id));
 diesel::joinable!(sensor_reads -> sensors (sensor_id));
+diesel::joinable!(user_sessions -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     actuators,
+    script_rules,
+    script_runs,
+    script_variables,
     scripts,
+    sensor_read_rollups,
     sensor_reads,
+    sensor_types,
     sensors,
+    user_sessions,
+    users,
 );
@@ -0,0 +1,185 @@
+use crate::events::SCRIPT_DEBUG_PAUSED_EVENT;
+use crate::protocol::ScriptDebugState;
+use crate::script_lexer::Span;
+use crate::script_parser::{ScriptContext, Variables};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tokio::select;
+use tokio::sync::Notify;
+
+struct PausedState {
+    span: Span,
+    variables: Variables,
+}
+
+/// Breakpoints and stepping state of a script started from the dashboard
+/// debugger. The interpreter asks it before every node whether to pause.
+pub struct ScriptDebugger {
+    script_id: i32,
+    breakpoints: HashSet<usize>,
+    stepping: Mutex<bool>,
+    paused: Mutex<Option<PausedState>>,
+    resume: Notify,
+}
+
+impl ScriptDebugger {
+    /// Debug runs stop on the first line, so there is always a chance to
+    /// look around before anything reaches the devices.
+    pub fn new(script_id: i32, breakpoints: HashSet<usize>) -> Self {
+        ScriptDebugger {
+            script_id,
+            breakpoints,
+            stepping: Mutex::new(true),
+            paused: Mutex::new(None),
+            resume: Notify::new(),
+        }
+    }
+
+    pub fn get_script_id(&self) -> i32 {
+        self.script_id
+    }
+
+    /// Waits for `step` or `continue_run` when stepping or when the node is
+    /// on a breakpoint. Returns false when the run was cancelled meanwhile.
+    pub async fn pause(&self, span: Span, variables: &Variables, context: &ScriptContext) -> bool {
+        if !*self.stepping.lock().unwrap() && !self.breakpoints.contains(&span.get_line()) {
+            return true;
+        }
+
+        *self.paused.lock().unwrap() = Some(PausedState {
+            span,
+            variables: variables.clone(),
+        });
+
+        match context.get_target().emit(
+            SCRIPT_DEBUG_PAUSED_EVENT,
+            ScriptDebugState::new(
+                self.script_id,
+                span.get_line(),
+                span.get_column(),
+                variables.clone(),
+            ),
+        ) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error sending debugger state to dashboard: {:?}", e);
+            }
+        };
+
+        let resumed = select! {
+            _ = self.resume.notified() => true,
+            _ = context.get_cancel_token().cancelled() => false,
+        };
+
+        *self.paused.lock().unwrap() = None;
+
+        resumed
+    }
+
+    /// Runs the paused node and stops again at the next one.
+    pub fn step(&self) -> bool {
+        self.resume_with(true)
+    }
+
+    /// Runs until the next breakpoint or the end of the script.
+    pub fn continue_run(&self) -> bool {
+        self.resume_with(false)
+    }
+
+    fn resume_with(&self, stepping: bool) -> bool {
+        if self.paused.lock().unwrap().take().is_none() {
+            return false;
+        }
+
+        *self.stepping.lock().unwrap() = stepping;
+        self.resume.notify_one();
+
+        true
+    }
+
+    /// Position and variables of the node the run is paused on.
+    pub fn get_paused_state(&self) -> Option<ScriptDebugState> {
+        self.paused.lock().unwrap().as_ref().map(|paused| {
+            ScriptDebugState::new(
+                self.script_id,
+                paused.span.get_line(),
+                paused.span.get_column(),
+                paused.variables.clone(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::helper::DashboardTarget;
+    use crate::script_parser::Script;
+    use serde_json::{json, Value};
+    use socketioxide::extract::SocketRef;
+    use socketioxide::SocketIo;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::sleep;
+    use tokio_util::sync::CancellationToken;
+
+    async fn wait_until_paused(debugger: &ScriptDebugger) -> Value {
+        for _ in 0..100 {
+            if let Some(paused_state) = debugger.get_paused_state() {
+                return serde_json::to_value(paused_state).unwrap();
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+
+        panic!("debugger did not pause");
+    }
+
+    #[tokio::test]
+    async fn test_step_and_continue() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let debugger = Arc::new(ScriptDebugger::new(0, HashSet::from([5])));
+
+        let mut context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+        context.set_debugger(debugger.clone());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+RUN
+    SET $a 1
+    ADD $a 1
+    SET $b = $a * 10
+    SEND_TO_DASHBOARD "$b"
+STOP
+"#,
+        )
+        .unwrap();
+
+        let run = tokio::spawn(async move {
+            let res = script.run(&context).await;
+            (res.is_ok(), context.get_output())
+        });
+
+        let paused_state = wait_until_paused(&debugger).await;
+        assert_eq!(paused_state["line"], 3);
+        assert_eq!(paused_state["variables"], json!({}));
+
+        assert!(debugger.continue_run());
+
+        let paused_state = wait_until_paused(&debugger).await;
+        assert_eq!(paused_state["line"], 5);
+        assert_eq!(paused_state["variables"], json!({ "$a": 2 }));
+
+        assert!(debugger.step());
+
+        let paused_state = wait_until_paused(&debugger).await;
+        assert_eq!(paused_state["line"], 6);
+        assert_eq!(paused_state["variables"], json!({ "$a": 2, "$b": 20 }));
+
+        assert!(debugger.continue_run());
+        assert_eq!(run.await.unwrap(), (true, vec!["20".to_string()]));
+        assert!(!debugger.step());
+    }
+}
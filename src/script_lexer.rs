@@ -0,0 +1,461 @@
+use anyhow::{Error, Result};
+use chrono::NaiveTime;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+const TWO_CHAR_SYMBOLS: [&str; 6] = ["==", "!=", "<=", ">=", "&&", "||"];
+const ONE_CHAR_SYMBOLS: &str = "()[]{},<>=+-*/%!";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    line: usize,
+    column: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize) -> Self {
+        Span { line, column }
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A problem found at a position in a script. It is the error type of the
+/// tokenizer and the parser, so callers can report where it happened.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema, JsonSchema)]
+pub struct ScriptDiagnostic {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl ScriptDiagnostic {
+    pub fn new(span: Span, message: String) -> Self {
+        ScriptDiagnostic {
+            line: span.get_line(),
+            column: span.get_column(),
+            message,
+        }
+    }
+
+    pub fn get_span(&self) -> Span {
+        Span::new(self.line, self.column)
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ScriptDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.get_span(), self.message)
+    }
+}
+
+impl std::error::Error for ScriptDiagnostic {}
+
+pub fn located_error(span: Span, message: String) -> Error {
+    Error::new(ScriptDiagnostic::new(span, message))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Word(String),
+    Variable(String),
+    String(String),
+    Number(String),
+    Symbol(String),
+    Newline,
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(s) | TokenKind::Variable(s) | TokenKind::Number(s) => {
+                write!(f, "{}", s)
+            }
+            TokenKind::Symbol(s) => write!(f, "'{}'", s),
+            TokenKind::String(s) => write!(f, "\"{}\"", s),
+            TokenKind::Newline => write!(f, "end of line"),
+            TokenKind::Eof => write!(f, "end of script"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Token { kind, span }
+    }
+
+    pub fn get_kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
+
+    pub fn is_word(&self, word: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(w) if w == word)
+    }
+
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(&self.kind, TokenKind::Symbol(s) if s == symbol)
+    }
+
+    pub fn is_end_of_line(&self) -> bool {
+        matches!(self.kind, TokenKind::Newline | TokenKind::Eof)
+    }
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+    tokens: Vec<Token>,
+    after_whitespace: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(code: &'a str) -> Self {
+        Lexer {
+            chars: code.chars().peekable(),
+            line: 1,
+            column: 1,
+            tokens: Vec::new(),
+            after_whitespace: false,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.line, self.column)
+    }
+
+    fn push(&mut self, kind: TokenKind, span: Span) {
+        self.tokens.push(Token::new(kind, span));
+        self.after_whitespace = false;
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+
+            s.push(c);
+            self.bump();
+        }
+
+        s
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+
+            self.bump();
+        }
+    }
+
+    /// A `-` directly followed by a digit is a negative number unless it
+    /// follows something that can be subtracted from. After a value it only
+    /// starts a number when spaced like an argument: `ADD $a -1`, whereas
+    /// `$a - 1` and `$a-1` subtract.
+    fn minus_starts_number(&self) -> bool {
+        match self.tokens.last().map(|token| token.get_kind()) {
+            Some(TokenKind::Number(_)) | Some(TokenKind::Variable(_)) => self.after_whitespace,
+            Some(TokenKind::String(_)) => self.after_whitespace,
+            Some(TokenKind::Symbol(s)) => s != ")" && s != "]",
+            _ => true,
+        }
+    }
+
+    fn lex_string(&mut self, span: Span) -> Result<()> {
+        self.bump();
+
+        let mut s = String::new();
+
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => return Err(located_error(span, "unterminated string".to_string())),
+                },
+                Some('\n') | None => {
+                    return Err(located_error(span, "unterminated string".to_string()))
+                }
+                Some(c) => s.push(c),
+            }
+        }
+
+        self.push(TokenKind::String(s), span);
+
+        Ok(())
+    }
+
+    fn lex_number(&mut self, span: Span) -> Result<()> {
+        let mut number = String::new();
+
+        if self.peek() == Some('-') {
+            number.push('-');
+            self.bump();
+        }
+
+        number.push_str(&self.take_while(|c| c.is_ascii_digit()));
+
+        if !number.starts_with('-')
+            && self.peek() == Some(':')
+            && self.peek_second().is_some_and(|c| c.is_ascii_digit())
+        {
+            return self.lex_time(number, span);
+        }
+
+        if self.peek() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            number.push('.');
+            number.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        self.push(TokenKind::Number(number), span);
+
+        Ok(())
+    }
+
+    /// `06:00` or `06:00:30` is a time of day. It is kept as a string, the
+    /// same as `$time`, so the two compare as times.
+    fn lex_time(&mut self, hours: String, span: Span) -> Result<()> {
+        let mut time = hours;
+
+        while self.peek() == Some(':') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            time.push(':');
+            time.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        let valid = time.split(':').count() <= 3
+            && time.split(':').skip(1).all(|part| part.len() == 2)
+            && ["%H:%M", "%H:%M:%S"]
+                .iter()
+                .any(|format| NaiveTime::parse_from_str(&time, format).is_ok());
+
+        if !valid {
+            return Err(located_error(span, format!("invalid time '{}'", time)));
+        }
+
+        self.push(TokenKind::String(time), span);
+
+        Ok(())
+    }
+
+    fn lex_symbol(&mut self, c: char, span: Span) -> Result<()> {
+        if let Some(next) = self.peek_second() {
+            let pair: String = [c, next].iter().collect();
+
+            if TWO_CHAR_SYMBOLS.contains(&pair.as_str()) {
+                self.bump();
+                self.bump();
+                self.push(TokenKind::Symbol(pair), span);
+                return Ok(());
+            }
+        }
+
+        if ONE_CHAR_SYMBOLS.contains(c) {
+            self.bump();
+            self.push(TokenKind::Symbol(c.to_string()), span);
+            return Ok(());
+        }
+
+        Err(located_error(span, format!("unexpected character '{}'", c)))
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>> {
+        while let Some(c) = self.peek() {
+            let span = self.span();
+
+            match c {
+                '\n' => {
+                    self.bump();
+                    self.push(TokenKind::Newline, span);
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                    self.after_whitespace = true;
+                }
+                '#' => self.skip_comment(),
+                '/' if self.peek_second() == Some('/') => self.skip_comment(),
+                '"' => self.lex_string(span)?,
+                '$' => {
+                    self.bump();
+
+                    let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+                    if name.is_empty() {
+                        return Err(located_error(
+                            span,
+                            "expected a variable name after '$'".to_string(),
+                        ));
+                    }
+
+                    self.push(TokenKind::Variable(format!("${}", name)), span);
+                }
+                c if c.is_ascii_digit() => self.lex_number(span)?,
+                '-' if self.peek_second().is_some_and(|c| c.is_ascii_digit())
+                    && self.minus_starts_number() =>
+                {
+                    self.lex_number(span)?
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let word = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                    self.push(TokenKind::Word(word), span);
+                }
+                c => self.lex_symbol(c, span)?,
+            }
+        }
+
+        let span = self.span();
+        self.push(TokenKind::Eof, span);
+
+        Ok(self.tokens)
+    }
+}
+
+/// Splits script source into tokens. Newlines are kept because statements are
+/// line based; comments (`#` or `//` to the end of the line) are dropped.
+pub fn tokenize(code: &str) -> Result<Vec<Token>> {
+    Lexer::new(code).tokenize()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(code: &str) -> Vec<TokenKind> {
+        tokenize(code)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.get_kind().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_statement() {
+        assert_eq!(
+            kinds("IF ($a>=-1.5) && $b != \"hello world\" THEN # comment\nEND"),
+            vec![
+                TokenKind::Word("IF".to_string()),
+                TokenKind::Symbol("(".to_string()),
+                TokenKind::Variable("$a".to_string()),
+                TokenKind::Symbol(">=".to_string()),
+                TokenKind::Number("-1.5".to_string()),
+                TokenKind::Symbol(")".to_string()),
+                TokenKind::Symbol("&&".to_string()),
+                TokenKind::Variable("$b".to_string()),
+                TokenKind::Symbol("!=".to_string()),
+                TokenKind::String("hello world".to_string()),
+                TokenKind::Word("THEN".to_string()),
+                TokenKind::Newline,
+                TokenKind::Word("END".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+
+        assert_eq!(
+            kinds("$a - 1"),
+            vec![
+                TokenKind::Variable("$a".to_string()),
+                TokenKind::Symbol("-".to_string()),
+                TokenKind::Number("1".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+
+        assert_eq!(
+            kinds("ADD $a -1"),
+            vec![
+                TokenKind::Word("ADD".to_string()),
+                TokenKind::Variable("$a".to_string()),
+                TokenKind::Number("-1".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+
+        assert_eq!(
+            kinds("$time >= 6:30 && $time < 08:00:15"),
+            vec![
+                TokenKind::Variable("$time".to_string()),
+                TokenKind::Symbol(">=".to_string()),
+                TokenKind::String("6:30".to_string()),
+                TokenKind::Symbol("&&".to_string()),
+                TokenKind::Variable("$time".to_string()),
+                TokenKind::Symbol("<".to_string()),
+                TokenKind::String("08:00:15".to_string()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_spans_and_errors() {
+        let tokens = tokenize("RUN\n  DELAY 10").unwrap();
+        assert_eq!(tokens[2].get_span(), Span::new(2, 3));
+        assert_eq!(tokens[3].get_span(), Span::new(2, 9));
+
+        let error = tokenize("RUN\nSEND_TO_DASHBOARD \"oops\nSTOP").unwrap_err();
+        assert_eq!(error.to_string(), "line 2, column 19: unterminated string");
+
+        let error = tokenize("IF $time > 25:00 THEN").unwrap_err();
+        assert_eq!(error.to_string(), "line 1, column 12: invalid time '25:00'");
+    }
+}
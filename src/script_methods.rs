@@ -1,9 +1,113 @@
+use crate::actuator_methods::get_all_registered_actuators;
 use crate::db::connect;
-use crate::models::{NewScript, Script, UpdateScript};
-use crate::schema::scripts;
+use crate::models::{NewScript, NewScriptRun, Script, ScriptRun, UpdateScript};
+use crate::schema::{script_rules, script_runs, scripts};
+use crate::script_lexer::ScriptDiagnostic;
+use crate::script_parser::{Script as ParsedScript, ScriptReferences};
+use crate::script_runner::ScriptSchedule;
+use crate::sensor_methods::get_all_registered_sensors;
 use anyhow::{Error, Result};
 use diesel::prelude::*;
 use serde_json::from_str;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SCRIPT_STATUS_IDLE: i32 = 0;
+pub const SCRIPT_STATUS_RUNNING: i32 = 1;
+pub const SCRIPT_STATUS_CANCELLED: i32 = 2;
+pub const SCRIPT_STATUS_FAILED: i32 = -1;
+pub const SCRIPT_STATUS_ERROR: i32 = -2;
+
+pub const SCRIPT_TRIGGER_MANUAL: &str = "manual";
+pub const SCRIPT_TRIGGER_SCHEDULE: &str = "schedule";
+pub const SCRIPT_TRIGGER_EVENT: &str = "event";
+pub const SCRIPT_TRIGGER_DEBUG: &str = "debug";
+
+pub const SCRIPT_RUN_OUTCOME_RUNNING: &str = "running";
+pub const SCRIPT_RUN_OUTCOME_SUCCESS: &str = "success";
+pub const SCRIPT_RUN_OUTCOME_ERROR: &str = "error";
+pub const SCRIPT_RUN_OUTCOME_CANCELLED: &str = "cancelled";
+
+const SCRIPT_RUNS_PAGE_SIZE: i64 = 20;
+const SCRIPT_RUNS_MAX_PAGE_SIZE: i64 = 200;
+
+/// Set when a script is saved, changed or removed, so the scheduler reloads
+/// the schedules instead of reading them on every tick.
+static SCHEDULES_CHANGED: AtomicBool = AtomicBool::new(true);
+
+fn mark_schedules_changed() {
+    SCHEDULES_CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the schedules changed since the last call.
+pub fn take_schedules_changed() -> bool {
+    SCHEDULES_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+/// Every problem found in a script that was about to be saved.
+#[derive(Debug)]
+pub struct ScriptValidationError {
+    diagnostics: Vec<ScriptDiagnostic>,
+}
+
+impl ScriptValidationError {
+    pub fn get_diagnostics(&self) -> &Vec<ScriptDiagnostic> {
+        &self.diagnostics
+    }
+}
+
+impl fmt::Display for ScriptValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid script")?;
+
+        for diagnostic in &self.diagnostics {
+            write!(f, "\n{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScriptValidationError {}
+
+/// Parses and validates script code against the registered sensors,
+/// actuators and scripts. Problems come back as a `ScriptValidationError`.
+pub fn validate_script_code(code: &str) -> Result<()> {
+    let script = match ParsedScript::parse_code(0, code) {
+        Ok(script) => script,
+        Err(e) => {
+            return match e.downcast::<ScriptDiagnostic>() {
+                Ok(diagnostic) => Err(Error::new(ScriptValidationError {
+                    diagnostics: vec![diagnostic],
+                })),
+                Err(e) => Err(e),
+            };
+        }
+    };
+
+    let references = ScriptReferences::new(
+        get_all_registered_sensors()?
+            .iter()
+            .map(|sensor| sensor.get_id())
+            .collect(),
+        get_all_registered_actuators()?
+            .iter()
+            .map(|actuator| actuator.get_id())
+            .collect(),
+        get_scripts()?
+            .iter()
+            .map(|script| script.get_id())
+            .collect(),
+    );
+
+    let diagnostics = script.validate(&references);
+
+    if !diagnostics.is_empty() {
+        return Err(Error::new(ScriptValidationError { diagnostics }));
+    }
+
+    Ok(())
+}
 
 pub fn get_script(id: i32) -> Result<Script> {
     let conn = &mut connect()?;
@@ -21,6 +125,28 @@ pub fn get_scripts() -> Result<Vec<Script>> {
     Ok(scripts)
 }
 
+pub fn get_scheduled_scripts() -> Result<Vec<Script>> {
+    let conn = &mut connect()?;
+
+    let scripts = scripts::table
+        .filter(scripts::schedule.is_not_null())
+        .filter(scripts::schedule.ne(""))
+        .load::<Script>(conn)?;
+
+    Ok(scripts)
+}
+
+/// Rejects a schedule the scheduler could not read, so it fails when saved
+/// instead of on the scheduler's next reload. Empty schedules count as none.
+fn validate_schedule(schedule: &Option<String>) -> Result<()> {
+    match schedule {
+        Some(schedule) if !schedule.trim().is_empty() => {
+            ScriptSchedule::parse(schedule).map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
 pub fn save_new_script(payload: String) -> Result<Script> {
     let conn = &mut connect()?;
 
@@ -31,20 +157,31 @@ pub fn save_new_script(payload: String) -> Result<Script> {
         }
     };
 
+    validate_script_code(script.get_code())?;
+    validate_schedule(script.get_schedule())?;
+
     diesel::insert_into(scripts::table)
         .values(script)
         .execute(conn)?;
 
     let new_script = scripts::table.order(scripts::id.desc()).first(conn)?;
 
+    mark_schedules_changed();
+
     Ok(new_script)
 }
 
 pub fn delete_script(id: i32) -> Result<()> {
     let conn = &mut connect()?;
 
+    diesel::delete(script_runs::table.filter(script_runs::script_id.eq(id))).execute(conn)?;
+
+    diesel::delete(script_rules::table.filter(script_rules::script_id.eq(id))).execute(conn)?;
+
     diesel::delete(scripts::table.find(id)).execute(conn)?;
 
+    mark_schedules_changed();
+
     Ok(())
 }
 
@@ -53,6 +190,9 @@ pub fn update_script(payload: String) -> Result<Script> {
 
     let script = from_str::<UpdateScript>(&payload)?;
 
+    validate_script_code(script.get_code())?;
+    validate_schedule(script.get_schedule())?;
+
     diesel::update(scripts::table.find(script.get_id()))
         .set((
             scripts::title.eq(&script.get_title()),
@@ -64,5 +204,108 @@ pub fn update_script(payload: String) -> Result<Script> {
 
     let updated_script = scripts::table.find(script.get_id()).first(conn)?;
 
+    mark_schedules_changed();
+
     Ok(updated_script)
 }
+
+/// Sets or clears a script's schedule without touching its code.
+pub fn set_script_schedule(id: i32, schedule: Option<String>) -> Result<Script> {
+    let conn = &mut connect()?;
+
+    if let Some(schedule) = &schedule {
+        ScriptSchedule::parse(schedule)?;
+    }
+
+    diesel::update(scripts::table.find(id))
+        .set((
+            scripts::schedule.eq(schedule),
+            scripts::updated_at.eq(chrono::Local::now().naive_local()),
+        ))
+        .execute(conn)?;
+
+    let updated_script = scripts::table.find(id).first(conn)?;
+
+    mark_schedules_changed();
+
+    Ok(updated_script)
+}
+
+pub fn update_script_status(id: i32, status: i32) -> Result<Script> {
+    let conn = &mut connect()?;
+
+    diesel::update(scripts::table.find(id))
+        .set((
+            scripts::status.eq(status),
+            scripts::updated_at.eq(chrono::Local::now().naive_local()),
+        ))
+        .execute(conn)?;
+
+    let updated_script = scripts::table.find(id).first(conn)?;
+
+    Ok(updated_script)
+}
+
+pub fn start_script_run(script_id: i32, trigger_source: &str) -> Result<ScriptRun> {
+    let conn = &mut connect()?;
+
+    let new_script_run = NewScriptRun::new(script_id, trigger_source, SCRIPT_RUN_OUTCOME_RUNNING);
+
+    // The inserted row itself, as runs of the same script can start at once.
+    let script_run = diesel::insert_into(script_runs::table)
+        .values(&new_script_run)
+        .get_result(conn)?;
+
+    Ok(script_run)
+}
+
+pub fn finish_script_run(
+    id: i32,
+    outcome: &str,
+    error: Option<String>,
+    output: &[String],
+) -> Result<ScriptRun> {
+    let conn = &mut connect()?;
+
+    diesel::update(script_runs::table.find(id))
+        .set((
+            script_runs::outcome.eq(outcome),
+            script_runs::error.eq(error),
+            script_runs::output.eq(output.join("\n")),
+            script_runs::finished_at.eq(chrono::Local::now().naive_local()),
+        ))
+        .execute(conn)?;
+
+    let script_run = script_runs::table.find(id).first(conn)?;
+
+    Ok(script_run)
+}
+
+/// Newest runs first; pass the id of the last run received as `before_id`
+/// to get the next page.
+pub fn get_script_runs(
+    script_id: i32,
+    before_id: Option<i32>,
+    limit: Option<i64>,
+) -> Result<Vec<ScriptRun>> {
+    let conn = &mut connect()?;
+
+    let limit = limit
+        .unwrap_or(SCRIPT_RUNS_PAGE_SIZE)
+        .clamp(1, SCRIPT_RUNS_MAX_PAGE_SIZE);
+
+    let mut query = script_runs::table
+        .filter(script_runs::script_id.eq(script_id))
+        .into_boxed();
+
+    if let Some(before_id) = before_id {
+        query = query.filter(script_runs::id.lt(before_id));
+    }
+
+    let script_runs = query
+        .order(script_runs::id.desc())
+        .limit(limit)
+        .load::<ScriptRun>(conn)?;
+
+    Ok(script_runs)
+}
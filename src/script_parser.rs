@@ -1,17 +1,35 @@
 use crate::actuator_handlers::send_message_to_actuator;
 use crate::condition_parser::parse_condition;
-use crate::helper::{send_message_to_dashboard, DashboardMessageType};
+use crate::events::SCRIPT_VARIABLE_CHANGED_EVENT;
+use crate::expression_parser::{
+    apply_function, parse_expression, ADD, DIVIDE, FUNCTION_AVG, FUNCTION_MAX, FUNCTION_MIN,
+    MODULO, MULTIPLY, SUBTRACT,
+};
+use crate::helper::{DashboardMessageType, DashboardTarget};
+use crate::models::SensorRead;
+use crate::protocol::ScriptVariableChanged;
+use crate::script_debugger::ScriptDebugger;
+use crate::script_lexer::{located_error, tokenize, ScriptDiagnostic, Span, Token, TokenKind};
 use crate::script_methods::get_script;
+use crate::script_simulation::ScriptSimulation;
+use crate::script_time::get_time_variable;
 use crate::sensor_handlers::send_message_to_sensor;
+use crate::sensor_methods::{get_last_sensor_read, get_sensor_values_since, parse_sensor_value};
+use crate::variable_methods::{get_script_variable, set_script_variable};
 use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Local};
 use regex::Regex;
-use socketioxide::extract::SocketRef;
-use std::collections::HashMap;
-
-#[cfg(windows)]
-const COMMAND_END: &str = "\r\n";
-#[cfg(not(windows))]
-const COMMAND_END: &str = "\n";
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::select;
+use tokio::task::{spawn_blocking, yield_now};
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
 type Command = &'static str;
 
@@ -21,9 +39,18 @@ const COMMAND_PULSE_ACTUATOR: Command = "PULSE";
 const COMMAND_READ_SENSOR: Command = "READ";
 const COMMAND_SEND_MESSAGE_TO_DASHBOARD: Command = "SEND_TO_DASHBOARD";
 
+const COMMAND_READ_LAST_SENSOR_VALUE: Command = "READ_LAST";
+const COMMAND_READ_SENSOR_AVERAGE: Command = "READ_AVG";
+const COMMAND_READ_SENSOR_MINIMUM: Command = "READ_MIN";
+const COMMAND_READ_SENSOR_MAXIMUM: Command = "READ_MAX";
+const COMMAND_READ_SENSOR_COUNT: Command = "READ_COUNT";
+
 const COMMAND_SET_VARIABLE: Command = "SET";
 const COMMAND_UNSET_VARIABLE: Command = "UNSET";
 
+const COMMAND_GET_GLOBAL_VARIABLE: Command = "GET_GLOBAL";
+const COMMAND_SET_GLOBAL_VARIABLE: Command = "SET_GLOBAL";
+
 const COMMAND_ADD_TO_VARIABLE: Command = "ADD";
 const COMMAND_SUBTRACT_FROM_VARIABLE: Command = "SUBTRACT";
 const COMMAND_MULTIPLY_VARIABLE: Command = "MULTIPLY";
@@ -32,9 +59,16 @@ const COMMAND_MODULO_VARIABLE: Command = "MODULO";
 
 const COMMAND_DELAY: Command = "DELAY";
 
+const COMMAND_CALL_FUNCTION: Command = "CALL";
+const COMMAND_CALL_SCRIPT: Command = "CALL_SCRIPT";
+const COMMAND_RETURN: Command = "RETURN";
+
+const CALL_RESULT_INTO: &str = "INTO";
+
 type Instruction = &'static str;
 
 const INSTRUCTION_IF: Instruction = "IF";
+const INSTRUCTION_ELSE: Instruction = "ELSE";
 const INSTRUCTION_LOOP: Instruction = "LOOP";
 const INSTRUCTION_WHILE_LOOP: Instruction = "WHILE";
 
@@ -47,81 +81,23 @@ const INSTRUCTION_BLOCK_END: Instruction = "END";
 const MAIN_BLOCK_START: Instruction = "RUN";
 const MAIN_BLOCK_END: Instruction = "STOP";
 
-fn operation_to_numeric_variable_value(
-    variable: &Value,
-    value: f64,
-    operation: Command,
-) -> Result<f64> {
-    if operation == COMMAND_ADD_TO_VARIABLE {
-        let v = match variable {
-            Value::Int32(s) => *s as f64 + value,
-            Value::Int64(s) => *s as f64 + value,
-            Value::Float32(s) => *s as f64 + value,
-            Value::Float64(s) => *s + value,
-            _ => {
-                return Err(Error::msg("Invalid variable value".to_string()));
-            }
-        };
-
-        Ok(v)
-    } else if operation == COMMAND_SUBTRACT_FROM_VARIABLE {
-        let v = match variable {
-            Value::Int32(s) => *s as f64 - value,
-            Value::Int64(s) => *s as f64 - value,
-            Value::Float32(s) => *s as f64 - value,
-            Value::Float64(s) => *s - value,
-            _ => {
-                return Err(Error::msg("Invalid variable value".to_string()));
-            }
-        };
-
-        Ok(v)
-    } else if operation == COMMAND_MULTIPLY_VARIABLE {
-        let v = match variable {
-            Value::Int32(s) => *s as f64 * value,
-            Value::Int64(s) => *s as f64 * value,
-            Value::Float32(s) => *s as f64 * value,
-            Value::Float64(s) => *s * value,
-            _ => {
-                return Err(Error::msg("Invalid variable value".to_string()));
-            }
-        };
-
-        Ok(v)
-    } else if operation == COMMAND_DIVIDE_VARIABLE {
-        let v = match variable {
-            Value::Int32(s) => *s as f64 / value,
-            Value::Int64(s) => *s as f64 / value,
-            Value::Float32(s) => *s as f64 / value,
-            Value::Float64(s) => *s / value,
-            _ => {
-                return Err(Error::msg("Invalid variable value".to_string()));
-            }
-        };
+const FUNCTION_DEFINITION: Instruction = "FUNCTION";
 
-        Ok(v)
-    } else if operation == COMMAND_MODULO_VARIABLE {
-        let v = match variable {
-            Value::Int32(s) => *s as f64 % value,
-            Value::Int64(s) => *s as f64 % value,
-            Value::Float32(s) => *s as f64 % value,
-            Value::Float64(s) => *s % value,
-            _ => {
-                return Err(Error::msg("Invalid variable value".to_string()));
-            }
-        };
+/// Function calls and `CALL_SCRIPT` nest; a script that keeps calling itself
+/// fails once it is this deep instead of running forever.
+const MAX_CALL_DEPTH: usize = 16;
 
-        Ok(v)
-    } else {
-        Err(Error::msg("Invalid operation".to_string()))
-    }
-}
+const BLOCK_TERMINATORS: [Instruction; 3] =
+    [INSTRUCTION_BLOCK_END, INSTRUCTION_ELSE, MAIN_BLOCK_END];
 
+/// `ADD $x 5` and friends predate expressions and are kept as shorthands for
+/// `SET $x = $x + 5`.
 fn change_numeric_variable_value(
     args: &Args,
     variables: &Variables,
     operation: Command,
-) -> Result<(String, f64)> {
+    now: &DateTime<Local>,
+) -> Result<(String, Value)> {
     let variable_name = match args.first().unwrap() {
         Value::Variable(s) => s,
         _ => {
@@ -129,40 +105,27 @@ fn change_numeric_variable_value(
         }
     };
 
-    let variable_value = match args.get(1).unwrap() {
-        Value::Int32(s) => *s as f64,
-        Value::Int64(s) => *s as f64,
-        Value::Float32(s) => *s as f64,
-        Value::Float64(s) => *s,
-        Value::Variable(s) => match variables.get(s) {
-            Some(variable) => match variable {
-                Value::Int32(s) => *s as f64,
-                Value::Int64(s) => *s as f64,
-                Value::Float32(s) => *s as f64,
-                Value::Float64(s) => *s,
-                _ => {
-                    return Err(Error::msg("Invalid variable value".to_string()));
-                }
-            },
-            None => {
-                return Err(Error::msg("Variable not found".to_string()));
-            }
-        },
+    let operator = match operation {
+        COMMAND_ADD_TO_VARIABLE => ADD,
+        COMMAND_SUBTRACT_FROM_VARIABLE => SUBTRACT,
+        COMMAND_MULTIPLY_VARIABLE => MULTIPLY,
+        COMMAND_DIVIDE_VARIABLE => DIVIDE,
+        COMMAND_MODULO_VARIABLE => MODULO,
         _ => {
-            return Err(Error::msg("Invalid variable value".to_string()));
-        }
-    };
-
-    let variable = match variables.get(variable_name) {
-        Some(variable) => variable,
-        None => {
-            return Err(Error::msg("Variable not found".to_string()));
+            return Err(Error::msg("Invalid operation".to_string()));
         }
     };
 
-    let variable_value = operation_to_numeric_variable_value(variable, variable_value, operation)?;
+    let expression = parse_expression(vec![
+        args[0].clone(),
+        Value::String(operator.to_string()),
+        args[1].clone(),
+    ])?;
 
-    Ok((variable_name.to_string(), variable_value))
+    Ok((
+        variable_name.to_string(),
+        expression.evaluate(variables, now)?,
+    ))
 }
 
 fn save_variable(res: CommandFunctionResult, variables: &mut Variables) {
@@ -171,7 +134,11 @@ fn save_variable(res: CommandFunctionResult, variables: &mut Variables) {
     }
 }
 
-fn compute_message_with_variables(message: String, variables: &Variables) -> String {
+fn compute_message_with_variables(
+    message: String,
+    variables: &Variables,
+    now: &DateTime<Local>,
+) -> String {
     let regex = Regex::new(r"\$[a-zA-Z0-9_]+").unwrap();
 
     let mut final_string = String::from(message.as_str());
@@ -179,8 +146,8 @@ fn compute_message_with_variables(message: String, variables: &Variables) -> Str
     for variable_name in regex.find_iter(message.as_str()) {
         let variable_name = variable_name.as_str();
 
-        let variable_value = match variables.get(variable_name) {
-            Some(variable) => variable.to_string(variables),
+        let variable_value = match get_variable(variable_name, variables, now) {
+            Some(variable) => variable.to_string(variables, now),
             None => {
                 continue;
             }
@@ -194,475 +161,1119 @@ fn compute_message_with_variables(message: String, variables: &Variables) -> Str
 
 fn parse_command_function(command: Command) -> CommandFunction {
     match command {
-        COMMAND_ACTIVATE_ACTUATOR => Box::new(|args, _variables, _socket| {
-            println!("Activate actuator: {:?}", args);
+        COMMAND_ACTIVATE_ACTUATOR => |args, _variables, context| {
+            Box::pin(async move {
+                println!("Activate actuator: {:?}", args);
 
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            let actuator_id = match args.clone().unwrap()[0] {
-                Value::Int32(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                let actuator_id = match args.clone().unwrap()[0] {
+                    Value::Int32(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                    }
+                };
+
+                if let Some(res) = simulate_command(
+                    context,
+                    COMMAND_ACTIVATE_ACTUATOR,
+                    format!("actuator {}", actuator_id),
+                ) {
+                    return res;
                 }
-            };
 
-            match send_message_to_actuator(actuator_id, &"ON".to_string()) {
-                Ok(res) => CommandFunctionResult::Return(Value::String(res)),
-                Err(e) => CommandFunctionResult::Error(e.to_string()),
-            }
-        }),
-        COMMAND_DEACTIVATE_ACTUATOR => Box::new(|args, _variables, _socket| {
-            println!("Deactivate actuator: {:?}", args);
-
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match run_blocking(move || send_message_to_actuator(actuator_id, &"ON".to_string()))
+                    .await
+                {
+                    Ok(_) => CommandFunctionResult::Continue,
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
                 }
-            }
+            })
+        },
+        COMMAND_DEACTIVATE_ACTUATOR => |args, _variables, context| {
+            Box::pin(async move {
+                println!("Deactivate actuator: {:?}", args);
 
-            let actuator_id = match args.clone().unwrap()[0] {
-                Value::Int32(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            };
 
-            match send_message_to_actuator(actuator_id, &"OFF".to_string()) {
-                Ok(res) => CommandFunctionResult::Return(Value::String(res)),
-                Err(e) => CommandFunctionResult::Error(e.to_string()),
-            }
-        }),
-        COMMAND_PULSE_ACTUATOR => Box::new(|args, _variables, _socket| {
-            println!("Pulse actuator: {:?}", args);
-
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                let actuator_id = match args.clone().unwrap()[0] {
+                    Value::Int32(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                    }
+                };
+
+                if let Some(res) = simulate_command(
+                    context,
+                    COMMAND_DEACTIVATE_ACTUATOR,
+                    format!("actuator {}", actuator_id),
+                ) {
+                    return res;
                 }
-            }
 
-            let actuator_id = match args.clone().unwrap()[0] {
-                Value::Int32(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                match run_blocking(move || {
+                    send_message_to_actuator(actuator_id, &"OFF".to_string())
+                })
+                .await
+                {
+                    Ok(_) => CommandFunctionResult::Continue,
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
                 }
-            };
+            })
+        },
+        COMMAND_PULSE_ACTUATOR => |args, _variables, context| {
+            Box::pin(async move {
+                println!("Pulse actuator: {:?}", args);
 
-            match send_message_to_actuator(actuator_id, &"ON-PULSE".to_string()) {
-                Ok(res) => CommandFunctionResult::Return(Value::String(res)),
-                Err(e) => CommandFunctionResult::Error(e.to_string()),
-            }
-        }),
-        COMMAND_READ_SENSOR => Box::new(|args, _variables, _socket| {
-            println!("Read sensor: {:?}", args);
-
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            let sensor_id = match args.clone().unwrap()[0] {
-                Value::Int32(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                let actuator_id = match args.clone().unwrap()[0] {
+                    Value::Int32(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                    }
+                };
+
+                if let Some(res) = simulate_command(
+                    context,
+                    COMMAND_PULSE_ACTUATOR,
+                    format!("actuator {}", actuator_id),
+                ) {
+                    return res;
                 }
-            };
 
-            match send_message_to_sensor(sensor_id, &"READ".to_string()) {
-                Ok(res) => CommandFunctionResult::SaveVariable(
-                    "$sensor_id_".to_string() + sensor_id.to_string().as_str(),
-                    Value::String(res),
-                ),
-                Err(e) => CommandFunctionResult::Error(e.to_string()),
-            }
-        }),
-        COMMAND_SET_VARIABLE => Box::new(|args, _variables, _socket| {
-            println!("Set variable: {:?}", args);
-
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match run_blocking(move || {
+                    send_message_to_actuator(actuator_id, &"ON-PULSE".to_string())
+                })
+                .await
+                {
+                    Ok(_) => CommandFunctionResult::Continue,
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
                 }
-            }
-            let args = args.clone().unwrap();
+            })
+        },
+        COMMAND_READ_SENSOR => |args, _variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-            let variable_name = match args.first().unwrap() {
-                Value::Variable(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid variable name".to_string());
+                println!("Read sensor: {:?}", args);
+
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            };
 
-            let variable_value = args.get(1).unwrap().clone();
+                let sensor_id = match args.clone().unwrap()[0] {
+                    Value::Int32(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid actuator id".to_string());
+                    }
+                };
 
-            CommandFunctionResult::SaveVariable(variable_name.to_string(), variable_value)
-        }),
-        COMMAND_UNSET_VARIABLE => Box::new(|args, _variables, _socket| {
-            println!("Unset variable: {:?}", args);
+                if let Some(simulation) = context.get_simulation() {
+                    let sensor_value = match simulation.get_sensor_value(sensor_id) {
+                        Some(sensor_value) => Value::from_sensor_value(sensor_value),
+                        None => match run_blocking(move || get_last_sensor_read(sensor_id)).await {
+                            Ok(Some(sensor_read)) => Value::from_sensor_read(&sensor_read),
+                            Ok(None) => {
+                                return CommandFunctionResult::Error(format!(
+                                    "No value for sensor {} in dry run",
+                                    sensor_id
+                                ));
+                            }
+                            Err(e) => {
+                                return CommandFunctionResult::Error(e.to_string());
+                            }
+                        },
+                    };
+
+                    if let Err(e) = simulation.record(
+                        context.get_span(),
+                        COMMAND_READ_SENSOR,
+                        format!(
+                            "sensor {} = {}",
+                            sensor_id,
+                            sensor_value.to_string(&HashMap::new(), now)
+                        ),
+                    ) {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
 
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                    return CommandFunctionResult::SaveVariable(
+                        "$sensor_id_".to_string() + sensor_id.to_string().as_str(),
+                        sensor_value,
+                    );
                 }
-            }
-
-            let args = args.clone().unwrap();
 
-            let variable_name = match args.first().unwrap() {
-                Value::Variable(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid variable name".to_string());
+                match run_blocking(move || send_message_to_sensor(sensor_id, &"READ".to_string()))
+                    .await
+                {
+                    Ok(res) => CommandFunctionResult::SaveVariable(
+                        "$sensor_id_".to_string() + sensor_id.to_string().as_str(),
+                        Value::from_sensor_value(&res),
+                    ),
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
                 }
-            };
+            })
+        },
+        COMMAND_READ_LAST_SENSOR_VALUE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Read last sensor value: {:?}", args);
+
+                let sensor_id = match args.as_deref() {
+                    Some([sensor_id]) => match sensor_id.clone().resolve(variables, now) {
+                        Value::Int32(sensor_id) => sensor_id,
+                        _ => {
+                            return CommandFunctionResult::Error("Invalid sensor id".to_string());
+                        }
+                    },
+                    _ => {
+                        return CommandFunctionResult::Error(
+                            "Invalid number of arguments".to_string(),
+                        );
+                    }
+                };
 
-            CommandFunctionResult::SaveVariable(variable_name.to_string(), Value::None)
-        }),
-        COMMAND_ADD_TO_VARIABLE => Box::new(|args, variables, _socket| {
-            println!("Add to variable: {:?}", args);
+                let variable_name = "$sensor_id_".to_string() + sensor_id.to_string().as_str();
 
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                if let Some(sensor_value) = context
+                    .get_simulation()
+                    .and_then(|simulation| simulation.get_sensor_value(sensor_id))
+                {
+                    return CommandFunctionResult::SaveVariable(
+                        variable_name,
+                        Value::from_sensor_value(sensor_value),
+                    );
                 }
-            }
 
-            let args = args.clone().unwrap();
+                match run_blocking(move || get_last_sensor_read(sensor_id)).await {
+                    Ok(Some(sensor_read)) => CommandFunctionResult::SaveVariable(
+                        variable_name,
+                        Value::from_sensor_read(&sensor_read),
+                    ),
+                    Ok(None) => CommandFunctionResult::Error(format!(
+                        "No stored value for sensor {}",
+                        sensor_id
+                    )),
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
+                }
+            })
+        },
+        COMMAND_READ_SENSOR_AVERAGE => |args, variables, context| {
+            Box::pin(read_sensor_history(
+                args,
+                variables,
+                COMMAND_READ_SENSOR_AVERAGE,
+                context.get_now(),
+            ))
+        },
+        COMMAND_READ_SENSOR_MINIMUM => |args, variables, context| {
+            Box::pin(read_sensor_history(
+                args,
+                variables,
+                COMMAND_READ_SENSOR_MINIMUM,
+                context.get_now(),
+            ))
+        },
+        COMMAND_READ_SENSOR_MAXIMUM => |args, variables, context| {
+            Box::pin(read_sensor_history(
+                args,
+                variables,
+                COMMAND_READ_SENSOR_MAXIMUM,
+                context.get_now(),
+            ))
+        },
+        COMMAND_READ_SENSOR_COUNT => |args, variables, context| {
+            Box::pin(read_sensor_history(
+                args,
+                variables,
+                COMMAND_READ_SENSOR_COUNT,
+                context.get_now(),
+            ))
+        },
+        COMMAND_SET_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Set variable: {:?}", args);
+
+                if let Some((variable_name, expression)) = split_assignment(args) {
+                    let variable_value = match parse_expression(expression)
+                        .and_then(|e| e.evaluate(variables, now))
+                    {
+                        Ok(value) => value,
+                        Err(e) => {
+                            return CommandFunctionResult::Error(e.to_string());
+                        }
+                    };
+
+                    return CommandFunctionResult::SaveVariable(variable_name, variable_value);
+                }
 
-            let (variable_name, variable_value) =
-                match change_numeric_variable_value(&args, variables, COMMAND_ADD_TO_VARIABLE) {
-                    Ok(res) => res,
+                match args_required(args, 2) {
+                    Ok(_) => {}
                     Err(e) => {
                         return CommandFunctionResult::Error(e.to_string());
                     }
+                }
+                let args = args.clone().unwrap();
+
+                let variable_name = match args.first().unwrap() {
+                    Value::Variable(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid variable name".to_string());
+                    }
                 };
 
-            CommandFunctionResult::SaveVariable(
-                variable_name.to_string(),
-                Value::Float64(variable_value),
-            )
-        }),
-        COMMAND_SUBTRACT_FROM_VARIABLE => Box::new(|args, variables, _socket| {
-            println!("Subtract from variable: {:?}", args);
-
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
-                }
-            }
+                let variable_value = args.get(1).unwrap().clone();
 
-            let args = args.clone().unwrap();
+                CommandFunctionResult::SaveVariable(variable_name.to_string(), variable_value)
+            })
+        },
+        COMMAND_UNSET_VARIABLE => |args, _variables, _context| {
+            Box::pin(async move {
+                println!("Unset variable: {:?}", args);
 
-            let (variable_name, variable_value) = match change_numeric_variable_value(
-                &args,
-                variables,
-                COMMAND_SUBTRACT_FROM_VARIABLE,
-            ) {
-                Ok(res) => res,
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            };
 
-            CommandFunctionResult::SaveVariable(
-                variable_name.to_string(),
-                Value::Float64(variable_value),
-            )
-        }),
-        COMMAND_MULTIPLY_VARIABLE => Box::new(|args, variables, _socket| {
-            println!("Multiply variable: {:?}", args);
-
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
-                }
-            }
+                let args = args.clone().unwrap();
 
-            let args = args.clone().unwrap();
+                let variable_name = match args.first().unwrap() {
+                    Value::Variable(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid variable name".to_string());
+                    }
+                };
 
-            let (variable_name, variable_value) =
-                match change_numeric_variable_value(&args, variables, COMMAND_MULTIPLY_VARIABLE) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        return CommandFunctionResult::Error(e.to_string());
+                CommandFunctionResult::SaveVariable(variable_name.to_string(), Value::None)
+            })
+        },
+        COMMAND_GET_GLOBAL_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Get global variable: {:?}", args);
+
+                let (variable_name, default_value) = match args.as_deref() {
+                    Some([Value::Variable(name)]) => (name.to_string(), Value::None),
+                    Some([Value::Variable(name), default_value]) => (
+                        name.to_string(),
+                        default_value.clone().resolve(variables, now),
+                    ),
+                    Some([_]) | Some([_, _]) => {
+                        return CommandFunctionResult::Error("Invalid variable name".to_string());
+                    }
+                    _ => {
+                        return CommandFunctionResult::Error(
+                            "Invalid number of arguments".to_string(),
+                        );
                     }
                 };
 
-            CommandFunctionResult::SaveVariable(
-                variable_name.to_string(),
-                Value::Float64(variable_value),
-            )
-        }),
-        COMMAND_DIVIDE_VARIABLE => Box::new(|args, variables, _socket| {
-            println!("Divide variable: {:?}", args);
-
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                if let Some(value) = context
+                    .get_simulation()
+                    .and_then(|simulation| simulation.get_global(&variable_name))
+                {
+                    return CommandFunctionResult::SaveVariable(variable_name, value);
                 }
-            }
 
-            let args = args.clone().unwrap();
-
-            let (variable_name, variable_value) =
-                match change_numeric_variable_value(&args, variables, COMMAND_DIVIDE_VARIABLE) {
-                    Ok(res) => res,
+                let name = variable_name.clone();
+
+                let variable_value = match run_blocking(move || get_script_variable(&name)).await {
+                    Ok(Some(script_variable)) => {
+                        match serde_json::from_str(script_variable.get_value())
+                            .map_err(Error::from)
+                            .and_then(Value::from_json)
+                        {
+                            Ok(value) => value,
+                            Err(e) => {
+                                return CommandFunctionResult::Error(e.to_string());
+                            }
+                        }
+                    }
+                    Ok(None) => default_value,
                     Err(e) => {
                         return CommandFunctionResult::Error(e.to_string());
                     }
                 };
 
-            CommandFunctionResult::SaveVariable(
-                variable_name.to_string(),
-                Value::Float64(variable_value),
-            )
-        }),
-        COMMAND_MODULO_VARIABLE => Box::new(|args, variables, _socket| {
-            println!("Modulo variable: {:?}", args);
-
-            match args_required(args, 2) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
-                }
-            }
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_SET_GLOBAL_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-            let args = args.clone().unwrap();
+                println!("Set global variable: {:?}", args);
 
-            let (variable_name, variable_value) =
-                match change_numeric_variable_value(&args, variables, COMMAND_MODULO_VARIABLE) {
+                let (variable_name, variable_value) = match global_assignment(args, variables, now)
+                {
                     Ok(res) => res,
                     Err(e) => {
                         return CommandFunctionResult::Error(e.to_string());
                     }
                 };
 
-            CommandFunctionResult::SaveVariable(
-                variable_name.to_string(),
-                Value::Float64(variable_value),
-            )
-        }),
-        COMMAND_SEND_MESSAGE_TO_DASHBOARD => Box::new(|args, variables, socket| {
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
-                }
-            }
+                let json_value = match serde_json::to_string(&variable_value) {
+                    Ok(json_value) => json_value,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-            let args = args.clone().unwrap();
+                // A dry run keeps its globals to itself.
+                if let Some(simulation) = context.get_simulation() {
+                    if let Err(e) = simulation.record(
+                        context.get_span(),
+                        COMMAND_SET_GLOBAL_VARIABLE,
+                        format!("{} = {}", variable_name, json_value),
+                    ) {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
 
-            let message = args.first().unwrap().clone();
+                    simulation.set_global(&variable_name, variable_value.clone());
 
-            let message = match message {
-                Value::String(s) => s,
-                _ => {
-                    return CommandFunctionResult::Error("Invalid message".to_string());
+                    return CommandFunctionResult::SaveVariable(variable_name, variable_value);
                 }
-            };
 
-            let message = compute_message_with_variables(message, variables);
+                let name = variable_name.clone();
+
+                match run_blocking(move || set_script_variable(&name, &json_value)).await {
+                    Ok(script_variable) => {
+                        context.get_target().emit_to_all(
+                            SCRIPT_VARIABLE_CHANGED_EVENT,
+                            ScriptVariableChanged::new(script_variable),
+                        );
 
-            match send_message_to_dashboard(socket, message, DashboardMessageType::Info) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                        CommandFunctionResult::SaveVariable(variable_name, variable_value)
+                    }
+                    Err(e) => CommandFunctionResult::Error(e.to_string()),
                 }
-            }
+            })
+        },
+        COMMAND_ADD_TO_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Add to variable: {:?}", args);
 
-            CommandFunctionResult::Continue
-        }),
-        COMMAND_DELAY => Box::new(|args, variables, _socket| {
-            match args_required(args, 1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 2) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            let args = args.clone().unwrap();
+                let args = args.clone().unwrap();
 
-            let delay = args[0].to_string(variables).parse::<u64>().unwrap();
+                let (variable_name, variable_value) = match change_numeric_variable_value(
+                    &args,
+                    variables,
+                    COMMAND_ADD_TO_VARIABLE,
+                    now,
+                ) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-            std::thread::sleep(std::time::Duration::from_millis(delay));
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_SUBTRACT_FROM_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-            CommandFunctionResult::Continue
-        }),
-        _ => Box::new(|_args, _variables, _socket| {
-            CommandFunctionResult::Error("Unknown command".to_string())
-        }),
-    }
-}
+                println!("Subtract from variable: {:?}", args);
 
-fn parse_instruction_function(instruction: Instruction) -> InstructionFunction {
-    match instruction {
-        INSTRUCTION_IF => Box::new(|args, inner_executions, variables, socket| {
-            match args_required(args, -1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 2) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            let args = args.clone().unwrap();
+                let args = args.clone().unwrap();
 
-            let condition = parse_condition(args, variables);
+                let (variable_name, variable_value) = match change_numeric_variable_value(
+                    &args,
+                    variables,
+                    COMMAND_SUBTRACT_FROM_VARIABLE,
+                    now,
+                ) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-            if condition.evaluate() {
-                return run_inner_executions(inner_executions, variables, socket);
-            }
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_MULTIPLY_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-            CommandFunctionResult::Continue
-        }),
-        INSTRUCTION_LOOP => Box::new(|_args, inner_executions, variables, socket| {
-            loop {
-                let res = run_inner_executions(inner_executions, variables, socket);
+                println!("Multiply variable: {:?}", args);
 
-                if res.is_break() {
-                    break;
+                match args_required(args, 2) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
 
-                if res.is_error() {
-                    return res;
-                }
+                let args = args.clone().unwrap();
 
-                if res.is_save_variable() {
-                    save_variable(res, variables);
-                    continue;
-                }
+                let (variable_name, variable_value) = match change_numeric_variable_value(
+                    &args,
+                    variables,
+                    COMMAND_MULTIPLY_VARIABLE,
+                    now,
+                ) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-                if res.is_return() {
-                    return res;
-                }
-            }
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_DIVIDE_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Divide variable: {:?}", args);
 
-            CommandFunctionResult::Continue
-        }),
-        INSTRUCTION_WHILE_LOOP => Box::new(|args, inner_executions, variables, socket| {
-            match args_required(args, -1) {
-                Ok(_) => {}
-                Err(e) => {
-                    return CommandFunctionResult::Error(e.to_string());
+                match args_required(args, 2) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            while parse_condition(args.clone().unwrap(), variables).evaluate() {
-                let res = run_inner_executions(inner_executions, variables, socket);
+                let args = args.clone().unwrap();
 
-                if res.is_break() {
-                    break;
-                }
+                let (variable_name, variable_value) = match change_numeric_variable_value(
+                    &args,
+                    variables,
+                    COMMAND_DIVIDE_VARIABLE,
+                    now,
+                ) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-                if res.is_error() {
-                    return res;
-                }
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_MODULO_VARIABLE => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-                if res.is_save_variable() {
-                    save_variable(res, variables);
-                    continue;
-                }
+                println!("Modulo variable: {:?}", args);
 
-                if res.is_return() {
-                    return res;
+                match args_required(args, 2) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
-            }
 
-            CommandFunctionResult::Continue
-        }),
-        INSTRUCTION_BREAK => {
-            Box::new(|_args, _inner_executions, _variables, _socket| CommandFunctionResult::Break)
-        }
-        INSTRUCTION_CONTINUE => Box::new(|_args, _inner_executions, _variables, _socket| {
-            CommandFunctionResult::Continue
-        }),
-        _ => {
-            panic!("Unknown instruction: {}", instruction);
-        }
-    }
-}
+                let args = args.clone().unwrap();
 
-fn args_required(args: &Option<Args>, number_of_args: isize) -> Result<()> {
-    if args.is_none() {
-        return Err(anyhow!("Arguments required"));
-    }
+                let (variable_name, variable_value) = match change_numeric_variable_value(
+                    &args,
+                    variables,
+                    COMMAND_MODULO_VARIABLE,
+                    now,
+                ) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
 
-    if number_of_args != -1i8 as isize
-        && args.clone().unwrap().len() != number_of_args.unsigned_abs()
-    {
-        return Err(anyhow!("Invalid number of arguments"));
-    }
+                CommandFunctionResult::SaveVariable(variable_name, variable_value)
+            })
+        },
+        COMMAND_SEND_MESSAGE_TO_DASHBOARD => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-    Ok(())
-}
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                }
 
-fn run_inner_executions(
-    inner_executions: &Vec<ScriptExecution>,
-    variables: &mut Variables,
-    socket: &SocketRef,
-) -> CommandFunctionResult {
-    for execution in inner_executions {
-        match execution {
-            ScriptExecution::Command(command) => {
-                let res = command.execute(variables, socket);
+                let args = args.clone().unwrap();
 
-                if res.is_break() {
-                    return CommandFunctionResult::Break;
-                }
+                let message = args.first().unwrap().clone();
 
-                if res.is_error() {
-                    return res;
-                }
+                let message = match message {
+                    Value::String(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid message".to_string());
+                    }
+                };
 
-                if res.is_save_variable() {
-                    save_variable(res, variables);
-                    continue;
-                }
+                let message = compute_message_with_variables(message, variables, now);
+
+                context.push_output(message.clone());
 
-                if res.is_return() {
+                if let Some(res) =
+                    simulate_command(context, COMMAND_SEND_MESSAGE_TO_DASHBOARD, message.clone())
+                {
                     return res;
                 }
-            }
-            ScriptExecution::Block(block) => {
-                let res = block.execute(variables, socket);
 
-                if res.is_break() {
-                    return CommandFunctionResult::Break;
+                match context
+                    .get_target()
+                    .send_message(message, DashboardMessageType::Info)
+                {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
 
-                if res.is_error() {
-                    return res;
+                CommandFunctionResult::Continue
+            })
+        },
+        COMMAND_DELAY => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                }
+
+                let args = args.clone().unwrap();
+
+                let delay = match args[0].to_string(variables, now).parse::<u64>() {
+                    Ok(delay) => delay,
+                    Err(_) => {
+                        return CommandFunctionResult::Error("Invalid delay".to_string());
+                    }
+                };
+
+                if let Some(simulation) = context.get_simulation() {
+                    return match simulation
+                        .record(context.get_span(), COMMAND_DELAY, format!("{} ms", delay))
+                        .and_then(|_| simulation.advance(delay))
+                    {
+                        Ok(_) => CommandFunctionResult::Continue,
+                        Err(e) => CommandFunctionResult::Error(e.to_string()),
+                    };
                 }
 
-                if res.is_save_variable() {
-                    save_variable(res, variables);
-                    continue;
+                select! {
+                    _ = sleep(Duration::from_millis(delay)) => CommandFunctionResult::Continue,
+                    _ = context.get_cancel_token().cancelled() => CommandFunctionResult::Cancelled,
                 }
+            })
+        },
+        COMMAND_CALL_FUNCTION => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
 
-                if res.is_return() {
-                    return res;
+                let args = args.clone().unwrap_or_default();
+
+                let (name, call_args) = match (args.first(), args.get(1)) {
+                    (Some(Value::String(name)), Some(Value::Array(call_args))) => (name, call_args),
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid function call".to_string());
+                    }
+                };
+
+                let function = match context.get_function(name) {
+                    Some(function) => function,
+                    None => {
+                        return CommandFunctionResult::Error(format!("Unknown function {}", name));
+                    }
+                };
+
+                if function.get_parameters().len() != call_args.len() {
+                    return CommandFunctionResult::Error("Invalid number of arguments".to_string());
+                }
+
+                let function_context = match context.nested(context.get_functions()) {
+                    Ok(function_context) => function_context,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
+
+                // Functions only see their own arguments.
+                let mut local_variables: Variables = function
+                    .get_parameters()
+                    .iter()
+                    .cloned()
+                    .zip(
+                        call_args
+                            .iter()
+                            .map(|arg| arg.clone().resolve(variables, now)),
+                    )
+                    .collect();
+
+                let value = match run_inner_executions(
+                    function.get_executions(),
+                    &mut local_variables,
+                    &function_context,
+                )
+                .await
+                {
+                    CommandFunctionResult::Return(value) => value,
+                    res if res.is_error() || res.is_cancelled() => {
+                        return res;
+                    }
+                    _ => Value::None,
+                };
+
+                match args.get(2) {
+                    Some(Value::Variable(variable_name)) => {
+                        CommandFunctionResult::SaveVariable(variable_name.to_string(), value)
+                    }
+                    _ => CommandFunctionResult::Continue,
+                }
+            })
+        },
+        COMMAND_CALL_SCRIPT => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                println!("Call script: {:?}", args);
+
+                match args_required(args, 1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                }
+
+                let script_id = match args.clone().unwrap()[0].clone().resolve(variables, now) {
+                    Value::Int32(s) => s,
+                    _ => {
+                        return CommandFunctionResult::Error("Invalid script id".to_string());
+                    }
+                };
+
+                let script = match run_blocking(move || Script::parse(script_id)).await {
+                    Ok(script) => script,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(format!(
+                            "Error parsing script {}: {}",
+                            script_id, e
+                        ));
+                    }
+                };
+
+                let mut script_context = match context.nested(script.get_functions()) {
+                    Ok(script_context) => script_context,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
+
+                // Breakpoints are lines of the debugged script, so a called
+                // script runs as a single step.
+                script_context.debugger = None;
+
+                let mut script_variables: Variables = HashMap::new();
+
+                match run_inner_executions(
+                    &script.executions,
+                    &mut script_variables,
+                    &script_context,
+                )
+                .await
+                {
+                    // Errors keep pointing at the line of the script they happened in.
+                    CommandFunctionResult::LocatedError(span, e) => {
+                        CommandFunctionResult::ScriptError(script_id, span, e)
+                    }
+                    res if res.is_error() || res.is_cancelled() => res,
+                    _ => CommandFunctionResult::Continue,
+                }
+            })
+        },
+        COMMAND_RETURN => |args, variables, context| {
+            Box::pin(async move {
+                let now = &context.get_now();
+
+                let value = match args {
+                    None => Value::None,
+                    Some(args) if args.len() == 1 => args[0].clone().resolve(variables, now),
+                    Some(_) => {
+                        return CommandFunctionResult::Error(
+                            "Invalid number of arguments".to_string(),
+                        );
+                    }
+                };
+
+                CommandFunctionResult::Return(value)
+            })
+        },
+        _ => |_args, _variables, _context| {
+            Box::pin(async move { CommandFunctionResult::Error("Unknown command".to_string()) })
+        },
+    }
+}
+
+fn parse_instruction_function(instruction: Instruction) -> InstructionFunction {
+    match instruction {
+        INSTRUCTION_IF => |args, inner_executions, else_executions, variables, context| {
+            Box::pin(async move {
+                match args_required(args, -1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                }
+
+                let args = args.clone().unwrap();
+
+                let condition = match parse_condition(args, variables, &context.get_now()) {
+                    Ok(condition) => condition,
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
+                };
+
+                if condition.evaluate() {
+                    return run_inner_executions(inner_executions, variables, context).await;
+                }
+
+                run_inner_executions(else_executions, variables, context).await
+            })
+        },
+        INSTRUCTION_LOOP => |_args, inner_executions, _else_executions, variables, context| {
+            Box::pin(async move {
+                loop {
+                    yield_now().await;
+
+                    if context.is_cancelled() {
+                        return CommandFunctionResult::Cancelled;
+                    }
+
+                    let res = run_inner_executions(inner_executions, variables, context).await;
+
+                    if res.is_break() {
+                        break;
+                    }
+
+                    if res.is_error() || res.is_cancelled() {
+                        return res;
+                    }
+
+                    if res.is_save_variable() {
+                        save_variable(res, variables);
+                        continue;
+                    }
+
+                    if res.is_return() {
+                        return res;
+                    }
+                }
+
+                CommandFunctionResult::Continue
+            })
+        },
+        INSTRUCTION_WHILE_LOOP => |args, inner_executions, _else_executions, variables, context| {
+            Box::pin(async move {
+                match args_required(args, -1) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        return CommandFunctionResult::Error(e.to_string());
+                    }
                 }
+
+                loop {
+                    match parse_condition(args.clone().unwrap(), variables, &context.get_now()) {
+                        Ok(condition) if condition.evaluate() => {}
+                        Ok(_) => break,
+                        Err(e) => {
+                            return CommandFunctionResult::Error(e.to_string());
+                        }
+                    }
+
+                    yield_now().await;
+
+                    if context.is_cancelled() {
+                        return CommandFunctionResult::Cancelled;
+                    }
+
+                    let res = run_inner_executions(inner_executions, variables, context).await;
+
+                    if res.is_break() {
+                        break;
+                    }
+
+                    if res.is_error() || res.is_cancelled() {
+                        return res;
+                    }
+
+                    if res.is_save_variable() {
+                        save_variable(res, variables);
+                        continue;
+                    }
+
+                    if res.is_return() {
+                        return res;
+                    }
+                }
+
+                CommandFunctionResult::Continue
+            })
+        },
+        INSTRUCTION_BREAK => |_args, _inner_executions, _else_executions, _variables, _context| {
+            Box::pin(async move { CommandFunctionResult::Break })
+        },
+        INSTRUCTION_CONTINUE => {
+            |_args, _inner_executions, _else_executions, _variables, _context| {
+                Box::pin(async move { CommandFunctionResult::Continue })
+            }
+        }
+        _ => |_args, _inner_executions, _else_executions, _variables, _context| {
+            Box::pin(async move { CommandFunctionResult::Error("Unknown instruction".to_string()) })
+        },
+    }
+}
+
+/// In a dry run, records the command in the trace instead of carrying it out.
+fn simulate_command(
+    context: &ScriptContext,
+    command: Command,
+    detail: String,
+) -> Option<CommandFunctionResult> {
+    let simulation = context.get_simulation()?;
+
+    Some(
+        match simulation.record(context.get_span(), command, detail) {
+            Ok(_) => CommandFunctionResult::Continue,
+            Err(e) => CommandFunctionResult::Error(e.to_string()),
+        },
+    )
+}
+
+/// Splits `SET $x = <expression>` into the variable name and the expression
+/// components; the older `SET $x value` form returns `None`.
+fn split_assignment(args: &Option<Args>) -> Option<(String, Args)> {
+    match args.as_deref() {
+        Some([Value::Variable(name), Value::String(equal), expression @ ..])
+            if equal == "=" && !expression.is_empty() =>
+        {
+            Some((name.to_string(), expression.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+/// `READ_AVG <sensor> <minutes> $variable` and its MIN/MAX/COUNT siblings
+/// summarize the readings stored over the last minutes instead of polling.
+async fn read_sensor_history(
+    args: &Option<Args>,
+    variables: &Variables,
+    command: Command,
+    now: DateTime<Local>,
+) -> CommandFunctionResult {
+    println!("Read sensor history: {} {:?}", command, args);
+
+    let (sensor_id, minutes, variable_name) = match args.as_deref() {
+        Some([sensor_id, minutes, Value::Variable(variable_name)]) => match (
+            sensor_id.clone().resolve(variables, &now),
+            minutes.clone().resolve(variables, &now),
+        ) {
+            (Value::Int32(sensor_id), Value::Int32(minutes)) if minutes > 0 => {
+                (sensor_id, minutes, variable_name.to_string())
+            }
+            _ => {
+                return CommandFunctionResult::Error(
+                    "Invalid sensor id or number of minutes".to_string(),
+                );
+            }
+        },
+        _ => {
+            return CommandFunctionResult::Error("Invalid number of arguments".to_string());
+        }
+    };
+
+    let since = chrono::Local::now().naive_local() - chrono::Duration::minutes(minutes as i64);
+
+    match run_blocking(move || get_sensor_values_since(sensor_id, since)).await {
+        Ok(sensor_values) => match summarize_sensor_values(&sensor_values, command) {
+            Ok(value) => CommandFunctionResult::SaveVariable(variable_name, value),
+            Err(e) => CommandFunctionResult::Error(e.to_string()),
+        },
+        Err(e) => CommandFunctionResult::Error(e.to_string()),
+    }
+}
+
+/// Counts every stored reading; the other summaries skip values that are not
+/// numbers and are empty when no number was stored.
+fn summarize_sensor_values(sensor_values: &[Option<f64>], command: Command) -> Result<Value> {
+    if command == COMMAND_READ_SENSOR_COUNT {
+        return Ok(Value::Int64(sensor_values.len() as i64));
+    }
+
+    let numbers = sensor_values
+        .iter()
+        .flatten()
+        .map(|sensor_value| Value::Float64(*sensor_value))
+        .collect::<Vec<Value>>();
+
+    if numbers.is_empty() {
+        return Ok(Value::None);
+    }
+
+    let function = match command {
+        COMMAND_READ_SENSOR_AVERAGE => FUNCTION_AVG,
+        COMMAND_READ_SENSOR_MINIMUM => FUNCTION_MIN,
+        COMMAND_READ_SENSOR_MAXIMUM => FUNCTION_MAX,
+        _ => return Err(anyhow!("Unknown sensor summary {}", command)),
+    };
+
+    apply_function(function, numbers)
+}
+
+/// `SET_GLOBAL $x` stores the local `$x`, while `SET_GLOBAL $x value` and
+/// `SET_GLOBAL $x = <expression>` store a new value.
+fn global_assignment(
+    args: &Option<Args>,
+    variables: &Variables,
+    now: &DateTime<Local>,
+) -> Result<(String, Value)> {
+    if let Some((variable_name, expression)) = split_assignment(args) {
+        return Ok((
+            variable_name,
+            parse_expression(expression)?.evaluate(variables, now)?,
+        ));
+    }
+
+    match args.as_deref() {
+        Some([Value::Variable(name)]) => match variables.get(name) {
+            Some(value) => Ok((name.to_string(), value.clone().resolve(variables, now))),
+            None => Err(anyhow!("Variable {} is not set", name)),
+        },
+        Some([Value::Variable(name), value]) => {
+            Ok((name.to_string(), value.clone().resolve(variables, now)))
+        }
+        Some([_]) | Some([_, _]) => Err(anyhow!("Invalid variable name")),
+        _ => Err(anyhow!("Invalid number of arguments")),
+    }
+}
+
+/// CoAP requests and database lookups block, so they run off the script task.
+async fn run_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    spawn_blocking(f).await?
+}
+
+fn args_required(args: &Option<Args>, number_of_args: isize) -> Result<()> {
+    if args.is_none() {
+        return Err(anyhow!("Arguments required"));
+    }
+
+    if number_of_args != -1i8 as isize
+        && args.clone().unwrap().len() != number_of_args.unsigned_abs()
+    {
+        return Err(anyhow!("Invalid number of arguments"));
+    }
+
+    Ok(())
+}
+
+async fn run_inner_executions(
+    inner_executions: &Vec<ScriptExecution>,
+    variables: &mut Variables,
+    context: &ScriptContext,
+) -> CommandFunctionResult {
+    for execution in inner_executions {
+        if context.is_cancelled() {
+            return CommandFunctionResult::Cancelled;
+        }
+
+        context.set_span(execution.get_span());
+
+        if let Some(debugger) = context.get_debugger() {
+            if !debugger
+                .pause(execution.get_span(), variables, context)
+                .await
+            {
+                return CommandFunctionResult::Cancelled;
+            }
+        }
+
+        let res = match execution {
+            ScriptExecution::Command(command) => command.execute(variables, context).await,
+            ScriptExecution::Block(block) => block.execute(variables, context).await,
+        };
+
+        let res = match res {
+            CommandFunctionResult::Error(e) => {
+                CommandFunctionResult::LocatedError(execution.get_span(), e)
             }
+            res => res,
+        };
+
+        if res.is_break() {
+            return CommandFunctionResult::Break;
+        }
+
+        if res.is_error() || res.is_cancelled() {
+            return res;
+        }
+
+        if res.is_save_variable() {
+            save_variable(res, variables);
+            continue;
+        }
+
+        if res.is_return() {
+            return res;
         }
     }
 
     CommandFunctionResult::Continue
 }
 
-fn parse_argument(s: String) -> Value {
+pub fn parse_argument(s: String) -> Value {
     if "true" == s {
         Value::Boolean(true)
     } else if "false" == s {
@@ -698,294 +1309,1224 @@ fn parse_argument(s: String) -> Value {
     }
 }
 
-fn parse_instruction(s: String) -> Result<(Instruction, Option<Args>)> {
-    let mut fragments = s.split_whitespace();
+/// Parses a standalone argument list, such as a rule condition.
+pub fn parse_arguments(s: &str) -> Result<Args> {
+    let tokens = tokenize(s)?
+        .into_iter()
+        .filter(|token| !token.is_end_of_line())
+        .collect::<Vec<Token>>();
 
-    let instruction = fragments.next();
-    let arguments = fragments
-        .map(|arg| parse_argument(arg.to_string()))
-        .collect::<Vec<Value>>();
+    tokens_to_args(&tokens)
+}
+
+fn token_to_value(tokens: &[Token], position: &mut usize) -> Result<Value> {
+    let token = &tokens[*position];
+    *position += 1;
+
+    match token.get_kind() {
+        TokenKind::Word(w) if w == "true" => Ok(Value::Boolean(true)),
+        TokenKind::Word(w) if w == "false" => Ok(Value::Boolean(false)),
+        TokenKind::Word(w) => Ok(Value::String(w.to_string())),
+        TokenKind::Variable(v) => Ok(Value::Variable(v.to_string())),
+        TokenKind::String(s) => Ok(Value::String(s.to_string())),
+        TokenKind::Number(n) => Ok(parse_argument(n.to_string())),
+        TokenKind::Symbol(s) if s == "[" || s == "{" => {
+            let close = if s == "[" { "]" } else { "}" };
+
+            let mut values = Vec::new();
+
+            loop {
+                match tokens.get(*position) {
+                    None => {
+                        return Err(located_error(token.get_span(), format!("unclosed '{}'", s)));
+                    }
+                    Some(t) if t.is_symbol(close) => {
+                        *position += 1;
+                        break;
+                    }
+                    Some(t) if t.is_symbol(",") => {
+                        *position += 1;
+                    }
+                    Some(_) => values.push(token_to_value(tokens, position)?),
+                }
+            }
+
+            Ok(Value::Array(values))
+        }
+        TokenKind::Symbol(s) => Ok(Value::String(s.to_string())),
+        kind => Err(located_error(
+            token.get_span(),
+            format!("unexpected {}", kind),
+        )),
+    }
+}
+
+fn tokens_to_args(tokens: &[Token]) -> Result<Args> {
+    let mut args = Vec::new();
+    let mut position = 0;
+
+    while position < tokens.len() {
+        args.push(token_to_value(tokens, &mut position)?);
+    }
+
+    Ok(args)
+}
+
+/// Parses `name(arg, ...)` at the start of `tokens`, returning the position
+/// right after the closing parenthesis.
+fn parse_call_signature(tokens: &[Token], span: Span) -> Result<(String, Args, usize)> {
+    let name = match tokens.first().map(|token| token.get_kind()) {
+        Some(TokenKind::Word(name)) => name.to_string(),
+        _ => {
+            return Err(located_error(span, "expected a function name".to_string()));
+        }
+    };
+
+    if !tokens.get(1).is_some_and(|token| token.is_symbol("(")) {
+        return Err(located_error(span, format!("expected '(' after {}", name)));
+    }
+
+    let mut args = Vec::new();
+    let mut position = 2;
+
+    loop {
+        match tokens.get(position) {
+            Some(token) if token.is_symbol(")") => {
+                return Ok((name, args, position + 1));
+            }
+            Some(token) if !args.is_empty() => {
+                if !token.is_symbol(",") {
+                    return Err(located_error(
+                        token.get_span(),
+                        format!("expected ',' or ')', found {}", token.get_kind()),
+                    ));
+                }
+
+                position += 1;
+            }
+            Some(_) => {}
+            None => {
+                return Err(located_error(
+                    span,
+                    format!("unclosed '(' in call to {}", name),
+                ));
+            }
+        }
+
+        match tokens.get(position) {
+            Some(token) if token.is_symbol(",") || token.is_symbol(")") => {
+                return Err(located_error(
+                    token.get_span(),
+                    format!("expected an argument, found {}", token.get_kind()),
+                ));
+            }
+            Some(_) => args.push(token_to_value(tokens, &mut position)?),
+            None => {
+                return Err(located_error(
+                    span,
+                    format!("unclosed '(' in call to {}", name),
+                ));
+            }
+        }
+    }
+}
+
+fn parse_instruction(name: &str) -> Option<Instruction> {
+    match name {
+        INSTRUCTION_IF => Some(INSTRUCTION_IF),
+        INSTRUCTION_LOOP => Some(INSTRUCTION_LOOP),
+        INSTRUCTION_WHILE_LOOP => Some(INSTRUCTION_WHILE_LOOP),
+        INSTRUCTION_BREAK => Some(INSTRUCTION_BREAK),
+        INSTRUCTION_CONTINUE => Some(INSTRUCTION_CONTINUE),
+        _ => None,
+    }
+}
+
+fn parse_command(name: &str) -> Option<Command> {
+    match name {
+        COMMAND_ACTIVATE_ACTUATOR => Some(COMMAND_ACTIVATE_ACTUATOR),
+        COMMAND_DEACTIVATE_ACTUATOR => Some(COMMAND_DEACTIVATE_ACTUATOR),
+        COMMAND_PULSE_ACTUATOR => Some(COMMAND_PULSE_ACTUATOR),
+        COMMAND_READ_SENSOR => Some(COMMAND_READ_SENSOR),
+        COMMAND_SEND_MESSAGE_TO_DASHBOARD => Some(COMMAND_SEND_MESSAGE_TO_DASHBOARD),
+        COMMAND_READ_LAST_SENSOR_VALUE => Some(COMMAND_READ_LAST_SENSOR_VALUE),
+        COMMAND_READ_SENSOR_AVERAGE => Some(COMMAND_READ_SENSOR_AVERAGE),
+        COMMAND_READ_SENSOR_MINIMUM => Some(COMMAND_READ_SENSOR_MINIMUM),
+        COMMAND_READ_SENSOR_MAXIMUM => Some(COMMAND_READ_SENSOR_MAXIMUM),
+        COMMAND_READ_SENSOR_COUNT => Some(COMMAND_READ_SENSOR_COUNT),
+        COMMAND_SET_VARIABLE => Some(COMMAND_SET_VARIABLE),
+        COMMAND_UNSET_VARIABLE => Some(COMMAND_UNSET_VARIABLE),
+        COMMAND_GET_GLOBAL_VARIABLE => Some(COMMAND_GET_GLOBAL_VARIABLE),
+        COMMAND_SET_GLOBAL_VARIABLE => Some(COMMAND_SET_GLOBAL_VARIABLE),
+        COMMAND_ADD_TO_VARIABLE => Some(COMMAND_ADD_TO_VARIABLE),
+        COMMAND_SUBTRACT_FROM_VARIABLE => Some(COMMAND_SUBTRACT_FROM_VARIABLE),
+        COMMAND_MULTIPLY_VARIABLE => Some(COMMAND_MULTIPLY_VARIABLE),
+        COMMAND_DIVIDE_VARIABLE => Some(COMMAND_DIVIDE_VARIABLE),
+        COMMAND_MODULO_VARIABLE => Some(COMMAND_MODULO_VARIABLE),
+        COMMAND_DELAY => Some(COMMAND_DELAY),
+        COMMAND_CALL_SCRIPT => Some(COMMAND_CALL_SCRIPT),
+        COMMAND_RETURN => Some(COMMAND_RETURN),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Value {
+    None,
+    Variable(String),
+    Array(Vec<Value>),
+    String(String),
+    Boolean(bool),
+    Float32(f32),
+    Float64(f64),
+    Int32(i32),
+    Int64(i64),
+}
+
+impl Value {
+    /// Converts a stored global variable back into a script value.
+    pub fn from_json(json: serde_json::Value) -> Result<Value> {
+        match json {
+            serde_json::Value::Null => Ok(Value::None),
+            serde_json::Value::Bool(b) => Ok(Value::Boolean(b)),
+            serde_json::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => Ok(match i32::try_from(i) {
+                    Ok(i) => Value::Int32(i),
+                    Err(_) => Value::Int64(i),
+                }),
+                (None, Some(f)) => Ok(Value::Float64(f)),
+                _ => Err(anyhow!("Unsupported number {}", n)),
+            },
+            serde_json::Value::String(s) => Ok(Value::String(s)),
+            serde_json::Value::Array(array) => Ok(Value::Array(
+                array
+                    .into_iter()
+                    .map(Value::from_json)
+                    .collect::<Result<Vec<Value>>>()?,
+            )),
+            serde_json::Value::Object(_) => Err(anyhow!("Objects are not supported")),
+        }
+    }
+
+    /// A sensor reading as a number, or the text itself when the device did
+    /// not send one.
+    pub fn from_sensor_value(raw_value: &str) -> Value {
+        match parse_sensor_value(raw_value) {
+            (Some(sensor_value), _) => Value::Float64(sensor_value),
+            (None, _) => Value::String(raw_value.to_string()),
+        }
+    }
+
+    pub fn from_sensor_read(sensor_read: &SensorRead) -> Value {
+        match sensor_read.get_sensor_value() {
+            Some(sensor_value) => Value::Float64(sensor_value),
+            None => Value::String(sensor_read.get_raw_value().to_string()),
+        }
+    }
+
+    /// Replaces a variable reference with its current value, or `None` when
+    /// the variable is not set.
+    pub fn resolve(self, variables: &Variables, now: &DateTime<Local>) -> Value {
+        match self {
+            Value::Variable(name) => get_variable(&name, variables, now).unwrap_or(Value::None),
+            _ => self,
+        }
+    }
+
+    pub fn to_string(&self, variables: &Variables, now: &DateTime<Local>) -> String {
+        match self {
+            Value::None => "".to_string(),
+            Value::String(s) => s.to_string(),
+            Value::Variable(s) => match get_variable(s, variables, now) {
+                Some(variable) => variable.to_string(variables, now),
+                None => "".to_string(),
+            },
+            Value::Int32(n) => n.to_string(),
+            Value::Int64(n) => n.to_string(),
+            Value::Float32(n) => n.to_string(),
+            Value::Float64(n) => n.to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Array(array) => {
+                let mut s = "[".to_string();
+                for value in array {
+                    s.push_str(value.to_string(variables, now).as_str());
+                    s += ", "
+                }
+                s += "]";
+                s
+            }
+        }
+    }
+}
+
+pub type Args = Vec<Value>;
+
+pub type Variables = HashMap<String, Value>;
+
+/// Looks a variable up among the script's own variables first, then among
+/// the built-in time variables such as `$hour` and `$sunset`.
+pub fn get_variable(name: &str, variables: &Variables, now: &DateTime<Local>) -> Option<Value> {
+    match variables.get(name) {
+        Some(value) => Some(value.clone()),
+        None => get_time_variable(name, now),
+    }
+}
+
+type ExecutionFuture<'a> = Pin<Box<dyn Future<Output = CommandFunctionResult> + Send + 'a>>;
+
+type CommandFunction =
+    for<'a> fn(&'a Option<Args>, &'a mut Variables, &'a ScriptContext) -> ExecutionFuture<'a>;
+type InstructionFunction = for<'a> fn(
+    &'a Option<Args>,
+    &'a Vec<ScriptExecution>,
+    &'a Vec<ScriptExecution>,
+    &'a mut Variables,
+    &'a ScriptContext,
+) -> ExecutionFuture<'a>;
+
+pub enum CommandFunctionResult {
+    Error(String),
+    LocatedError(Span, String),
+    ScriptError(i32, Span, String),
+    SaveVariable(String, Value),
+    Return(Value),
+    Continue,
+    Break,
+    Cancelled,
+}
+
+impl CommandFunctionResult {
+    fn is_save_variable(&self) -> bool {
+        matches!(self, CommandFunctionResult::SaveVariable(_, _))
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(
+            self,
+            CommandFunctionResult::Error(_)
+                | CommandFunctionResult::LocatedError(_, _)
+                | CommandFunctionResult::ScriptError(_, _, _)
+        )
+    }
+
+    fn is_return(&self) -> bool {
+        matches!(self, CommandFunctionResult::Return(_))
+    }
+
+    fn is_break(&self) -> bool {
+        matches!(self, CommandFunctionResult::Break)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, CommandFunctionResult::Cancelled)
+    }
+}
+
+struct ScriptCommand {
+    command: Command,
+    function: CommandFunction,
+    arguments: Option<Args>,
+    span: Span,
+}
+
+impl ScriptCommand {
+    fn new(command: Command, arguments: Option<Args>, span: Span) -> ScriptCommand {
+        ScriptCommand {
+            command,
+            arguments,
+            function: parse_command_function(command),
+            span,
+        }
+    }
+
+    fn get_command(&self) -> Command {
+        self.command
+    }
+
+    fn get_arguments(&self) -> &Option<Args> {
+        &self.arguments
+    }
+
+    fn get_function(&self) -> &CommandFunction {
+        &self.function
+    }
+
+    async fn execute(
+        &self,
+        variables: &mut Variables,
+        context: &ScriptContext,
+    ) -> CommandFunctionResult {
+        self.get_function()(self.get_arguments(), variables, context).await
+    }
+}
+
+struct ScriptBlock {
+    function: InstructionFunction,
+    arguments: Option<Args>,
+    inner_executions: Vec<ScriptExecution>,
+    else_executions: Vec<ScriptExecution>,
+    span: Span,
+}
+
+impl ScriptBlock {
+    fn new(
+        instruction: Instruction,
+        arguments: Option<Args>,
+        inner_executions: Vec<ScriptExecution>,
+        else_executions: Vec<ScriptExecution>,
+        span: Span,
+    ) -> ScriptBlock {
+        ScriptBlock {
+            arguments,
+            function: parse_instruction_function(instruction),
+            inner_executions,
+            else_executions,
+            span,
+        }
+    }
+
+    fn get_arguments(&self) -> &Option<Args> {
+        &self.arguments
+    }
+
+    fn get_function(&self) -> &InstructionFunction {
+        &self.function
+    }
+
+    fn get_inner_executions(&self) -> &Vec<ScriptExecution> {
+        &self.inner_executions
+    }
+
+    fn get_else_executions(&self) -> &Vec<ScriptExecution> {
+        &self.else_executions
+    }
+
+    async fn execute(
+        &self,
+        variables: &mut Variables,
+        context: &ScriptContext,
+    ) -> CommandFunctionResult {
+        self.get_function()(
+            self.get_arguments(),
+            self.get_inner_executions(),
+            self.get_else_executions(),
+            variables,
+            context,
+        )
+        .await
+    }
+}
+
+enum ScriptExecution {
+    Command(ScriptCommand),
+    Block(ScriptBlock),
+}
+
+/// A `FUNCTION name($a, $b) THEN ... END` definition. The body runs with only
+/// the parameters as variables and hands back the value of its `RETURN`.
+struct ScriptFunction {
+    parameters: Vec<String>,
+    executions: Vec<ScriptExecution>,
+}
+
+impl ScriptFunction {
+    fn new(parameters: Vec<String>, executions: Vec<ScriptExecution>) -> Self {
+        ScriptFunction {
+            parameters,
+            executions,
+        }
+    }
+
+    fn get_parameters(&self) -> &Vec<String> {
+        &self.parameters
+    }
+
+    fn get_executions(&self) -> &Vec<ScriptExecution> {
+        &self.executions
+    }
+}
+
+type Functions = HashMap<String, ScriptFunction>;
+
+impl ScriptExecution {
+    fn get_span(&self) -> Span {
+        match self {
+            ScriptExecution::Command(command) => command.span,
+            ScriptExecution::Block(block) => block.span,
+        }
+    }
+}
+
+/// Ids that a script may refer to, checked before a script is saved.
+pub struct ScriptReferences {
+    sensor_ids: HashSet<i32>,
+    actuator_ids: HashSet<i32>,
+    script_ids: HashSet<i32>,
+}
+
+impl ScriptReferences {
+    pub fn new(
+        sensor_ids: HashSet<i32>,
+        actuator_ids: HashSet<i32>,
+        script_ids: HashSet<i32>,
+    ) -> Self {
+        ScriptReferences {
+            sensor_ids,
+            actuator_ids,
+            script_ids,
+        }
+    }
+}
+
+fn expect_number_of_args(command: Command, args: &[Value], number_of_args: usize) -> Result<()> {
+    if args.len() != number_of_args {
+        return Err(anyhow!(
+            "{} takes {} argument{}, found {}",
+            command,
+            number_of_args,
+            if number_of_args == 1 { "" } else { "s" },
+            args.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn expect_variable(value: &Value) -> Result<()> {
+    match value {
+        Value::Variable(_) => Ok(()),
+        _ => Err(anyhow!(
+            "expected a variable, found {}",
+            value.to_string(&HashMap::new(), &Local::now())
+        )),
+    }
+}
+
+/// Ids given as literals must exist; ids held in variables are only known at
+/// run time.
+fn expect_reference(value: &Value, ids: &HashSet<i32>, kind: &str) -> Result<()> {
+    match value {
+        Value::Int32(id) if ids.contains(id) => Ok(()),
+        Value::Int32(id) => Err(anyhow!("{} {} does not exist", kind, id)),
+        Value::Variable(_) => Ok(()),
+        _ => Err(anyhow!("expected {} id", kind)),
+    }
+}
+
+fn validate_command(command: &ScriptCommand, references: &ScriptReferences) -> Result<()> {
+    let name = command.get_command();
+    let args = command.get_arguments().as_deref().unwrap_or_default();
+
+    match name {
+        COMMAND_ACTIVATE_ACTUATOR | COMMAND_DEACTIVATE_ACTUATOR | COMMAND_PULSE_ACTUATOR => {
+            expect_number_of_args(name, args, 1)?;
+            expect_reference(&args[0], &references.actuator_ids, "actuator")
+        }
+        COMMAND_READ_SENSOR | COMMAND_READ_LAST_SENSOR_VALUE => {
+            expect_number_of_args(name, args, 1)?;
+            expect_reference(&args[0], &references.sensor_ids, "sensor")
+        }
+        COMMAND_READ_SENSOR_AVERAGE
+        | COMMAND_READ_SENSOR_MINIMUM
+        | COMMAND_READ_SENSOR_MAXIMUM
+        | COMMAND_READ_SENSOR_COUNT => {
+            expect_number_of_args(name, args, 3)?;
+            expect_reference(&args[0], &references.sensor_ids, "sensor")?;
+
+            match &args[1] {
+                Value::Int32(minutes) if *minutes > 0 => {}
+                Value::Variable(_) => {}
+                _ => return Err(anyhow!("expected a number of minutes")),
+            }
+
+            expect_variable(&args[2])
+        }
+        COMMAND_CALL_SCRIPT => {
+            expect_number_of_args(name, args, 1)?;
+            expect_reference(&args[0], &references.script_ids, "script")
+        }
+        COMMAND_SEND_MESSAGE_TO_DASHBOARD => {
+            expect_number_of_args(name, args, 1)?;
+
+            match &args[0] {
+                Value::String(_) => Ok(()),
+                _ => Err(anyhow!("expected a message in quotes")),
+            }
+        }
+        COMMAND_SET_VARIABLE if split_assignment(command.get_arguments()).is_some() => Ok(()),
+        COMMAND_SET_VARIABLE => {
+            expect_number_of_args(name, args, 2)?;
+            expect_variable(&args[0])
+        }
+        COMMAND_UNSET_VARIABLE => {
+            expect_number_of_args(name, args, 1)?;
+            expect_variable(&args[0])
+        }
+        COMMAND_SET_GLOBAL_VARIABLE if split_assignment(command.get_arguments()).is_some() => {
+            Ok(())
+        }
+        COMMAND_GET_GLOBAL_VARIABLE | COMMAND_SET_GLOBAL_VARIABLE => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(anyhow!(
+                    "{} takes 1 or 2 arguments, found {}",
+                    name,
+                    args.len()
+                ));
+            }
+
+            expect_variable(&args[0])
+        }
+        COMMAND_ADD_TO_VARIABLE
+        | COMMAND_SUBTRACT_FROM_VARIABLE
+        | COMMAND_MULTIPLY_VARIABLE
+        | COMMAND_DIVIDE_VARIABLE
+        | COMMAND_MODULO_VARIABLE => {
+            expect_number_of_args(name, args, 2)?;
+            expect_variable(&args[0])?;
+
+            match &args[1] {
+                Value::Int32(_)
+                | Value::Int64(_)
+                | Value::Float32(_)
+                | Value::Float64(_)
+                | Value::Variable(_) => Ok(()),
+                _ => Err(anyhow!("expected a number")),
+            }
+        }
+        COMMAND_DELAY => {
+            expect_number_of_args(name, args, 1)?;
+
+            match &args[0] {
+                Value::Int32(n) if *n >= 0 => Ok(()),
+                Value::Int64(n) if *n >= 0 => Ok(()),
+                Value::Variable(_) => Ok(()),
+                _ => Err(anyhow!("expected a delay in milliseconds")),
+            }
+        }
+        COMMAND_RETURN if args.len() > 1 => Err(anyhow!("{} takes at most one value", name)),
+        _ => Ok(()),
+    }
+}
+
+fn validate_executions(
+    executions: &[ScriptExecution],
+    references: &ScriptReferences,
+    diagnostics: &mut Vec<ScriptDiagnostic>,
+) {
+    for execution in executions {
+        match execution {
+            ScriptExecution::Command(command) => {
+                if let Err(e) = validate_command(command, references) {
+                    diagnostics.push(ScriptDiagnostic::new(command.span, e.to_string()));
+                }
+            }
+            ScriptExecution::Block(block) => {
+                validate_executions(block.get_inner_executions(), references, diagnostics);
+                validate_executions(block.get_else_executions(), references, diagnostics);
+            }
+        }
+    }
+}
+
+/// Recursive-descent parser over the token stream. Statements are line based:
+/// a command with its arguments, or an instruction line ending in `THEN` that
+/// opens a block closed by its own `END` line.
+struct ScriptParser {
+    tokens: Vec<Token>,
+    position: usize,
+    functions: Functions,
+    calls: Vec<(String, usize, Span)>,
+}
+
+impl ScriptParser {
+    fn new(tokens: Vec<Token>) -> Self {
+        ScriptParser {
+            tokens,
+            position: 0,
+            functions: HashMap::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+
+        // The trailing Eof token is never consumed.
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().get_kind() == &TokenKind::Newline {
+            self.next();
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<()> {
+        let token = self.next();
+
+        if !token.is_word(word) {
+            return Err(located_error(
+                token.get_span(),
+                format!("expected {}, found {}", word, token.get_kind()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn expect_end_of_line(&mut self) -> Result<()> {
+        let token = self.next();
+
+        if !token.is_end_of_line() {
+            return Err(located_error(
+                token.get_span(),
+                format!("expected end of line, found {}", token.get_kind()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn take_line(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+
+        while !self.peek().is_end_of_line() {
+            tokens.push(self.next());
+        }
+
+        tokens
+    }
+
+    /// Function definitions may come before or after the `RUN ... STOP` block.
+    fn parse_script(&mut self) -> Result<(Vec<ScriptExecution>, Functions)> {
+        self.parse_functions()?;
+        self.expect_word(MAIN_BLOCK_START)?;
+        self.expect_end_of_line()?;
+
+        let executions = self.parse_statements(&[MAIN_BLOCK_END])?;
+
+        self.expect_word(MAIN_BLOCK_END)?;
+        self.expect_end_of_line()?;
+        self.parse_functions()?;
+
+        let token = self.peek();
+
+        if token.get_kind() != &TokenKind::Eof {
+            return Err(located_error(
+                token.get_span(),
+                format!("unexpected {} after {}", token.get_kind(), MAIN_BLOCK_END),
+            ));
+        }
+
+        self.check_calls()?;
+
+        Ok((executions, std::mem::take(&mut self.functions)))
+    }
+
+    fn parse_functions(&mut self) -> Result<()> {
+        loop {
+            self.skip_newlines();
+
+            let token = self.peek().clone();
+
+            if !token.is_word(FUNCTION_DEFINITION) {
+                return Ok(());
+            }
+
+            self.next();
+            self.parse_function(token.get_span())?;
+        }
+    }
+
+    fn parse_function(&mut self, span: Span) -> Result<()> {
+        let mut tokens = self.take_line();
+        self.expect_end_of_line()?;
+
+        match tokens.last() {
+            Some(token) if token.is_word(INSTRUCTION_BLOCK_START) => {
+                tokens.pop();
+            }
+            _ => {
+                return Err(located_error(
+                    span,
+                    format!(
+                        "expected {} at the end of {}",
+                        INSTRUCTION_BLOCK_START, FUNCTION_DEFINITION
+                    ),
+                ));
+            }
+        }
+
+        let (name, arguments, position) = parse_call_signature(&tokens, span)?;
+
+        if let Some(token) = tokens.get(position) {
+            return Err(located_error(
+                token.get_span(),
+                format!(
+                    "expected {}, found {}",
+                    INSTRUCTION_BLOCK_START,
+                    token.get_kind()
+                ),
+            ));
+        }
+
+        let mut parameters = Vec::new();
+
+        for argument in arguments {
+            match argument {
+                Value::Variable(parameter) if !parameters.contains(&parameter) => {
+                    parameters.push(parameter)
+                }
+                Value::Variable(parameter) => {
+                    return Err(located_error(
+                        span,
+                        format!("duplicate parameter {}", parameter),
+                    ));
+                }
+                _ => {
+                    return Err(located_error(
+                        span,
+                        format!("parameters of {} must be variables", name),
+                    ));
+                }
+            }
+        }
+
+        if self.functions.contains_key(&name) {
+            return Err(located_error(
+                span,
+                format!("function {} is already defined", name),
+            ));
+        }
+
+        let executions = self.parse_statements(&[INSTRUCTION_BLOCK_END])?;
+
+        self.expect_word(INSTRUCTION_BLOCK_END)?;
+        self.expect_end_of_line()?;
+
+        self.functions
+            .insert(name, ScriptFunction::new(parameters, executions));
+
+        Ok(())
+    }
+
+    /// Calls may come before the function they call is defined, so they are
+    /// checked once the whole script has been parsed.
+    fn check_calls(&self) -> Result<()> {
+        for (name, number_of_args, span) in &self.calls {
+            match self.functions.get(name) {
+                Some(function) if function.get_parameters().len() == *number_of_args => {}
+                Some(function) => {
+                    return Err(located_error(
+                        *span,
+                        format!(
+                            "{} expects {} arguments, found {}",
+                            name,
+                            function.get_parameters().len(),
+                            number_of_args
+                        ),
+                    ));
+                }
+                None => {
+                    return Err(located_error(*span, format!("unknown function {}", name)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses statements up to a line starting with one of `terminators`,
+    /// which is left for the caller to consume.
+    fn parse_statements(&mut self, terminators: &[&str]) -> Result<Vec<ScriptExecution>> {
+        let mut executions = Vec::new();
+
+        loop {
+            self.skip_newlines();
+
+            let token = self.peek();
+
+            if terminators
+                .iter()
+                .any(|terminator| token.is_word(terminator))
+            {
+                return Ok(executions);
+            }
+
+            if token.get_kind() == &TokenKind::Eof {
+                return Err(located_error(
+                    token.get_span(),
+                    format!(
+                        "expected {} before the end of the script",
+                        terminators.join(" or ")
+                    ),
+                ));
+            }
+
+            if BLOCK_TERMINATORS
+                .iter()
+                .any(|terminator| token.is_word(terminator))
+            {
+                return Err(located_error(
+                    token.get_span(),
+                    format!(
+                        "expected {}, found {}",
+                        terminators.join(" or "),
+                        token.get_kind()
+                    ),
+                ));
+            }
+
+            executions.push(self.parse_statement()?);
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<ScriptExecution> {
+        let token = self.next();
+        let span = token.get_span();
+
+        let name = match token.get_kind() {
+            TokenKind::Word(name) => name.to_string(),
+            kind => {
+                return Err(located_error(
+                    span,
+                    format!("expected a command, found {}", kind),
+                ));
+            }
+        };
 
-    let i = match instruction.unwrap() {
-        INSTRUCTION_IF => INSTRUCTION_IF,
-        INSTRUCTION_LOOP => INSTRUCTION_LOOP,
-        INSTRUCTION_WHILE_LOOP => INSTRUCTION_WHILE_LOOP,
-        INSTRUCTION_BREAK => INSTRUCTION_BREAK,
-        INSTRUCTION_CONTINUE => INSTRUCTION_CONTINUE,
-        _ => {
-            return Err(anyhow!("Unknown instruction: {}", instruction.unwrap()));
+        if let Some(instruction) = parse_instruction(&name) {
+            return self.parse_block(instruction, span);
         }
-    };
 
-    Ok((
-        i,
-        if !arguments.is_empty() {
-            Some(arguments)
-        } else {
-            None
-        },
-    ))
-}
+        if name == FUNCTION_DEFINITION {
+            return Err(located_error(
+                span,
+                format!("functions must be defined outside {}", MAIN_BLOCK_START),
+            ));
+        }
 
-fn parse_command(s: String) -> Result<(Command, Option<Args>)> {
-    let mut fragments = s.split_whitespace();
+        if name == COMMAND_CALL_FUNCTION {
+            return self.parse_call(span);
+        }
 
-    let command = fragments.next();
-    let arguments = fragments
-        .map(|arg| parse_argument(arg.to_string()))
-        .collect::<Vec<Value>>();
+        let command = match parse_command(&name) {
+            Some(command) => command,
+            None => {
+                return Err(located_error(span, format!("unknown command {}", name)));
+            }
+        };
 
-    let c = match command.unwrap() {
-        COMMAND_ACTIVATE_ACTUATOR => COMMAND_ACTIVATE_ACTUATOR,
-        COMMAND_DEACTIVATE_ACTUATOR => COMMAND_DEACTIVATE_ACTUATOR,
-        COMMAND_PULSE_ACTUATOR => COMMAND_PULSE_ACTUATOR,
-        COMMAND_READ_SENSOR => COMMAND_READ_SENSOR,
-        COMMAND_SEND_MESSAGE_TO_DASHBOARD => COMMAND_SEND_MESSAGE_TO_DASHBOARD,
-        COMMAND_SET_VARIABLE => COMMAND_SET_VARIABLE,
-        COMMAND_UNSET_VARIABLE => COMMAND_UNSET_VARIABLE,
-        COMMAND_ADD_TO_VARIABLE => COMMAND_ADD_TO_VARIABLE,
-        COMMAND_SUBTRACT_FROM_VARIABLE => COMMAND_SUBTRACT_FROM_VARIABLE,
-        COMMAND_MULTIPLY_VARIABLE => COMMAND_MULTIPLY_VARIABLE,
-        COMMAND_DIVIDE_VARIABLE => COMMAND_DIVIDE_VARIABLE,
-        COMMAND_MODULO_VARIABLE => COMMAND_MODULO_VARIABLE,
-        COMMAND_DELAY => COMMAND_DELAY,
-        _ => {
-            return Err(anyhow!("Unknown command: {}", s));
-        }
-    };
+        let tokens = self.take_line();
+        self.expect_end_of_line()?;
 
-    Ok((
-        c,
-        if !arguments.is_empty() {
-            Some(arguments)
+        let arguments = if !tokens.is_empty() {
+            Some(tokens_to_args(&tokens)?)
         } else {
             None
-        },
-    ))
-}
-
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub enum Value {
-    None,
-    Variable(String),
-    Array(Vec<Value>),
-    String(String),
-    Boolean(bool),
-    Float32(f32),
-    Float64(f64),
-    Int32(i32),
-    Int64(i64),
-}
+        };
 
-impl Value {
-    pub fn to_string(&self, variables: &Variables) -> String {
-        match self {
-            Value::None => "".to_string(),
-            Value::String(s) => s.to_string(),
-            Value::Variable(s) => variables.get(s).unwrap().to_string(variables),
-            Value::Int32(n) => n.to_string(),
-            Value::Int64(n) => n.to_string(),
-            Value::Float32(n) => n.to_string(),
-            Value::Float64(n) => n.to_string(),
-            Value::Boolean(b) => b.to_string(),
-            Value::Array(array) => {
-                let mut s = "[".to_string();
-                for value in array {
-                    s.push_str(value.to_string(variables).as_str());
-                    s += ", "
+        if command == COMMAND_SET_VARIABLE || command == COMMAND_SET_GLOBAL_VARIABLE {
+            if let Some((_, expression)) = split_assignment(&arguments) {
+                if let Err(e) = parse_expression(expression) {
+                    return Err(located_error(span, e.to_string()));
                 }
-                s += "]";
-                s
             }
         }
+
+        Ok(ScriptExecution::Command(ScriptCommand::new(
+            command, arguments, span,
+        )))
     }
-}
 
-pub type Args = Vec<Value>;
+    /// `CALL name(arg, ...)`, optionally followed by `INTO $variable` to keep
+    /// the returned value.
+    fn parse_call(&mut self, span: Span) -> Result<ScriptExecution> {
+        let tokens = self.take_line();
+        self.expect_end_of_line()?;
 
-pub type Variables = HashMap<String, Value>;
+        let (name, call_args, position) = parse_call_signature(&tokens, span)?;
 
-type CommandFunction =
-    Box<dyn Fn(&Option<Args>, &mut Variables, &SocketRef) -> CommandFunctionResult>;
-type InstructionFunction = Box<
-    dyn Fn(
-        &Option<Args>,
-        &Vec<ScriptExecution>,
-        &mut Variables,
-        &SocketRef,
-    ) -> CommandFunctionResult,
->;
+        self.calls.push((name.clone(), call_args.len(), span));
 
-pub enum CommandFunctionResult {
-    Error(String),
-    SaveVariable(String, Value),
-    Return(Value),
-    Continue,
-    Break,
-}
+        let mut arguments = vec![Value::String(name), Value::Array(call_args)];
 
-impl CommandFunctionResult {
-    fn is_save_variable(&self) -> bool {
-        matches!(self, CommandFunctionResult::SaveVariable(_, _))
-    }
+        match &tokens[position..] {
+            [] => {}
+            [into, variable] if into.is_word(CALL_RESULT_INTO) => match variable.get_kind() {
+                TokenKind::Variable(v) => arguments.push(Value::Variable(v.to_string())),
+                kind => {
+                    return Err(located_error(
+                        variable.get_span(),
+                        format!("expected a variable, found {}", kind),
+                    ));
+                }
+            },
+            [token, ..] => {
+                return Err(located_error(
+                    token.get_span(),
+                    format!(
+                        "expected {} or end of line, found {}",
+                        CALL_RESULT_INTO,
+                        token.get_kind()
+                    ),
+                ));
+            }
+        }
 
-    fn is_error(&self) -> bool {
-        matches!(self, CommandFunctionResult::Error(_))
+        Ok(ScriptExecution::Command(ScriptCommand::new(
+            COMMAND_CALL_FUNCTION,
+            Some(arguments),
+            span,
+        )))
     }
 
-    fn is_return(&self) -> bool {
-        matches!(self, CommandFunctionResult::Return(_))
-    }
+    fn parse_block(&mut self, instruction: Instruction, span: Span) -> Result<ScriptExecution> {
+        let mut tokens = self.take_line();
+        self.expect_end_of_line()?;
 
-    fn is_break(&self) -> bool {
-        matches!(self, CommandFunctionResult::Break)
-    }
-}
+        if instruction == INSTRUCTION_BREAK || instruction == INSTRUCTION_CONTINUE {
+            if !tokens.is_empty() {
+                return Err(located_error(
+                    span,
+                    format!("{} takes no arguments", instruction),
+                ));
+            }
 
-struct ScriptCommand {
-    function: CommandFunction,
-    arguments: Option<Args>,
-}
+            return Ok(ScriptExecution::Block(ScriptBlock::new(
+                instruction,
+                None,
+                Vec::new(),
+                Vec::new(),
+                span,
+            )));
+        }
 
-impl ScriptCommand {
-    fn new(fragment: String) -> Result<ScriptCommand> {
-        let (command, arguments) = parse_command(fragment)?;
+        match tokens.last() {
+            Some(token) if token.is_word(INSTRUCTION_BLOCK_START) => {
+                tokens.pop();
+            }
+            _ => {
+                return Err(located_error(
+                    span,
+                    format!(
+                        "expected {} at the end of {}",
+                        INSTRUCTION_BLOCK_START, instruction
+                    ),
+                ));
+            }
+        }
 
-        Ok(ScriptCommand {
-            arguments: match &arguments {
-                Some(args) if !args.is_empty() => Some(args.clone()),
-                _ => None,
-            },
-            function: parse_command_function(command),
-        })
-    }
+        let arguments = tokens_to_args(&tokens)?;
 
-    fn get_arguments(&self) -> &Option<Args> {
-        &self.arguments
-    }
+        if instruction == INSTRUCTION_LOOP {
+            if !arguments.is_empty() {
+                return Err(located_error(
+                    span,
+                    format!("{} takes no arguments", instruction),
+                ));
+            }
+        } else if arguments.is_empty() {
+            return Err(located_error(
+                span,
+                format!("{} requires a condition", instruction),
+            ));
+        } else if let Err(e) = parse_condition(arguments.clone(), &HashMap::new(), &Local::now()) {
+            return Err(located_error(span, e.to_string()));
+        }
 
-    fn get_function(&self) -> &CommandFunction {
-        &self.function
-    }
+        let arguments = if !arguments.is_empty() {
+            Some(arguments)
+        } else {
+            None
+        };
 
-    fn execute(&self, variables: &mut Variables, socket: &SocketRef) -> CommandFunctionResult {
-        self.get_function()(self.get_arguments(), variables, socket)
-    }
-}
+        if instruction == INSTRUCTION_IF {
+            return self.parse_if_branches(arguments, span);
+        }
 
-struct ScriptBlock {
-    function: InstructionFunction,
-    arguments: Option<Args>,
-    inner_executions: Vec<ScriptExecution>,
-}
+        let inner_executions = self.parse_statements(&[INSTRUCTION_BLOCK_END])?;
 
-impl ScriptBlock {
-    fn new(
-        instruction_fragment: String,
-        inner_executions: Vec<ScriptExecution>,
-    ) -> Result<ScriptBlock> {
-        let (instruction, arguments) = parse_instruction(instruction_fragment)?;
+        self.expect_word(INSTRUCTION_BLOCK_END)?;
+        self.expect_end_of_line()?;
 
-        Ok(ScriptBlock {
+        Ok(ScriptExecution::Block(ScriptBlock::new(
+            instruction,
             arguments,
-            function: parse_instruction_function(instruction),
             inner_executions,
-        })
+            Vec::new(),
+            span,
+        )))
     }
 
-    fn get_arguments(&self) -> &Option<Args> {
-        &self.arguments
-    }
+    /// Parses the body of an `IF` after its condition line. `ELSE IF` becomes a
+    /// nested `IF` in the else branch, and the single closing `END` of the
+    /// whole chain is consumed by the innermost branch.
+    fn parse_if_branches(
+        &mut self,
+        arguments: Option<Args>,
+        span: Span,
+    ) -> Result<ScriptExecution> {
+        let inner_executions = self.parse_statements(&[INSTRUCTION_BLOCK_END, INSTRUCTION_ELSE])?;
 
-    fn get_function(&self) -> &InstructionFunction {
-        &self.function
-    }
+        let else_executions = if self.peek().is_word(INSTRUCTION_ELSE) {
+            self.next();
 
-    fn get_inner_executions(&self) -> &Vec<ScriptExecution> {
-        &self.inner_executions
-    }
+            let token = self.peek().clone();
 
-    fn execute(&self, variables: &mut Variables, socket: &SocketRef) -> CommandFunctionResult {
-        self.get_function()(
-            self.get_arguments(),
-            self.get_inner_executions(),
-            variables,
-            socket,
-        )
+            if token.is_word(INSTRUCTION_IF) {
+                self.next();
+                vec![self.parse_block(INSTRUCTION_IF, token.get_span())?]
+            } else {
+                self.expect_end_of_line()?;
+
+                let else_executions = self.parse_statements(&[INSTRUCTION_BLOCK_END])?;
+
+                self.expect_word(INSTRUCTION_BLOCK_END)?;
+                self.expect_end_of_line()?;
+
+                else_executions
+            }
+        } else {
+            self.expect_word(INSTRUCTION_BLOCK_END)?;
+            self.expect_end_of_line()?;
+
+            Vec::new()
+        };
+
+        Ok(ScriptExecution::Block(ScriptBlock::new(
+            INSTRUCTION_IF,
+            arguments,
+            inner_executions,
+            else_executions,
+            span,
+        )))
     }
 }
 
-enum ScriptExecution {
-    Command(ScriptCommand),
-    Block(ScriptBlock),
+pub struct ScriptContext {
+    target: DashboardTarget,
+    output: Arc<Mutex<Vec<String>>>,
+    cancel_token: CancellationToken,
+    functions: Arc<Functions>,
+    depth: usize,
+    simulation: Option<Arc<ScriptSimulation>>,
+    debugger: Option<Arc<ScriptDebugger>>,
+    span: Mutex<Span>,
 }
 
-fn remove_tabs_and_multiple_whitespace(s: String) -> String {
-    let regex = Regex::new(r"\s+").unwrap();
-    regex
-        .replace_all(s.replace("\t", " ").as_str(), " ")
-        .to_string()
-}
+impl ScriptContext {
+    pub fn new(target: DashboardTarget, cancel_token: CancellationToken) -> Self {
+        Self {
+            target,
+            output: Arc::new(Mutex::new(Vec::new())),
+            cancel_token,
+            functions: Arc::new(HashMap::new()),
+            depth: 0,
+            simulation: None,
+            debugger: None,
+            span: Mutex::new(Span::default()),
+        }
+    }
+
+    /// Context for a function body or a called script: one call deeper, with
+    /// the same target, output and cancellation as the caller.
+    fn nested(&self, functions: Arc<Functions>) -> Result<ScriptContext> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(anyhow!("Maximum call depth of {} exceeded", MAX_CALL_DEPTH));
+        }
 
-fn parse_commands(fragment: String) -> Result<Vec<ScriptExecution>> {
-    let splitted = fragment.split(COMMAND_END);
+        Ok(Self {
+            target: self.target.clone(),
+            output: self.output.clone(),
+            cancel_token: self.cancel_token.clone(),
+            functions,
+            depth: self.depth + 1,
+            simulation: self.simulation.clone(),
+            debugger: self.debugger.clone(),
+            span: Mutex::new(Span::default()),
+        })
+    }
 
-    let mut vec = Vec::new();
+    /// Makes this a dry run: devices are simulated and every command that
+    /// would touch them is traced instead.
+    pub fn set_simulation(&mut self, simulation: Arc<ScriptSimulation>) {
+        self.simulation = Some(simulation);
+    }
 
-    for instruction in splitted {
-        let c = ScriptCommand::new(instruction.to_owned());
+    pub fn get_simulation(&self) -> Option<&ScriptSimulation> {
+        self.simulation.as_deref()
+    }
 
-        let c = match c {
-            Ok(res) => res,
-            Err(e) => {
-                return Err(e);
-            }
-        };
+    /// Time seen by `$now` and the other time variables, which follows the
+    /// simulated clock during a dry run.
+    pub fn get_now(&self) -> DateTime<Local> {
+        match self.get_simulation() {
+            Some(simulation) => simulation.get_now(),
+            None => Local::now(),
+        }
+    }
 
-        vec.push(ScriptExecution::Command(c));
+    /// Lets the dashboard debugger pause the run before each node.
+    pub fn set_debugger(&mut self, debugger: Arc<ScriptDebugger>) {
+        self.debugger = Some(debugger);
     }
 
-    Ok(vec)
-}
+    pub fn get_debugger(&self) -> Option<&ScriptDebugger> {
+        self.debugger.as_deref()
+    }
 
-fn parse_block(fragment: String) -> Result<Vec<ScriptExecution>> {
-    let start_index = fragment.find(INSTRUCTION_BLOCK_START).unwrap();
-    let end_index = fragment.rfind(INSTRUCTION_BLOCK_END).unwrap();
+    /// Position of the node being executed.
+    pub fn get_span(&self) -> Span {
+        *self.span.lock().unwrap()
+    }
 
-    let inner_executions = parse_execution_step(
-        fragment[start_index + INSTRUCTION_BLOCK_START.len()..end_index].to_string(),
-    );
+    fn set_span(&self, span: Span) {
+        *self.span.lock().unwrap() = span;
+    }
 
-    let inner_executions = match inner_executions {
-        Ok(res) => res,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+    fn get_functions(&self) -> Arc<Functions> {
+        self.functions.clone()
+    }
 
-    let s = ScriptBlock::new(fragment[0..start_index].to_string(), inner_executions);
+    fn get_function(&self, name: &str) -> Option<&ScriptFunction> {
+        self.functions.get(name)
+    }
 
-    let s = match s {
-        Ok(res) => res,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+    pub fn get_target(&self) -> &DashboardTarget {
+        &self.target
+    }
 
-    Ok(vec![ScriptExecution::Block(s)])
-}
+    pub fn get_cancel_token(&self) -> &CancellationToken {
+        &self.cancel_token
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_token.is_cancelled()
+    }
 
-fn parse_execution_step(fragment: String) -> Result<Vec<ScriptExecution>> {
-    if fragment.contains(INSTRUCTION_BLOCK_START) && fragment.contains(INSTRUCTION_BLOCK_END) {
-        return parse_block(fragment);
+    pub fn push_output(&self, message: String) {
+        self.output.lock().unwrap().push(message);
     }
 
-    parse_commands(fragment)
+    pub fn get_output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
 }
 
 pub struct Script {
     id: i32,
     executions: Vec<ScriptExecution>,
+    functions: Arc<Functions>,
 }
 
 impl Script {
@@ -997,25 +2538,18 @@ impl Script {
             }
         };
 
-        let script = script_model.get_code().to_string();
-
-        if !script.contains(MAIN_BLOCK_START) || !script.contains(MAIN_BLOCK_END) {
-            return Err(Error::msg("Script does not contain main block"));
-        }
-
-        let minified_script = remove_tabs_and_multiple_whitespace(script);
+        Script::parse_code(script_id, script_model.get_code())
+    }
 
-        let main_block = minified_script
-            .replace(MAIN_BLOCK_START, "")
-            .replace(MAIN_BLOCK_END, "")
-            .trim()
-            .to_string();
+    pub fn parse_code(script_id: i32, code: &str) -> Result<Script> {
+        let tokens = tokenize(code)?;
 
-        let executions = parse_execution_step(main_block)?;
+        let (executions, functions) = ScriptParser::new(tokens).parse_script()?;
 
         Ok(Script {
             id: script_id,
             executions,
+            functions: Arc::new(functions),
         })
     }
 
@@ -1023,14 +2557,44 @@ impl Script {
         self.id
     }
 
-    pub fn run(&self, socket: &SocketRef) -> Result<CommandFunctionResult> {
+    fn get_functions(&self) -> Arc<Functions> {
+        self.functions.clone()
+    }
+
+    /// Checks what parsing alone does not: argument counts and types, and
+    /// that the sensors, actuators and scripts it refers to exist.
+    pub fn validate(&self, references: &ScriptReferences) -> Vec<ScriptDiagnostic> {
+        let mut diagnostics = Vec::new();
+
+        validate_executions(&self.executions, references, &mut diagnostics);
+
+        for function in self.functions.values() {
+            validate_executions(function.get_executions(), references, &mut diagnostics);
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.get_span());
+
+        diagnostics
+    }
+
+    pub async fn run(&self, context: &ScriptContext) -> Result<CommandFunctionResult> {
+        let context = context.nested(self.get_functions())?;
+
         let mut variables: Variables = HashMap::new();
 
-        let res = run_inner_executions(&self.executions, &mut variables, socket);
+        let res = run_inner_executions(&self.executions, &mut variables, &context).await;
+
+        if res.is_cancelled() {
+            return Ok(res);
+        }
 
         if res.is_error() {
             return Err(Error::msg(match res {
                 CommandFunctionResult::Error(e) => e,
+                CommandFunctionResult::LocatedError(span, e) => format!("{}: {}", span, e),
+                CommandFunctionResult::ScriptError(script_id, span, e) => {
+                    format!("script {}, {}: {}", script_id, span, e)
+                }
                 _ => {
                     return Err(Error::msg("Unknown error".to_string()));
                 }
@@ -1040,3 +2604,493 @@ impl Script {
         Ok(CommandFunctionResult::Return(Value::None))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use socketioxide::extract::SocketRef;
+    use socketioxide::SocketIo;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_delay_does_not_block_other_runs() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        let context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+
+        let executions = Script::parse_code(0, "RUN\nDELAY 200\nSTOP")
+            .unwrap()
+            .executions;
+
+        let mut first_variables: Variables = HashMap::new();
+        let mut second_variables: Variables = HashMap::new();
+
+        let start = Instant::now();
+
+        let (first, second) = tokio::join!(
+            run_inner_executions(&executions, &mut first_variables, &context),
+            run_inner_executions(&executions, &mut second_variables, &context),
+        );
+
+        assert!(!first.is_error() && !second.is_error());
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_interrupts_delay() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        let cancel_token = CancellationToken::new();
+        let context = ScriptContext::new(DashboardTarget::All(io), cancel_token.clone());
+
+        let executions = Script::parse_code(0, "RUN\nDELAY 60000\nSTOP")
+            .unwrap()
+            .executions;
+        let mut variables: Variables = HashMap::new();
+
+        let start = Instant::now();
+
+        let (res, _) = tokio::join!(
+            run_inner_executions(&executions, &mut variables, &context),
+            async {
+                sleep(Duration::from_millis(50)).await;
+                cancel_token.cancel();
+            },
+        );
+
+        assert!(res.is_cancelled());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_parse_and_run_nested_blocks() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+RUN
+    # count to three
+    SET $a 0
+    LOOP THEN
+        ADD $a 1
+        IF $a >= 3 THEN
+            BREAK
+        END
+    END
+    IF $a == 3 && ($a > 1 || $a < 0) THEN
+        SEND_TO_DASHBOARD "counted to $a, the END"
+    END
+    IF $a != 3 THEN
+        SEND_TO_DASHBOARD "unreachable"
+    END
+STOP
+"#,
+        )
+        .unwrap();
+
+        assert!(script.run(&context).await.is_ok());
+        assert_eq!(context.get_output(), vec!["counted to 3, the END"]);
+    }
+
+    #[test]
+    fn test_parse_errors_have_locations() {
+        let error = |code: &str| Script::parse_code(0, code).err().unwrap().to_string();
+
+        assert_eq!(
+            error("RUN\nIF $a > 1 THEN\n    DELAY 5\nSTOP"),
+            "line 4, column 1: expected END or ELSE, found STOP"
+        );
+        assert_eq!(
+            error("RUN\n  BLINK 3\nSTOP"),
+            "line 2, column 3: unknown command BLINK"
+        );
+        assert_eq!(
+            error("RUN\nWHILE $a > THEN\nEND\nSTOP"),
+            "line 2, column 1: Incomplete condition"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_else_if_chain() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        for (temperature, expected) in [(35, "open"), (25, "half"), (10, "close")] {
+            let context =
+                ScriptContext::new(DashboardTarget::All(io.clone()), CancellationToken::new());
+
+            let script = Script::parse_code(
+                0,
+                &format!(
+                    r#"
+RUN
+    SET $t {}
+    IF $t > 30 THEN
+        SEND_TO_DASHBOARD "open"
+    ELSE IF $t > 20 THEN
+        IF $t > 100 THEN
+            SEND_TO_DASHBOARD "unreachable"
+        ELSE
+            SEND_TO_DASHBOARD "half"
+        END
+    ELSE
+        SEND_TO_DASHBOARD "close"
+    END
+STOP
+"#,
+                    temperature
+                ),
+            )
+            .unwrap();
+
+            assert!(script.run(&context).await.is_ok());
+            assert_eq!(context.get_output(), vec![expected]);
+        }
+
+        let error = Script::parse_code(0, "RUN\nLOOP THEN\nELSE\nEND\nSTOP")
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 3, column 1: expected END, found ELSE"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_time_literals() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        for (time, expected) in [("05:59", "off"), ("06:00", "water"), ("07:59:30", "water")] {
+            let context =
+                ScriptContext::new(DashboardTarget::All(io.clone()), CancellationToken::new());
+
+            let script = Script::parse_code(
+                0,
+                &format!(
+                    r#"
+RUN
+    SET $t {}
+    IF $t >= 06:00 && $t < 08:00 THEN
+        SEND_TO_DASHBOARD "water"
+    ELSE
+        SEND_TO_DASHBOARD "off"
+    END
+STOP
+"#,
+                    time
+                ),
+            )
+            .unwrap();
+
+            assert!(script.run(&context).await.is_ok());
+            assert_eq!(context.get_output(), vec![expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_functions() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+FUNCTION larger($a, $b) THEN
+    IF $a > $b THEN
+        RETURN $a
+    END
+    RETURN $b
+END
+
+RUN
+    SET $a 3
+    CALL larger($a, 7) INTO $max
+    CALL report($max)
+    SEND_TO_DASHBOARD "a is still $a"
+STOP
+
+FUNCTION report($value) THEN
+    SEND_TO_DASHBOARD "max is $value"
+END
+"#,
+        )
+        .unwrap();
+
+        assert!(script.run(&context).await.is_ok());
+        assert_eq!(context.get_output(), vec!["max is 7", "a is still 3"]);
+
+        let script = Script::parse_code(
+            0,
+            "FUNCTION forever() THEN
+    CALL forever()
+END
+RUN
+CALL forever()
+STOP",
+        )
+        .unwrap();
+
+        let error = script.run(&context).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 2, column 5: Maximum call depth of 16 exceeded"
+        );
+
+        let error = |code: &str| Script::parse_code(0, code).err().unwrap().to_string();
+
+        assert_eq!(
+            error(
+                "RUN
+CALL missing(1)
+STOP"
+            ),
+            "line 2, column 1: unknown function missing"
+        );
+        assert_eq!(
+            error(
+                "FUNCTION f($a) THEN
+END
+RUN
+CALL f(1, 2)
+STOP"
+            ),
+            "line 4, column 1: f expects 1 arguments, found 2"
+        );
+        assert_eq!(
+            error(
+                "RUN
+FUNCTION f() THEN
+END
+STOP"
+            ),
+            "line 2, column 1: functions must be defined outside RUN"
+        );
+    }
+
+    #[test]
+    fn test_validate_script() {
+        let references =
+            ScriptReferences::new(HashSet::from([1]), HashSet::from([2]), HashSet::from([3]));
+
+        let script = Script::parse_code(
+            0,
+            r#"
+FUNCTION toggle($id) THEN
+    ACTIVATE $id
+    DEACTIVATE 5
+END
+
+RUN
+    READ 1
+    IF $sensor_id_1 > 20 THEN
+        ACTIVATE 2 3
+    ELSE
+        CALL_SCRIPT 4
+    END
+    SET $a = $a + 1
+    ADD $a "one"
+    DELAY -5
+    CALL toggle(2)
+STOP
+"#,
+        )
+        .unwrap();
+
+        let diagnostics = script
+            .validate(&references)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "line 4, column 5: actuator 5 does not exist",
+                "line 10, column 9: ACTIVATE takes 1 argument, found 2",
+                "line 12, column 9: script 4 does not exist",
+                "line 15, column 5: expected a number",
+                "line 16, column 5: expected a delay in milliseconds",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_simulates_devices() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let simulation = Arc::new(ScriptSimulation::new(HashMap::from([(
+            1,
+            "25".to_string(),
+        )])));
+
+        let mut context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+        context.set_simulation(simulation.clone());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+RUN
+    READ 1
+    IF 1 == 1 THEN
+        ACTIVATE 2
+        DELAY 60000
+        DEACTIVATE 2
+    END
+    SEND_TO_DASHBOARD "read $sensor_id_1"
+STOP
+"#,
+        )
+        .unwrap();
+
+        let start = Instant::now();
+
+        assert!(script.run(&context).await.is_ok());
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let trace = simulation
+            .get_trace()
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{} {} {} {}",
+                    entry.get_line(),
+                    entry.get_elapsed_ms(),
+                    entry.get_command(),
+                    entry.get_detail()
+                )
+            })
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            trace,
+            vec![
+                "3 0 READ sensor 1 = 25",
+                "5 0 ACTIVATE actuator 2",
+                "6 0 DELAY 60000 ms",
+                "7 60000 DEACTIVATE actuator 2",
+                "9 60000 SEND_TO_DASHBOARD read 25",
+            ]
+        );
+        assert_eq!(simulation.get_elapsed_ms(), 60000);
+        assert_eq!(context.get_output(), vec!["read 25"]);
+    }
+
+    #[tokio::test]
+    async fn test_global_variables_in_dry_run() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let simulation = Arc::new(ScriptSimulation::new(HashMap::new()));
+
+        let mut context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+        context.set_simulation(simulation.clone());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+RUN
+    SET $count 3
+    SET_GLOBAL $count = $count + 1
+    UNSET $count
+    GET_GLOBAL $count
+    SEND_TO_DASHBOARD "$count"
+STOP
+"#,
+        )
+        .unwrap();
+
+        assert!(script.run(&context).await.is_ok());
+        assert_eq!(context.get_output(), vec!["4"]);
+        assert_eq!(simulation.get_trace()[0].get_detail(), "$count = 4");
+
+        let json = serde_json::json!([1, 3000000000_i64, 1.5, "on", null]);
+        assert_eq!(
+            Value::from_json(json.clone()).unwrap(),
+            Value::Array(vec![
+                Value::Int32(1),
+                Value::Int64(3000000000),
+                Value::Float64(1.5),
+                Value::String("on".to_string()),
+                Value::None,
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(Value::from_json(json.clone()).unwrap()).unwrap(),
+            json
+        );
+    }
+
+    #[tokio::test]
+    async fn test_time_variables_in_dry_run() {
+        let (_layer, io) = SocketIo::builder().build_layer();
+        io.ns("/", |_socket: SocketRef| {});
+
+        let simulation = Arc::new(ScriptSimulation::new(HashMap::new()));
+
+        let mut context = ScriptContext::new(DashboardTarget::All(io), CancellationToken::new());
+        context.set_simulation(simulation.clone());
+
+        let script = Script::parse_code(
+            0,
+            r#"
+RUN
+    SEND_TO_DASHBOARD "$hour"
+    DELAY 7200000
+    SEND_TO_DASHBOARD "$hour"
+STOP
+"#,
+        )
+        .unwrap();
+
+        assert!(script.run(&context).await.is_ok());
+
+        let hours: Vec<u32> = context
+            .get_output()
+            .iter()
+            .map(|hour| hour.parse().unwrap())
+            .collect();
+        assert_eq!(hours[1], (hours[0] + 2) % 24);
+    }
+
+    #[test]
+    fn test_summarize_sensor_values() {
+        let sensor_values = [Some(69.0), Some(71.5), None, Some(74.0)];
+
+        let summary = |command| summarize_sensor_values(&sensor_values, command).unwrap();
+
+        assert_eq!(summary(COMMAND_READ_SENSOR_AVERAGE), Value::Float64(71.5));
+        assert_eq!(summary(COMMAND_READ_SENSOR_MINIMUM), Value::Float64(69.0));
+        assert_eq!(summary(COMMAND_READ_SENSOR_MAXIMUM), Value::Float64(74.0));
+        assert_eq!(summary(COMMAND_READ_SENSOR_COUNT), Value::Int64(4));
+
+        assert_eq!(
+            summarize_sensor_values(&[], COMMAND_READ_SENSOR_AVERAGE).unwrap(),
+            Value::None
+        );
+
+        let references = ScriptReferences::new(HashSet::from([1]), HashSet::new(), HashSet::new());
+        let script = Script::parse_code(
+            0,
+            "RUN\nREAD_AVG 1 30 $humidity\nREAD_MAX 1 0 $max\nREAD_LAST 2\nSTOP",
+        )
+        .unwrap();
+
+        assert_eq!(
+            script
+                .validate(&references)
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "line 3, column 1: expected a number of minutes",
+                "line 4, column 1: sensor 2 does not exist",
+            ]
+        );
+    }
+}
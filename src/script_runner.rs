@@ -0,0 +1,878 @@
+use crate::condition_parser::parse_condition;
+use crate::events::{
+    SCRIPT_DEBUG_FINISHED_EVENT, SCRIPT_DRY_RUN_EVENT, SCRIPT_STATUS_CHANGE_EVENT,
+};
+use crate::helper::{invalid_input, DashboardMessageType, DashboardTarget};
+use crate::models::{ScriptRule, SensorRead};
+use crate::protocol::{
+    ScriptDebugFinished, ScriptDebugState, ScriptDryRunFinished, ScriptStatusChanged,
+};
+use crate::rule_methods::{get_enabled_script_rules_for_sensor, update_script_rule_match};
+use crate::script_debugger::ScriptDebugger;
+use crate::script_methods::{
+    finish_script_run, get_scheduled_scripts, start_script_run, take_schedules_changed,
+    update_script_status, SCRIPT_RUN_OUTCOME_CANCELLED, SCRIPT_RUN_OUTCOME_ERROR,
+    SCRIPT_RUN_OUTCOME_SUCCESS, SCRIPT_STATUS_CANCELLED, SCRIPT_STATUS_ERROR, SCRIPT_STATUS_IDLE,
+    SCRIPT_STATUS_RUNNING, SCRIPT_TRIGGER_DEBUG, SCRIPT_TRIGGER_EVENT, SCRIPT_TRIGGER_SCHEDULE,
+};
+use crate::script_parser::{
+    parse_arguments, CommandFunctionResult, Script, ScriptContext, Value, Variables,
+};
+use crate::script_simulation::{ScriptDryRun, ScriptSimulation};
+use anyhow::{Error, Result};
+use chrono::{DateTime, Local, NaiveDateTime};
+use regex::Regex;
+use socketioxide::SocketIo;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle as TaskHandle;
+use tokio_util::sync::CancellationToken;
+
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+/// Schedules are reloaded when a script changes, and at this interval to pick
+/// up changes made outside the hub.
+const SCHEDULE_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static SCRIPT_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+type RunningScripts = Mutex<HashMap<i32, HashMap<u64, CancellationToken>>>;
+
+static RUNNING_SCRIPTS: OnceLock<RunningScripts> = OnceLock::new();
+static NEXT_RUN_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// A debug run and the dashboard that started it, so the run can be
+/// cancelled when that dashboard goes away.
+struct DebugSession {
+    debugger: Arc<ScriptDebugger>,
+    socket_id: Option<String>,
+    cancel_token: CancellationToken,
+}
+
+static DEBUG_SESSIONS: OnceLock<Mutex<HashMap<i32, DebugSession>>> = OnceLock::new();
+
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// A standard cron weekday, `0`-`7` from Sunday (both `0` and `7` are Sunday)
+/// or a day name, as a number `0`-`7`.
+fn parse_cron_weekday(weekday: &str) -> Option<usize> {
+    match weekday.parse::<usize>() {
+        Ok(weekday) if weekday <= 7 => Some(weekday),
+        Ok(_) => None,
+        Err(_) => WEEKDAY_NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(weekday)),
+    }
+}
+
+/// Rewrites the day-of-week field of a standard cron expression with day
+/// names. The cron crate numbers weekdays `1`-`7` from Sunday, so numbers
+/// cannot be passed through.
+fn translate_cron_weekdays(field: &str) -> Result<String> {
+    if field == "*" || field == "?" {
+        return Ok(field.to_string());
+    }
+
+    let invalid = || invalid_input(&format!("Invalid day of week '{}'", field));
+    let mut weekdays = vec![];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<usize>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(invalid()),
+            },
+            None => (part, None),
+        };
+
+        let (first, last) = match (range, range.split_once('-')) {
+            ("*", _) => (0, 6),
+            (_, Some((first, last))) => (
+                parse_cron_weekday(first).ok_or_else(invalid)?,
+                parse_cron_weekday(last).ok_or_else(invalid)?,
+            ),
+            (weekday, None) => {
+                let weekday = parse_cron_weekday(weekday).ok_or_else(invalid)?;
+
+                // `1/2` runs from Monday to the end of the week.
+                (weekday, if step.is_some() { 6 } else { weekday })
+            }
+        };
+
+        if first > last {
+            return Err(invalid());
+        }
+
+        weekdays.extend(
+            (first..=last)
+                .step_by(step.unwrap_or(1))
+                .map(|weekday| weekday % 7),
+        );
+    }
+
+    weekdays.sort();
+    weekdays.dedup();
+
+    Ok(weekdays
+        .iter()
+        .map(|weekday| WEEKDAY_NAMES[*weekday])
+        .collect::<Vec<&str>>()
+        .join(","))
+}
+
+pub enum ScriptSchedule {
+    Cron(Box<cron::Schedule>),
+    Every(chrono::Duration),
+}
+
+impl ScriptSchedule {
+    /// Accepts standard 5-field cron expressions (`*/5 * * * *`, weekdays `0`-`7`
+    /// from Sunday), the cron crate's 6/7-field form with seconds (weekdays
+    /// `1`-`7` from Sunday), `@hourly`-style shortcuts and `every N minutes`.
+    pub fn parse(schedule: &str) -> Result<ScriptSchedule> {
+        let schedule = schedule.trim();
+
+        let every_regex = Regex::new(r"(?i)^every\s+(\d+\s+)?(second|minute|hour|day)s?$").unwrap();
+
+        if let Some(captures) = every_regex.captures(schedule) {
+            let amount = match captures.get(1) {
+                Some(amount) => amount
+                    .as_str()
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| invalid_input(&format!("Invalid schedule '{}'", schedule)))?,
+                None => 1,
+            };
+
+            if amount <= 0 {
+                return Err(invalid_input("Schedule interval must be greater than zero"));
+            }
+
+            let interval = match captures[2].to_lowercase().as_str() {
+                "second" => chrono::Duration::seconds(amount),
+                "minute" => chrono::Duration::minutes(amount),
+                "hour" => chrono::Duration::hours(amount),
+                _ => chrono::Duration::days(amount),
+            };
+
+            return Ok(ScriptSchedule::Every(interval));
+        }
+
+        let fields = schedule.split_whitespace().collect::<Vec<&str>>();
+
+        let expression = if fields.len() == 5 {
+            format!(
+                "0 {} {}",
+                fields[..4].join(" "),
+                translate_cron_weekdays(fields[4])?
+            )
+        } else {
+            schedule.to_string()
+        };
+
+        match cron::Schedule::from_str(&expression) {
+            Ok(cron_schedule) => Ok(ScriptSchedule::Cron(Box::new(cron_schedule))),
+            Err(e) => Err(invalid_input(&format!(
+                "Invalid schedule '{}': {}",
+                schedule, e
+            ))),
+        }
+    }
+
+    pub fn next_run_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            ScriptSchedule::Cron(cron_schedule) => cron_schedule.after(after).next(),
+            ScriptSchedule::Every(interval) => Some(*after + *interval),
+        }
+    }
+}
+
+struct ScheduledScript {
+    schedule: String,
+    parsed_schedule: ScriptSchedule,
+    next_run: Option<DateTime<Local>>,
+}
+
+fn set_script_status(target: &DashboardTarget, script_id: i32, status: i32) {
+    if let Err(e) = update_script_status(script_id, status) {
+        println!("Error updating script status: {:?}", e);
+    }
+
+    target.emit_to_all(
+        SCRIPT_STATUS_CHANGE_EVENT,
+        ScriptStatusChanged::new(script_id, status),
+    );
+}
+
+async fn execute_script(script_id: i32, context: &ScriptContext) -> Result<CommandFunctionResult> {
+    let target = context.get_target();
+
+    let script = match Script::parse(script_id) {
+        Ok(script) => script,
+        Err(e) => {
+            let _: Result<()> = target.send_message(
+                format!("Error parsing script: {:?}", e).to_string(),
+                DashboardMessageType::Error,
+            );
+            return Err(e);
+        }
+    };
+
+    set_script_status(target, script.get_id(), SCRIPT_STATUS_RUNNING);
+
+    match script.run(context).await {
+        Ok(res) if res.is_cancelled() => {
+            set_script_status(target, script.get_id(), SCRIPT_STATUS_CANCELLED);
+            Ok(res)
+        }
+        Ok(res) => {
+            set_script_status(target, script.get_id(), SCRIPT_STATUS_IDLE);
+            Ok(res)
+        }
+        Err(e) => {
+            set_script_status(target, script.get_id(), SCRIPT_STATUS_ERROR);
+
+            match target.send_message(
+                format!("Error running script: {:?}", e).to_string(),
+                DashboardMessageType::Error,
+            ) {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Error sending message to dashboard: {:?}", e);
+                }
+            };
+
+            Err(e)
+        }
+    }
+}
+
+fn script_runtime() -> &'static Runtime {
+    SCRIPT_RUNTIME.get_or_init(|| Runtime::new().expect("Failed to create Tokio runtime"))
+}
+
+fn running_scripts() -> &'static RunningScripts {
+    RUNNING_SCRIPTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn register_running_script(script_id: i32, cancel_token: CancellationToken) -> u64 {
+    let run_key = NEXT_RUN_KEY.fetch_add(1, Ordering::Relaxed);
+
+    running_scripts()
+        .lock()
+        .unwrap()
+        .entry(script_id)
+        .or_default()
+        .insert(run_key, cancel_token);
+
+    run_key
+}
+
+fn unregister_running_script(script_id: i32, run_key: u64) {
+    let mut running = running_scripts().lock().unwrap();
+
+    if let Some(runs) = running.get_mut(&script_id) {
+        runs.remove(&run_key);
+
+        if runs.is_empty() {
+            running.remove(&script_id);
+        }
+    }
+}
+
+/// Cancels every in-flight run of the script. The interpreter notices between
+/// commands and while sleeping in `DELAY`, then marks the script cancelled.
+/// Returns false when the script was not running.
+pub fn stop_script(script_id: i32) -> bool {
+    match running_scripts().lock().unwrap().get(&script_id) {
+        Some(runs) => {
+            runs.values().for_each(|cancel_token| cancel_token.cancel());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Runs a stored script and records the run in `script_runs`, whatever the outcome.
+pub async fn run_script(
+    script_id: i32,
+    trigger_source: &str,
+    target: &DashboardTarget,
+) -> Result<()> {
+    run_script_with_debugger(
+        script_id,
+        trigger_source,
+        target,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+}
+
+async fn run_script_with_debugger(
+    script_id: i32,
+    trigger_source: &str,
+    target: &DashboardTarget,
+    cancel_token: CancellationToken,
+    debugger: Option<Arc<ScriptDebugger>>,
+) -> Result<()> {
+    let script_run = match start_script_run(script_id, trigger_source) {
+        Ok(script_run) => Some(script_run),
+        Err(e) => {
+            println!("Error recording script run: {:?}", e);
+            None
+        }
+    };
+
+    let run_key = register_running_script(script_id, cancel_token.clone());
+
+    let mut context = ScriptContext::new(target.clone(), cancel_token);
+
+    if let Some(debugger) = debugger {
+        context.set_debugger(debugger);
+    }
+
+    let res = execute_script(script_id, &context).await;
+
+    unregister_running_script(script_id, run_key);
+
+    if let Some(script_run) = script_run {
+        let (outcome, error) = match &res {
+            Ok(res) if res.is_cancelled() => (SCRIPT_RUN_OUTCOME_CANCELLED, None),
+            Ok(_) => (SCRIPT_RUN_OUTCOME_SUCCESS, None),
+            Err(e) => (SCRIPT_RUN_OUTCOME_ERROR, Some(e.to_string())),
+        };
+
+        if let Err(e) =
+            finish_script_run(script_run.get_id(), outcome, error, &context.get_output())
+        {
+            println!("Error recording script run: {:?}", e);
+        }
+    }
+
+    res.map(|_| ())
+}
+
+/// Starts a script run as a task on the script runtime, so it can be called
+/// from socket callbacks, CoAP handlers and plain threads alike.
+pub fn spawn_script(
+    script_id: i32,
+    trigger_source: &'static str,
+    target: DashboardTarget,
+) -> TaskHandle<Result<()>> {
+    script_runtime().spawn(async move { run_script(script_id, trigger_source, &target).await })
+}
+
+fn debug_sessions() -> &'static Mutex<HashMap<i32, DebugSession>> {
+    DEBUG_SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn get_debug_session(script_id: i32) -> Option<Arc<ScriptDebugger>> {
+    debug_sessions()
+        .lock()
+        .unwrap()
+        .get(&script_id)
+        .map(|session| session.debugger.clone())
+}
+
+/// Starts a real run of the script under the debugger, paused on its first
+/// line. Only one debug session per script is allowed at a time.
+pub fn spawn_debug_script(
+    script_id: i32,
+    breakpoints: HashSet<usize>,
+    target: DashboardTarget,
+) -> Result<TaskHandle<Result<()>>> {
+    let debugger = Arc::new(ScriptDebugger::new(script_id, breakpoints));
+    let cancel_token = CancellationToken::new();
+
+    match debug_sessions().lock().unwrap().entry(script_id) {
+        Entry::Occupied(_) => {
+            return Err(Error::msg("Script is already being debugged"));
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(DebugSession {
+                debugger: debugger.clone(),
+                socket_id: target.get_socket_id().map(str::to_string),
+                cancel_token: cancel_token.clone(),
+            });
+        }
+    }
+
+    Ok(script_runtime().spawn(async move {
+        let res = run_script_with_debugger(
+            script_id,
+            SCRIPT_TRIGGER_DEBUG,
+            &target,
+            cancel_token,
+            Some(debugger),
+        )
+        .await;
+
+        debug_sessions().lock().unwrap().remove(&script_id);
+
+        if let Err(e) = target.emit(
+            SCRIPT_DEBUG_FINISHED_EVENT,
+            ScriptDebugFinished::new(script_id, res.as_ref().err().map(|e| e.to_string())),
+        ) {
+            println!("Error sending debugger state to dashboard: {:?}", e);
+        }
+
+        res
+    }))
+}
+
+/// Cancels the debug runs started by a dashboard that disconnected. Nobody
+/// else can step them, and they would keep the scripts locked otherwise.
+pub fn cancel_socket_debug_sessions(socket_id: &str) {
+    debug_sessions()
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|session| session.socket_id.as_deref() == Some(socket_id))
+        .for_each(|session| session.cancel_token.cancel());
+}
+
+/// Executes the node the debugged script is paused on. Returns false when the
+/// script is not paused in the debugger.
+pub fn step_script(script_id: i32) -> bool {
+    get_debug_session(script_id).is_some_and(|debugger| debugger.step())
+}
+
+/// Lets the debugged script run to the next breakpoint.
+pub fn continue_script(script_id: i32) -> bool {
+    get_debug_session(script_id).is_some_and(|debugger| debugger.continue_run())
+}
+
+/// Position and variables of a paused debug run.
+pub fn get_debug_state(script_id: i32) -> Option<ScriptDebugState> {
+    get_debug_session(script_id).and_then(|debugger| debugger.get_paused_state())
+}
+
+/// Runs a stored script against simulated devices. Nothing is sent to the
+/// actuators or sensors, and the run is neither recorded in `script_runs` nor
+/// reflected in the script status.
+pub async fn dry_run_script(
+    script_id: i32,
+    sensor_values: HashMap<i32, String>,
+    target: &DashboardTarget,
+) -> Result<ScriptDryRun> {
+    let script = Script::parse(script_id)?;
+
+    let simulation = Arc::new(ScriptSimulation::new(sensor_values));
+
+    let cancel_token = CancellationToken::new();
+    let run_key = register_running_script(script_id, cancel_token.clone());
+
+    let mut context = ScriptContext::new(target.clone(), cancel_token);
+    context.set_simulation(simulation.clone());
+
+    let res = script.run(&context).await;
+
+    unregister_running_script(script_id, run_key);
+
+    let (outcome, error) = match &res {
+        Ok(res) if res.is_cancelled() => (SCRIPT_RUN_OUTCOME_CANCELLED, None),
+        Ok(_) => (SCRIPT_RUN_OUTCOME_SUCCESS, None),
+        Err(e) => (SCRIPT_RUN_OUTCOME_ERROR, Some(e.to_string())),
+    };
+
+    Ok(ScriptDryRun::new(
+        script_id,
+        outcome,
+        error,
+        context.get_output(),
+        &simulation,
+    ))
+}
+
+/// Starts a dry run on the script runtime and sends its trace to the target.
+pub fn spawn_dry_run(
+    script_id: i32,
+    sensor_values: HashMap<i32, String>,
+    target: DashboardTarget,
+) -> TaskHandle<()> {
+    script_runtime().spawn(async move {
+        let res = match dry_run_script(script_id, sensor_values, &target).await {
+            Ok(dry_run) => target.emit(SCRIPT_DRY_RUN_EVENT, ScriptDryRunFinished::new(dry_run)),
+            Err(e) => target.send_message(
+                format!("Error parsing script: {:?}", e).to_string(),
+                DashboardMessageType::Error,
+            ),
+        };
+
+        if let Err(e) = res {
+            println!("Error sending dry run to dashboard: {:?}", e);
+        }
+    })
+}
+
+fn schedules_need_refresh(changed: bool, last_refresh: Option<Instant>, now: Instant) -> bool {
+    changed
+        || last_refresh.is_none_or(|last_refresh| now - last_refresh >= SCHEDULE_REFRESH_INTERVAL)
+}
+
+fn refresh_scheduled_scripts(
+    scheduled: &mut HashMap<i32, ScheduledScript>,
+    now: &DateTime<Local>,
+) -> Result<()> {
+    let scripts = get_scheduled_scripts()?;
+
+    scheduled.retain(|script_id, _| scripts.iter().any(|s| s.get_id() == *script_id));
+
+    for script in scripts {
+        let schedule = match script.get_schedule() {
+            Some(schedule) => schedule,
+            None => continue,
+        };
+
+        if let Some(entry) = scheduled.get(&script.get_id()) {
+            if &entry.schedule == schedule {
+                continue;
+            }
+        }
+
+        match ScriptSchedule::parse(schedule) {
+            Ok(parsed_schedule) => {
+                let next_run = parsed_schedule.next_run_after(now);
+
+                scheduled.insert(
+                    script.get_id(),
+                    ScheduledScript {
+                        schedule: schedule.to_string(),
+                        parsed_schedule,
+                        next_run,
+                    },
+                );
+            }
+            Err(e) => {
+                println!(
+                    "Error parsing schedule of script {}: {:?}",
+                    script.get_id(),
+                    e
+                );
+                scheduled.remove(&script.get_id());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn run_script_scheduler(socket: &SocketIo) -> JoinHandle<()> {
+    let target = DashboardTarget::All(socket.clone());
+
+    spawn(move || {
+        let mut scheduled: HashMap<i32, ScheduledScript> = HashMap::new();
+        let mut running: HashMap<i32, TaskHandle<Result<()>>> = HashMap::new();
+        let mut last_refresh: Option<Instant> = None;
+
+        loop {
+            let now = Local::now();
+
+            // A failed reload is retried at the next interval, not every tick.
+            if schedules_need_refresh(take_schedules_changed(), last_refresh, Instant::now()) {
+                last_refresh = Some(Instant::now());
+
+                match refresh_scheduled_scripts(&mut scheduled, &now) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error loading scheduled scripts: {:?}", e);
+                    }
+                }
+            }
+
+            running.retain(|_, handle| !handle.is_finished());
+
+            for (script_id, entry) in scheduled.iter_mut() {
+                match entry.next_run {
+                    Some(next_run) if next_run <= now => {}
+                    _ => continue,
+                }
+
+                entry.next_run = entry.parsed_schedule.next_run_after(&now);
+
+                // A run that outlasts its interval is not started a second time.
+                if running.contains_key(script_id) {
+                    continue;
+                }
+
+                running.insert(
+                    *script_id,
+                    spawn_script(*script_id, SCRIPT_TRIGGER_SCHEDULE, target.clone()),
+                );
+            }
+
+            std::thread::sleep(SCHEDULER_TICK);
+        }
+    })
+}
+
+/// Returns the rule's new consecutive match count and whether it fires: the
+/// condition must hold for `debounce_reads` readings in a row and the
+/// cooldown since the last trigger must have elapsed.
+fn evaluate_rule_match(rule: &ScriptRule, matched: bool, now: &NaiveDateTime) -> (i32, bool) {
+    if !matched {
+        return (0, false);
+    }
+
+    let match_count = (rule.get_match_count() + 1).min(rule.get_debounce_reads());
+
+    if match_count < rule.get_debounce_reads() {
+        return (match_count, false);
+    }
+
+    if let Some(last_triggered_at) = rule.get_last_triggered_at() {
+        let cooldown = chrono::Duration::seconds(rule.get_cooldown_seconds() as i64);
+
+        if *now - *last_triggered_at < cooldown {
+            return (match_count, false);
+        }
+    }
+
+    (0, true)
+}
+
+fn sensor_read_variables(sensor_read: &SensorRead) -> Variables {
+    let mut variables: Variables = HashMap::new();
+
+    let value = Value::from_sensor_read(sensor_read);
+
+    variables.insert("$value".to_string(), value);
+    variables.insert(
+        "$sensor_id".to_string(),
+        Value::Int32(sensor_read.get_sensor_id()),
+    );
+
+    variables
+}
+
+/// Evaluates the sensor's rules against a freshly stored reading and starts
+/// the scripts whose rules fire.
+pub fn run_sensor_rules(socket: &SocketIo, sensor_read: &SensorRead) {
+    let rules = match get_enabled_script_rules_for_sensor(sensor_read.get_sensor_id()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            println!("Error loading script rules: {:?}", e);
+            return;
+        }
+    };
+
+    if rules.is_empty() {
+        return;
+    }
+
+    let variables = sensor_read_variables(sensor_read);
+    let now = Local::now();
+
+    for rule in rules {
+        let matched = match parse_arguments(rule.get_condition())
+            .and_then(|condition| parse_condition(condition, &variables, &now))
+        {
+            Ok(condition) => condition.evaluate(),
+            Err(e) => {
+                println!(
+                    "Error parsing condition of script rule {}: {:?}",
+                    rule.get_id(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let (match_count, triggered) = evaluate_rule_match(&rule, matched, &now.naive_local());
+
+        if match_count == rule.get_match_count() && !triggered {
+            continue;
+        }
+
+        if let Err(e) = update_script_rule_match(
+            rule.get_id(),
+            match_count,
+            triggered.then_some(now.naive_local()),
+        ) {
+            println!("Error updating script rule: {:?}", e);
+            continue;
+        }
+
+        if triggered {
+            spawn_script(
+                rule.get_script_id(),
+                SCRIPT_TRIGGER_EVENT,
+                DashboardTarget::All(socket.clone()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Datelike, TimeZone, Timelike, Weekday};
+    use serde_json::json;
+
+    #[test]
+    fn test_cancel_socket_debug_sessions() {
+        let session = |script_id: i32, socket_id: Option<&str>| {
+            let cancel_token = CancellationToken::new();
+
+            debug_sessions().lock().unwrap().insert(
+                script_id,
+                DebugSession {
+                    debugger: Arc::new(ScriptDebugger::new(script_id, HashSet::new())),
+                    socket_id: socket_id.map(str::to_string),
+                    cancel_token: cancel_token.clone(),
+                },
+            );
+
+            cancel_token
+        };
+
+        let closed = session(-1, Some("closed"));
+        let open = session(-2, Some("open"));
+        let scheduled = session(-3, None);
+
+        cancel_socket_debug_sessions("closed");
+
+        assert!(closed.is_cancelled());
+        assert!(!open.is_cancelled());
+        assert!(!scheduled.is_cancelled());
+
+        debug_sessions()
+            .lock()
+            .unwrap()
+            .retain(|script_id, _| *script_id >= 0);
+    }
+
+    #[test]
+    fn test_schedules_need_refresh() {
+        let now = Instant::now();
+
+        assert!(schedules_need_refresh(false, None, now));
+        assert!(schedules_need_refresh(true, Some(now), now));
+        assert!(!schedules_need_refresh(
+            false,
+            Some(now),
+            now + Duration::from_secs(59)
+        ));
+        assert!(schedules_need_refresh(
+            false,
+            Some(now),
+            now + SCHEDULE_REFRESH_INTERVAL
+        ));
+    }
+
+    #[test]
+    fn test_parse_every_schedule() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap();
+
+        let schedule = ScriptSchedule::parse("every 5 minutes").unwrap();
+        assert_eq!(
+            schedule.next_run_after(&now).unwrap(),
+            now + chrono::Duration::minutes(5)
+        );
+
+        let schedule = ScriptSchedule::parse("Every hour").unwrap();
+        assert_eq!(
+            schedule.next_run_after(&now).unwrap(),
+            now + chrono::Duration::hours(1)
+        );
+
+        assert!(ScriptSchedule::parse("every 0 minutes").is_err());
+    }
+
+    #[test]
+    fn test_parse_cron_schedule() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 10, 2, 30).unwrap();
+
+        let schedule = ScriptSchedule::parse("*/15 * * * *").unwrap();
+        let next_run = schedule.next_run_after(&now).unwrap();
+        assert_eq!((next_run.hour(), next_run.minute()), (10, 15));
+
+        let schedule = ScriptSchedule::parse("0 30 6 * * Mon-Fri").unwrap();
+        let next_run = schedule.next_run_after(&now).unwrap();
+        assert_eq!((next_run.hour(), next_run.minute()), (6, 30));
+
+        assert!(ScriptSchedule::parse("not a schedule").is_err());
+    }
+
+    #[test]
+    fn test_parse_cron_weekdays() {
+        // A Sunday.
+        let sunday = Local.with_ymd_and_hms(2024, 1, 7, 10, 0, 0).unwrap();
+
+        let schedule = ScriptSchedule::parse("0 9 * * 1-5").unwrap();
+        let runs = std::iter::successors(schedule.next_run_after(&sunday), |run| {
+            schedule.next_run_after(run)
+        })
+        .take(10)
+        .collect::<Vec<DateTime<Local>>>();
+
+        assert!(runs.iter().all(|run| run.weekday() != Weekday::Sun));
+        assert_eq!(runs[0].weekday(), Weekday::Mon);
+        assert_eq!(runs[0].hour(), 9);
+
+        let schedule = ScriptSchedule::parse("* * * * 0").unwrap();
+        assert_eq!(
+            schedule.next_run_after(&sunday).unwrap().weekday(),
+            Weekday::Sun
+        );
+
+        assert_eq!(translate_cron_weekdays("7").unwrap(), "SUN");
+        assert_eq!(translate_cron_weekdays("5-7").unwrap(), "SUN,FRI,SAT");
+        assert_eq!(
+            translate_cron_weekdays("mon-wed,*/3").unwrap(),
+            "SUN,MON,TUE,WED,SAT"
+        );
+        assert!(ScriptSchedule::parse("0 9 * * 8").is_err());
+        assert!(ScriptSchedule::parse("0 9 * * 5-1").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_rule_match() {
+        let rule = |match_count: i32, last_triggered_at: Option<&str>| {
+            serde_json::from_value::<ScriptRule>(json!({
+                "id": 1,
+                "sensor_id": 1,
+                "script_id": 1,
+                "condition": "$value > 30",
+                "debounce_reads": 3,
+                "cooldown_seconds": 60,
+                "enabled": true,
+                "match_count": match_count,
+                "last_triggered_at": last_triggered_at,
+                "created_at": "2024-01-01T10:00:00",
+                "updated_at": null,
+            }))
+            .unwrap()
+        };
+
+        let now =
+            NaiveDateTime::parse_from_str("2024-01-01 10:10:00", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        assert_eq!(evaluate_rule_match(&rule(1, None), true, &now), (2, false));
+        assert_eq!(evaluate_rule_match(&rule(2, None), false, &now), (0, false));
+        assert_eq!(evaluate_rule_match(&rule(2, None), true, &now), (0, true));
+
+        let recently = Some("2024-01-01T10:09:30");
+        assert_eq!(
+            evaluate_rule_match(&rule(2, recently), true, &now),
+            (3, false)
+        );
+        assert_eq!(
+            evaluate_rule_match(&rule(3, recently), true, &now),
+            (3, false)
+        );
+
+        let long_ago = Some("2024-01-01T10:00:00");
+        assert_eq!(
+            evaluate_rule_match(&rule(3, long_ago), true, &now),
+            (0, true)
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::script_lexer::Span;
+use crate::script_parser::Value;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Fast-forwarded delays make an endless `LOOP` finish in no time, so a dry run
+/// stops once it has simulated a day or recorded this many steps.
+const MAX_SIMULATED_MILLIS: u64 = 24 * 60 * 60 * 1000;
+const MAX_TRACE_ENTRIES: usize = 1000;
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct ScriptTraceEntry {
+    line: usize,
+    elapsed_ms: u64,
+    command: String,
+    detail: String,
+}
+
+impl ScriptTraceEntry {
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_elapsed_ms(&self) -> u64 {
+        self.elapsed_ms
+    }
+
+    pub fn get_command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn get_detail(&self) -> &str {
+        &self.detail
+    }
+}
+
+/// Device backend of a dry run: actuator commands are recorded instead of
+/// sent, sensor reads use supplied values, and delays only move a simulated
+/// clock.
+pub struct ScriptSimulation {
+    sensor_values: HashMap<i32, String>,
+    globals: Mutex<HashMap<String, Value>>,
+    started_at: DateTime<Local>,
+    elapsed_ms: Mutex<u64>,
+    trace: Mutex<Vec<ScriptTraceEntry>>,
+}
+
+impl ScriptSimulation {
+    pub fn new(sensor_values: HashMap<i32, String>) -> Self {
+        ScriptSimulation {
+            sensor_values,
+            globals: Mutex::new(HashMap::new()),
+            started_at: Local::now(),
+            elapsed_ms: Mutex::new(0),
+            trace: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get_sensor_value(&self, sensor_id: i32) -> Option<&String> {
+        self.sensor_values.get(&sensor_id)
+    }
+
+    /// Globals set during the dry run; they are never written to the database.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.lock().unwrap().get(name).cloned()
+    }
+
+    pub fn set_global(&self, name: &str, value: Value) {
+        self.globals.lock().unwrap().insert(name.to_string(), value);
+    }
+
+    pub fn get_elapsed_ms(&self) -> u64 {
+        *self.elapsed_ms.lock().unwrap()
+    }
+
+    /// The simulated clock: when the dry run started plus the delays so far.
+    pub fn get_now(&self) -> DateTime<Local> {
+        self.started_at + chrono::Duration::milliseconds(self.get_elapsed_ms() as i64)
+    }
+
+    pub fn get_trace(&self) -> Vec<ScriptTraceEntry> {
+        self.trace.lock().unwrap().clone()
+    }
+
+    pub fn record(&self, span: Span, command: &str, detail: String) -> Result<()> {
+        let mut trace = self.trace.lock().unwrap();
+
+        if trace.len() >= MAX_TRACE_ENTRIES {
+            return Err(anyhow!("Dry run stopped after {} steps", MAX_TRACE_ENTRIES));
+        }
+
+        trace.push(ScriptTraceEntry {
+            line: span.get_line(),
+            elapsed_ms: self.get_elapsed_ms(),
+            command: command.to_string(),
+            detail,
+        });
+
+        Ok(())
+    }
+
+    pub fn advance(&self, millis: u64) -> Result<()> {
+        let mut elapsed_ms = self.elapsed_ms.lock().unwrap();
+
+        *elapsed_ms = elapsed_ms.saturating_add(millis);
+
+        if *elapsed_ms > MAX_SIMULATED_MILLIS {
+            return Err(anyhow!("Dry run stopped after simulating 24 hours"));
+        }
+
+        Ok(())
+    }
+}
+
+/// What a dry run would have done, sent to the dashboard that asked for it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ScriptDryRun {
+    script_id: i32,
+    outcome: String,
+    error: Option<String>,
+    elapsed_ms: u64,
+    output: Vec<String>,
+    trace: Vec<ScriptTraceEntry>,
+}
+
+impl ScriptDryRun {
+    pub fn new(
+        script_id: i32,
+        outcome: &str,
+        error: Option<String>,
+        output: Vec<String>,
+        simulation: &ScriptSimulation,
+    ) -> Self {
+        ScriptDryRun {
+            script_id,
+            outcome: outcome.to_string(),
+            error,
+            elapsed_ms: simulation.get_elapsed_ms(),
+            output,
+            trace: simulation.get_trace(),
+        }
+    }
+
+    pub fn get_outcome(&self) -> &str {
+        &self.outcome
+    }
+
+    pub fn get_error(&self) -> &Option<String> {
+        &self.error
+    }
+
+    pub fn get_trace(&self) -> &Vec<ScriptTraceEntry> {
+        &self.trace
+    }
+}
@@ -0,0 +1,185 @@
+use crate::script_parser::Value;
+use chrono::{
+    DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+};
+use std::env;
+
+const VARIABLE_NOW: &str = "$now";
+const VARIABLE_DATE: &str = "$date";
+const VARIABLE_TIME: &str = "$time";
+const VARIABLE_HOUR: &str = "$hour";
+const VARIABLE_MINUTE: &str = "$minute";
+const VARIABLE_WEEKDAY: &str = "$weekday";
+const VARIABLE_SUNRISE: &str = "$sunrise";
+const VARIABLE_SUNSET: &str = "$sunset";
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIME_FORMAT: &str = "%H:%M";
+
+/// Location used for `$sunrise` and `$sunset`, from the `LATITUDE` and
+/// `LONGITUDE` environment variables (degrees, east and north positive).
+fn get_location() -> Option<(f64, f64)> {
+    let latitude = env::var("LATITUDE").ok()?.trim().parse::<f64>().ok()?;
+    let longitude = env::var("LONGITUDE").ok()?.trim().parse::<f64>().ok()?;
+
+    Some((latitude, longitude))
+}
+
+/// Value of a built-in time variable at `now`. Scripts can shadow these
+/// names with their own variables.
+pub fn get_time_variable(name: &str, now: &DateTime<Local>) -> Option<Value> {
+    match name {
+        VARIABLE_NOW => Some(Value::String(now.format(DATETIME_FORMAT).to_string())),
+        VARIABLE_DATE => Some(Value::String(now.format(DATE_FORMAT).to_string())),
+        VARIABLE_TIME => Some(Value::String(now.format(TIME_FORMAT).to_string())),
+        VARIABLE_HOUR => Some(Value::Int32(now.hour() as i32)),
+        VARIABLE_MINUTE => Some(Value::Int32(now.minute() as i32)),
+        VARIABLE_WEEKDAY => Some(Value::Int32(now.weekday().number_from_monday() as i32)),
+        VARIABLE_SUNRISE | VARIABLE_SUNSET => {
+            let (latitude, longitude) = get_location()?;
+            let (sunrise, sunset) = get_sun_times(now.date_naive(), latitude, longitude)?;
+
+            let time = if name == VARIABLE_SUNRISE {
+                sunrise
+            } else {
+                sunset
+            };
+
+            Some(Value::String(
+                time.with_timezone(&Local).format(TIME_FORMAT).to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Sunrise and sunset on `date` using the NOAA sunrise equation, accurate to
+/// about a minute. Returns `None` during polar day or night.
+pub fn get_sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let j2000 = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+
+    let day = (date - j2000).num_days() as f64 + 0.0008;
+    let mean_solar_time = day - longitude / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let anomaly_rad = anomaly.to_radians();
+
+    let center = 1.9148 * anomaly_rad.sin()
+        + 0.02 * (2.0 * anomaly_rad).sin()
+        + 0.0003 * (3.0 * anomaly_rad).sin();
+
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+
+    let transit = 2451545.0 + mean_solar_time + 0.0053 * anomaly_rad.sin()
+        - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+
+    Some((
+        julian_to_utc(transit - hour_angle)?,
+        julian_to_utc(transit + hour_angle)?,
+    ))
+}
+
+fn julian_to_utc(julian_date: f64) -> Option<DateTime<Utc>> {
+    let seconds = ((julian_date - 2440587.5) * 86400.0).round() as i64;
+
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+enum TimeValue {
+    Time(NaiveTime),
+    DateTime(NaiveDateTime),
+}
+
+fn parse_time_value(s: &str) -> Option<TimeValue> {
+    let s = s.trim();
+
+    if let Ok(time) = NaiveTime::parse_from_str(s, "%H:%M:%S") {
+        return Some(TimeValue::Time(time));
+    }
+
+    if let Ok(time) = NaiveTime::parse_from_str(s, TIME_FORMAT) {
+        return Some(TimeValue::Time(time));
+    }
+
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(s, DATETIME_FORMAT) {
+        return Some(TimeValue::DateTime(datetime));
+    }
+
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+        return Some(TimeValue::DateTime(datetime));
+    }
+
+    NaiveDate::parse_from_str(s, DATE_FORMAT)
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(TimeValue::DateTime)
+}
+
+/// Orders two strings as times of day (`"6:30" < "18:00"`) or as dates,
+/// when both are written that way.
+pub fn compare_times(left: &str, right: &str) -> Option<std::cmp::Ordering> {
+    match (parse_time_value(left)?, parse_time_value(right)?) {
+        (TimeValue::Time(left), TimeValue::Time(right)) => Some(left.cmp(&right)),
+        (TimeValue::DateTime(left), TimeValue::DateTime(right)) => Some(left.cmp(&right)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn test_time_variables_and_sun_times() {
+        let now = Local.with_ymd_and_hms(2024, 6, 21, 7, 5, 0).unwrap();
+
+        assert_eq!(
+            get_time_variable("$now", &now),
+            Some(Value::String("2024-06-21 07:05:00".to_string()))
+        );
+        assert_eq!(
+            get_time_variable("$time", &now),
+            Some(Value::String("07:05".to_string()))
+        );
+        assert_eq!(get_time_variable("$weekday", &now), Some(Value::Int32(5)));
+        assert_eq!(get_time_variable("$other", &now), None);
+
+        // London on the summer solstice: 03:43 and 20:21 UTC.
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (sunrise, sunset) = get_sun_times(date, 51.5074, -0.1278).unwrap();
+
+        let minutes = |time: DateTime<Utc>| (time.hour() * 60 + time.minute()) as i32;
+        assert!((minutes(sunrise) - (3 * 60 + 43)).abs() <= 2);
+        assert!((minutes(sunset) - (20 * 60 + 21)).abs() <= 2);
+
+        assert!(get_sun_times(date, 80.0, 0.0).is_none());
+
+        assert_eq!(compare_times("6:30", "18:00"), Some(Ordering::Less));
+        assert_eq!(compare_times("07:05", "07:05:00"), Some(Ordering::Equal));
+        assert_eq!(
+            compare_times("2024-06-21 07:05:00", "2024-06-21"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(compare_times("07:05", "2024-06-21"), None);
+    }
+}
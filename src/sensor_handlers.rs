@@ -3,10 +3,16 @@ use crate::events::{
     SENSOR_CHANGE_ONLINE_EVENT, SENSOR_NAME_CHANGE_EVENT, SENSOR_READ_EVENT, SENSOR_REGISTER_EVENT,
     SENSOR_UNREGISTER_EVENT,
 };
-use crate::models::Sensor;
+use crate::models::{Sensor, SensorRead};
+use crate::protocol::{
+    SensorOnlineChanged, SensorReadReceived, SensorRegistered, SensorRenamed, SensorUnregistered,
+};
 use crate::schema::sensors;
 use crate::schema::sensors::{online, updated_at};
-use crate::sensor_methods::{change_sensor_name, read_sensor, register_sensor, unregister_sensor};
+use crate::script_runner::run_sensor_rules;
+use crate::sensor_methods::{
+    change_sensor_name, read_sensors, register_sensors, unregister_sensor,
+};
 use crate::CoAPClient;
 use anyhow::{Error, Result};
 use coap_lite::{CoapRequest, RequestType};
@@ -14,7 +20,6 @@ use diesel::prelude::*;
 use diesel::{update, QueryDsl};
 use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
-use serde_json::json;
 use socketioxide::SocketIo;
 use std::net::SocketAddr;
 
@@ -32,49 +37,17 @@ pub fn sensor_register_handler<'a>(
             return "KO".to_string();
         }
 
-        match register_sensor(payload) {
-            Ok(sensor) => {
-                if let Some(ns) = socket.of("/") {
-                    match ns.broadcast().emit(
-                        SENSOR_REGISTER_EVENT,
-                        json!({
-                               "sensor_id": sensor.get_id(),
-                               "sensor_name": sensor.get_name(),
-                               "sensor_ip_address": sensor.get_ip_address(),
-                               "sensor_port": sensor.get_port(),
-                               "sensor_type": sensor.get_sensor_type(),
-                               "online": sensor.get_online(),
-                               "created_at": sensor.get_created_at(),
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting sensor register event: {:?}", e);
-                        }
-                    }
+        match register_sensors(payload) {
+            Ok(sensors) => {
+                for sensor in &sensors {
+                    emit_sensor_registered(socket, sensor);
                 }
 
-                if let Some(ns) = socket.of("/") {
-                    match ns.emit(
-                        SENSOR_REGISTER_EVENT,
-                        json!({
-                               "sensor_id": sensor.get_id(),
-                               "sensor_name": sensor.get_name(),
-                               "sensor_ip_address": sensor.get_ip_address(),
-                               "sensor_port": sensor.get_port(),
-                               "sensor_type": sensor.get_sensor_type(),
-                               "online": sensor.get_online(),
-                               "created_at": sensor.get_created_at(),
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting sensor register event: {:?}", e);
-                        }
-                    }
-                }
-
-                sensor.get_id().to_string()
+                sensors
+                    .iter()
+                    .map(|sensor| sensor.get_id().to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
             }
             Err(e) => {
                 println!("Error registering sensor: {:?}", e);
@@ -85,6 +58,31 @@ pub fn sensor_register_handler<'a>(
     .boxed()
 }
 
+/// Tells the dashboards about a sensor, once per channel for multi-value
+/// devices.
+fn emit_sensor_registered(socket: &SocketIo, sensor: &Sensor) {
+    if let Some(ns) = socket.of("/") {
+        match ns
+            .broadcast()
+            .emit(SENSOR_REGISTER_EVENT, SensorRegistered::new(sensor))
+        {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting sensor register event: {:?}", e);
+            }
+        }
+    }
+
+    if let Some(ns) = socket.of("/") {
+        match ns.emit(SENSOR_REGISTER_EVENT, SensorRegistered::new(sensor)) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting sensor register event: {:?}", e);
+            }
+        }
+    }
+}
+
 pub fn sensor_unregister_handler<'a>(
     socket: &'a SocketIo,
     request: &'a CoapRequest<SocketAddr>,
@@ -104,9 +102,7 @@ pub fn sensor_unregister_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.broadcast().emit(
                         SENSOR_UNREGISTER_EVENT,
-                        json!({
-                               "sensor_id": sensor.get_id(),
-                        }),
+                        SensorUnregistered::new(sensor.get_id()),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -118,9 +114,7 @@ pub fn sensor_unregister_handler<'a>(
                 if let Some(ns) = socket.of("/") {
                     match ns.emit(
                         SENSOR_UNREGISTER_EVENT,
-                        json!({
-                               "sensor_id": sensor.get_id(),
-                        }),
+                        SensorUnregistered::new(sensor.get_id()),
                     ) {
                         Ok(_) => {}
                         Err(e) => {
@@ -154,40 +148,12 @@ pub fn sensor_read_handler<'a>(
             return "KO".to_string();
         }
 
-        match read_sensor(payload) {
-            Ok(sensor_read) => {
-                if let Some(ns) = socket.of("/") {
-                    match ns.broadcast().emit(
-                        SENSOR_READ_EVENT,
-                        json!({
-                            "id": sensor_read.get_id(),
-                            "sensor_id": sensor_read.get_sensor_id(),
-                            "sensor_value": sensor_read.get_sensor_value(),
-                            "created_at": sensor_read.get_created_at(),
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting sensor read event broadcast: {:?}", e);
-                        }
-                    }
-                }
+        match read_sensors(payload) {
+            Ok(sensor_reads) => {
+                for sensor_read in &sensor_reads {
+                    emit_sensor_read(socket, sensor_read);
 
-                if let Some(ns) = socket.of("/") {
-                    match ns.emit(
-                        SENSOR_READ_EVENT,
-                        json!({
-                            "id": sensor_read.get_id(),
-                            "sensor_id": sensor_read.get_sensor_id(),
-                            "sensor_value": sensor_read.get_sensor_value(),
-                            "created_at": sensor_read.get_created_at(),
-                        }),
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error emitting sensor read event: {:?}", e);
-                        }
-                    }
+                    run_sensor_rules(socket, sensor_read);
                 }
 
                 "OK".to_string()
@@ -201,6 +167,29 @@ pub fn sensor_read_handler<'a>(
     .boxed()
 }
 
+fn emit_sensor_read(socket: &SocketIo, sensor_read: &SensorRead) {
+    if let Some(ns) = socket.of("/") {
+        match ns
+            .broadcast()
+            .emit(SENSOR_READ_EVENT, SensorReadReceived::new(sensor_read))
+        {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting sensor read event broadcast: {:?}", e);
+            }
+        }
+    }
+
+    if let Some(ns) = socket.of("/") {
+        match ns.emit(SENSOR_READ_EVENT, SensorReadReceived::new(sensor_read)) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error emitting sensor read event: {:?}", e);
+            }
+        }
+    }
+}
+
 pub fn sensor_update_handler<'a>(
     socket: &'a SocketIo,
     request: &'a CoapRequest<SocketAddr>,
@@ -218,14 +207,10 @@ pub fn sensor_update_handler<'a>(
         match change_sensor_name(payload) {
             Ok(sensor) => {
                 if let Some(ns) = socket.of("/") {
-                    match ns.broadcast().emit(
-                        SENSOR_NAME_CHANGE_EVENT,
-                        json!({
-                            "sensor_id": sensor.get_id(),
-                            "sensor_name": sensor.get_name(),
-                            "updated_at": sensor.get_updated_at(),
-                        }),
-                    ) {
+                    match ns
+                        .broadcast()
+                        .emit(SENSOR_NAME_CHANGE_EVENT, SensorRenamed::new(&sensor))
+                    {
                         Ok(_) => {}
                         Err(e) => {
                             println!(
@@ -237,14 +222,7 @@ pub fn sensor_update_handler<'a>(
                 }
 
                 if let Some(ns) = socket.of("/") {
-                    match ns.emit(
-                        SENSOR_NAME_CHANGE_EVENT,
-                        json!({
-                            "sensor_id": sensor.get_id(),
-                            "sensor_name": sensor.get_name(),
-                            "updated_at": sensor.get_updated_at(),
-                        }),
-                    ) {
+                    match ns.emit(SENSOR_NAME_CHANGE_EVENT, SensorRenamed::new(&sensor)) {
                         Ok(_) => {}
                         Err(e) => {
                             println!("Error emitting sensor name changed event: {:?}", e);
@@ -295,11 +273,7 @@ pub fn ping_sensor(sensor: &Sensor, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.broadcast().emit(
                     SENSOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "sensor_id": sensor.get_id(),
-                           "online": true,
-                           "updated_at": uat,
-                    }),
+                    SensorOnlineChanged::new(sensor.get_id(), true, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -311,11 +285,7 @@ pub fn ping_sensor(sensor: &Sensor, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.emit(
                     SENSOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "sensor_id": sensor.get_id(),
-                           "online": true,
-                           "updated_at": uat,
-                    }),
+                    SensorOnlineChanged::new(sensor.get_id(), true, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -349,11 +319,7 @@ pub fn ping_sensor(sensor: &Sensor, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.broadcast().emit(
                     SENSOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "sensor_id": sensor.get_id(),
-                           "online": false,
-                           "updated_at": uat,
-                    }),
+                    SensorOnlineChanged::new(sensor.get_id(), false, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -365,11 +331,7 @@ pub fn ping_sensor(sensor: &Sensor, socket: &SocketIo) {
             if let Some(ns) = socket.of("/") {
                 match ns.emit(
                     SENSOR_CHANGE_ONLINE_EVENT,
-                    json!({
-                           "sensor_id": sensor.get_id(),
-                           "online": false,
-                           "updated_at": uat,
-                    }),
+                    SensorOnlineChanged::new(sensor.get_id(), false, uat),
                 ) {
                     Ok(_) => {}
                     Err(e) => {
@@ -411,4 +373,3 @@ pub fn send_message_to_sensor(sensor_id: i32, message: &String) -> Result<String
         Err(_) => Err(Error::msg("Error sending message to sensor")),
     }
 }
-
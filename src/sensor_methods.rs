@@ -1,32 +1,34 @@
 use anyhow::{Error, Result};
 use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Timestamp};
 use diesel::{insert_into, sql_query, update};
 
 use crate::db::connect;
+use crate::helper::invalid_input;
 use crate::models::{
-    NewSensor, NewSensorRead, Sensor, SensorRead, SensorUnregister, UpdateSensorName,
+    GetSensorReadings, NewSensor, NewSensorRead, Sensor, SensorChannelsRead,
+    SensorChannelsRegister, SensorRead, SensorReadPayload, SensorReadRollup, SensorReadingBucket,
+    SensorReadings, SensorReadingsPage, SensorUnregister, UpdateSensorName,
 };
 
+use crate::schema::script_rules;
 use crate::schema::sensors;
 use crate::schema::sensors::dsl::{id, ip_address, name, sensor_type};
 
+use crate::rollup_methods::{bucket_expression, choose_tier, RollupTier};
+use crate::schema::sensor_read_rollups;
 use crate::schema::sensor_reads;
 use crate::schema::sensor_reads::created_at;
 use crate::schema::sensor_reads::dsl::{id as sensor_read_id, sensor_id, sensor_value};
 use crate::schema::sensors::updated_at;
 use serde_json::from_str;
 
-use crate::sensor_types::{
-    SENSOR_TYPE_CURRENT, SENSOR_TYPE_HUMIDITY, SENSOR_TYPE_PRESSURE, SENSOR_TYPE_RAIN,
-    SENSOR_TYPE_SOLAR_RADIATION, SENSOR_TYPE_TEMPERATURE, SENSOR_TYPE_UNKNOWN, SENSOR_TYPE_UV,
-    SENSOR_TYPE_WIND_DIRECTION, SENSOR_TYPE_WIND_SPEED,
-};
-
-pub fn register_sensor(payload: String) -> Result<Sensor> {
-    let conn = &mut connect()?;
-
-    let mut new_sensor = from_str::<NewSensor>(&payload)?;
+use crate::sensor_type_methods::{find_sensor_type, normalize_sensor_type_name};
+use crate::sensor_types::SENSOR_TYPE_UNKNOWN;
 
+/// Returns the sensor already registered with this type at this address, or
+/// registers it under its canonical type and default name.
+fn insert_sensor(conn: &mut SqliteConnection, mut new_sensor: NewSensor) -> Result<Sensor> {
     new_sensor.set_created_at(chrono::Local::now().naive_local());
 
     if let Ok(sensor) = sensors::table
@@ -37,38 +39,15 @@ pub fn register_sensor(payload: String) -> Result<Sensor> {
         return Ok(sensor);
     }
 
-    let sensor_t = new_sensor.get_sensor_type().to_string().to_lowercase();
-
-    new_sensor.set_name(Some("Unknown sensor".to_string()));
-    new_sensor.set_sensor_type(SENSOR_TYPE_UNKNOWN.to_string());
-
-    if sensor_t == SENSOR_TYPE_CURRENT {
-        new_sensor.set_name(Some("Current sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_CURRENT.to_string());
-    } else if sensor_t == SENSOR_TYPE_TEMPERATURE {
-        new_sensor.set_name(Some("Temperature sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_TEMPERATURE.to_string());
-    } else if sensor_t == SENSOR_TYPE_HUMIDITY {
-        new_sensor.set_name(Some("Humidity sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_HUMIDITY.to_string());
-    } else if sensor_t == SENSOR_TYPE_PRESSURE {
-        new_sensor.set_name(Some("Pressure sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_PRESSURE.to_string());
-    } else if sensor_t == SENSOR_TYPE_WIND_SPEED {
-        new_sensor.set_name(Some("Wind sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_WIND_SPEED.to_string());
-    } else if sensor_t == SENSOR_TYPE_WIND_DIRECTION {
-        new_sensor.set_name(Some("Wind direction sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_WIND_DIRECTION.to_string());
-    } else if sensor_t == SENSOR_TYPE_RAIN {
-        new_sensor.set_name(Some("Rain sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_RAIN.to_string());
-    } else if sensor_t == SENSOR_TYPE_UV {
-        new_sensor.set_name(Some("UV sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_UV.to_string());
-    } else if sensor_t == SENSOR_TYPE_SOLAR_RADIATION {
-        new_sensor.set_name(Some("Solar radiation sensor".to_string()));
-        new_sensor.set_sensor_type(SENSOR_TYPE_SOLAR_RADIATION.to_string());
+    match find_sensor_type(conn, new_sensor.get_sensor_type())? {
+        Some(known_type) => {
+            new_sensor.set_name(Some(known_type.get_display_name().to_string()));
+            new_sensor.set_sensor_type(known_type.get_name().to_string());
+        }
+        None => {
+            new_sensor.set_name(Some("Unknown sensor".to_string()));
+            new_sensor.set_sensor_type(SENSOR_TYPE_UNKNOWN.to_string());
+        }
     }
 
     let res = insert_into(sensors::table)
@@ -93,6 +72,46 @@ pub fn register_sensor(payload: String) -> Result<Sensor> {
     }
 }
 
+pub fn register_sensor(payload: String) -> Result<Sensor> {
+    let conn = &mut connect()?;
+
+    let new_sensor = from_str::<NewSensor>(&payload)?;
+
+    insert_sensor(conn, new_sensor)
+}
+
+/// Registers a single sensor, or one sensor per channel when the payload
+/// lists `sensor_types`. Sensors come back in the order of the payload.
+pub fn register_sensors(payload: String) -> Result<Vec<Sensor>> {
+    if from_str::<serde_json::Value>(&payload)?
+        .get("sensor_types")
+        .is_none()
+    {
+        return Ok(vec![register_sensor(payload)?]);
+    }
+
+    let conn = &mut connect()?;
+
+    let register = from_str::<SensorChannelsRegister>(&payload)?;
+
+    if register.get_sensor_types().is_empty() {
+        return Err(Error::msg("No sensor types to register"));
+    }
+
+    conn.transaction(|conn| {
+        register
+            .get_sensor_types()
+            .iter()
+            .map(|channel_type| {
+                let mut new_sensor = NewSensor::new(channel_type, register.get_ip_address());
+                new_sensor.set_port(register.get_port());
+
+                insert_sensor(conn, new_sensor)
+            })
+            .collect()
+    })
+}
+
 pub fn unregister_sensor(payload: String) -> Result<Sensor> {
     let conn = &mut connect()?;
 
@@ -109,6 +128,18 @@ pub fn unregister_sensor(payload: String) -> Result<Sensor> {
         }
     };
 
+    let res = diesel::delete(
+        script_rules::table.filter(script_rules::sensor_id.eq(sensor_unregister.get_id())),
+    )
+    .execute(conn);
+
+    match res {
+        Ok(_) => {}
+        Err(e) => {
+            return Err(Error::from(e));
+        }
+    }
+
     let res = diesel::delete(sensor_reads::table.filter(sensor_id.eq(sensor_unregister.get_id())))
         .execute(conn);
 
@@ -119,6 +150,19 @@ pub fn unregister_sensor(payload: String) -> Result<Sensor> {
         }
     }
 
+    let res = diesel::delete(
+        sensor_read_rollups::table
+            .filter(sensor_read_rollups::sensor_id.eq(sensor_unregister.get_id())),
+    )
+    .execute(conn);
+
+    match res {
+        Ok(_) => {}
+        Err(e) => {
+            return Err(Error::from(e));
+        }
+    }
+
     let res =
         diesel::delete(sensors::table.filter(id.eq(sensor_unregister.get_id()))).execute(conn);
 
@@ -159,10 +203,140 @@ pub fn change_sensor_name(payload: String) -> Result<Sensor> {
     }
 }
 
+/// Splits a reading into its number and trailing unit: `"21.5 °C"` gives
+/// `(Some(21.5), Some("°C"))`, while text that does not start with a number
+/// has no numeric value.
+pub fn parse_sensor_value(raw_value: &str) -> (Option<f64>, Option<String>) {
+    let raw_value = raw_value.trim();
+
+    let number_end = raw_value
+        .char_indices()
+        .find(|(_, c)| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .map(|(i, _)| i)
+        .unwrap_or(raw_value.len());
+
+    // The whole run of number characters has to parse, so "5e" or "3-4" are
+    // text rather than 5 or 3 with a unit.
+    match raw_value[..number_end].parse::<f64>() {
+        Ok(value) if value.is_finite() => match raw_value[number_end..].trim() {
+            "" => (Some(value), None),
+            unit => (Some(value), Some(unit.to_string())),
+        },
+        _ => (None, None),
+    }
+}
+
+/// Text form of a reading sent either as a JSON number or as a string.
+pub fn raw_sensor_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Pairs every channel of a multi-value read with the sensor registered for
+/// that type on the device.
+fn match_sensor_channels(
+    channels_read: &SensorChannelsRead,
+    device_sensors: &[Sensor],
+) -> Result<Vec<NewSensorRead>> {
+    if channels_read.get_channels().is_empty() {
+        return Err(Error::msg("No channels in sensor read"));
+    }
+
+    channels_read
+        .get_channels()
+        .iter()
+        .map(|(channel, value)| {
+            let channel_type = normalize_sensor_type_name(channel)?;
+
+            let sensor = device_sensors
+                .iter()
+                .find(|sensor| sensor.get_sensor_type() == channel_type)
+                .ok_or_else(|| {
+                    Error::msg(format!(
+                        "No {} sensor registered at {}",
+                        channel_type,
+                        channels_read.get_ip_address()
+                    ))
+                })?;
+
+            Ok(NewSensorRead::new(
+                sensor.get_id(),
+                &raw_sensor_value(value),
+                channels_read.get_unit(channel),
+            ))
+        })
+        .collect()
+}
+
+/// Readings that do not state a unit get the one of their sensor's type.
+fn apply_type_unit(
+    conn: &mut SqliteConnection,
+    new_sensor_read: &mut NewSensorRead,
+    sensor: &Sensor,
+) -> Result<()> {
+    if new_sensor_read.get_unit().is_some() {
+        return Ok(());
+    }
+
+    if let Some(known_type) = find_sensor_type(conn, sensor.get_sensor_type())? {
+        new_sensor_read.set_unit(known_type.get_unit().clone());
+    }
+
+    Ok(())
+}
+
+/// Stores a single reading, or one reading per channel when the payload
+/// carries `channels`. Channels are stored together or not at all.
+pub fn read_sensors(payload: String) -> Result<Vec<SensorRead>> {
+    if from_str::<serde_json::Value>(&payload)?
+        .get("channels")
+        .is_none()
+    {
+        return Ok(vec![read_sensor(payload)?]);
+    }
+
+    let conn = &mut connect()?;
+
+    let channels_read = from_str::<SensorChannelsRead>(&payload)?;
+
+    let device_sensors = sensors::table
+        .filter(ip_address.eq(channels_read.get_ip_address()))
+        .load::<Sensor>(conn)?;
+
+    let mut new_sensor_reads = match_sensor_channels(&channels_read, &device_sensors)?;
+
+    for new_sensor_read in new_sensor_reads.iter_mut() {
+        if let Some(sensor) = device_sensors
+            .iter()
+            .find(|sensor| sensor.get_id() == new_sensor_read.get_sensor_id())
+        {
+            apply_type_unit(conn, new_sensor_read, sensor)?;
+        }
+    }
+
+    let now = chrono::Local::now().naive_local();
+
+    conn.transaction(|conn| {
+        new_sensor_reads
+            .iter_mut()
+            .map(|new_sensor_read| {
+                new_sensor_read.set_created_at(now);
+
+                insert_into(sensor_reads::table)
+                    .values(&*new_sensor_read)
+                    .get_result(conn)
+                    .map_err(Error::from)
+            })
+            .collect()
+    })
+}
+
 pub fn read_sensor(payload: String) -> Result<SensorRead> {
     let conn = &mut connect()?;
 
-    let mut new_sensor_read = from_str::<NewSensorRead>(&payload)?;
+    let mut new_sensor_read = from_str::<SensorReadPayload>(&payload)?.into_new_sensor_read();
 
     new_sensor_read.set_created_at(chrono::Local::now().naive_local());
 
@@ -171,26 +345,14 @@ pub fn read_sensor(payload: String) -> Result<SensorRead> {
         .get_result::<Sensor>(conn);
 
     match sensor {
-        Ok(_) => {}
+        Ok(sensor) => apply_type_unit(conn, &mut new_sensor_read, &sensor)?,
         Err(e) => {
             return Err(Error::from(e));
         }
     }
 
-    let res = insert_into(sensor_reads::table)
+    let sensor_read = insert_into(sensor_reads::table)
         .values(&new_sensor_read)
-        .execute(conn);
-
-    match res {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(Error::from(e));
-        }
-    }
-
-    let sensor_read = sensor_reads::table
-        .filter(sensor_id.eq(new_sensor_read.get_sensor_id()))
-        .filter(sensor_value.like(&new_sensor_read.get_sensor_value()))
         .get_result(conn);
 
     match sensor_read {
@@ -210,11 +372,19 @@ pub fn get_all_registered_sensors() -> Result<Vec<Sensor>> {
     }
 }
 
+pub fn get_sensor(other_sensor_id: i32) -> Result<Sensor> {
+    let conn = &mut connect()?;
+
+    let sensor = sensors::table.find(other_sensor_id).first(conn)?;
+
+    Ok(sensor)
+}
+
 pub fn get_all_last_sensor_readings() -> Result<Vec<SensorRead>> {
     let conn = &mut connect()?;
 
     let sensor_reads = sql_query("
-            SELECT sensor_reads.id, sensor_reads.sensor_id, sensor_reads.sensor_value, sensor_reads.created_at, sensor_reads.updated_at
+            SELECT sensor_reads.id, sensor_reads.sensor_id, sensor_reads.sensor_value, sensor_reads.unit, sensor_reads.raw_value, sensor_reads.created_at, sensor_reads.updated_at
             FROM sensor_reads WHERE id IN (SELECT MAX(id) FROM sensor_reads GROUP BY sensor_id)
     ")
         .load(conn);
@@ -225,38 +395,331 @@ pub fn get_all_last_sensor_readings() -> Result<Vec<SensorRead>> {
     }
 }
 
-pub fn get_sensor_readings(
-    other_sensor_id: i32,
-    from_date: &str,
-    to_date: &str,
-) -> Result<Vec<SensorRead>> {
+pub fn get_last_sensor_read(other_sensor_id: i32) -> Result<Option<SensorRead>> {
     let conn = &mut connect()?;
 
-    let from_datetime = chrono::NaiveDateTime::parse_from_str(from_date, "%Y-%m-%d %H:%M:%S")
-        .map_err(|e| Error::msg(format!("Invalid from_date format: {}", e)))?;
-    let to_datetime = chrono::NaiveDateTime::parse_from_str(to_date, "%Y-%m-%d %H:%M:%S")
-        .map_err(|e| Error::msg(format!("Invalid to_date format: {}", e)))?;
-
-    let sensor_reads = sensor_reads::table
+    let sensor_read = sensor_reads::table
         .filter(sensor_id.eq(other_sensor_id))
-        .filter(created_at.ge(from_datetime))
-        .filter(created_at.le(to_datetime))
         .order_by(sensor_read_id.desc())
-        .limit(50)
-        .get_results(conn);
+        .first(conn)
+        .optional()?;
 
-    match sensor_reads {
-        Ok(sensor_reads) => Ok(sensor_reads),
-        Err(e) => Err(Error::from(e)),
+    Ok(sensor_read)
+}
+
+/// Stored values of a sensor since the given time, oldest first. Readings
+/// that were not numbers are `None`.
+pub fn get_sensor_values_since(
+    other_sensor_id: i32,
+    since: chrono::NaiveDateTime,
+) -> Result<Vec<Option<f64>>> {
+    let conn = &mut connect()?;
+
+    let values = sensor_reads::table
+        .filter(sensor_id.eq(other_sensor_id))
+        .filter(created_at.ge(since))
+        .order_by(sensor_read_id.asc())
+        .select(sensor_value)
+        .load::<Option<f64>>(conn)?;
+
+    Ok(values)
+}
+
+const DEFAULT_READINGS_LIMIT: i64 = 1000;
+const MAX_READINGS_LIMIT: i64 = 10000;
+
+/// Time resolution of a history request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadingBucket {
+    Auto,
+    Raw,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl ReadingBucket {
+    pub fn parse(bucket: Option<&str>) -> Result<Self> {
+        match bucket.map(|bucket| bucket.trim().to_lowercase()).as_deref() {
+            None | Some("auto") => Ok(ReadingBucket::Auto),
+            Some("raw") => Ok(ReadingBucket::Raw),
+            Some("minute") => Ok(ReadingBucket::Minute),
+            Some("hour") => Ok(ReadingBucket::Hour),
+            Some("day") => Ok(ReadingBucket::Day),
+            Some(bucket) => Err(invalid_input(&format!("Unknown bucket '{}'", bucket))),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ReadingBucket::Auto => "auto",
+            ReadingBucket::Raw => "raw",
+            ReadingBucket::Minute => "minute",
+            ReadingBucket::Hour => "hour",
+            ReadingBucket::Day => "day",
+        }
+    }
+
+    fn get_seconds(&self) -> i32 {
+        match self {
+            ReadingBucket::Auto | ReadingBucket::Raw => 0,
+            ReadingBucket::Minute => 60,
+            ReadingBucket::Hour => 3600,
+            ReadingBucket::Day => 86400,
+        }
+    }
+
+    /// Rollup tier holding this bucket size once the raw reads are gone.
+    /// There are no 1-minute rollups, so minute buckets coarsen to 5 minutes.
+    fn get_rollup_tier(&self) -> Option<RollupTier> {
+        match self {
+            ReadingBucket::Minute => Some(RollupTier::FiveMinutes),
+            ReadingBucket::Hour => Some(RollupTier::Hourly),
+            ReadingBucket::Day => Some(RollupTier::Daily),
+            _ => None,
+        }
     }
 }
 
+/// How the numeric reads of a bucket are summarized into its `value`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadingAggregate {
+    Avg,
+    Min,
+    Max,
+    Sum,
+    Count,
+}
+
+impl ReadingAggregate {
+    pub fn parse(aggregate: Option<&str>) -> Result<Self> {
+        match aggregate
+            .map(|aggregate| aggregate.trim().to_lowercase())
+            .as_deref()
+        {
+            None | Some("avg") => Ok(ReadingAggregate::Avg),
+            Some("min") => Ok(ReadingAggregate::Min),
+            Some("max") => Ok(ReadingAggregate::Max),
+            Some("sum") => Ok(ReadingAggregate::Sum),
+            Some("count") => Ok(ReadingAggregate::Count),
+            Some(aggregate) => Err(invalid_input(&format!("Unknown aggregate '{}'", aggregate))),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ReadingAggregate::Avg => "avg",
+            ReadingAggregate::Min => "min",
+            ReadingAggregate::Max => "max",
+            ReadingAggregate::Sum => "sum",
+            ReadingAggregate::Count => "count",
+        }
+    }
+
+    fn get_sql(&self) -> &'static str {
+        match self {
+            ReadingAggregate::Avg => "AVG(sensor_value)",
+            ReadingAggregate::Min => "MIN(sensor_value)",
+            ReadingAggregate::Max => "MAX(sensor_value)",
+            ReadingAggregate::Sum => "SUM(sensor_value)",
+            ReadingAggregate::Count => "CAST(COUNT(sensor_value) AS REAL)",
+        }
+    }
+
+    fn apply_to_rollup(&self, rollup: &SensorReadRollup) -> f64 {
+        match self {
+            ReadingAggregate::Avg => rollup.get_avg_value(),
+            ReadingAggregate::Min => rollup.get_min_value(),
+            ReadingAggregate::Max => rollup.get_max_value(),
+            ReadingAggregate::Sum => rollup.get_avg_value() * rollup.get_read_count() as f64,
+            ReadingAggregate::Count => rollup.get_read_count() as f64,
+        }
+    }
+}
+
+/// Reads an RFC 3339 timestamp (`2024-02-16T08:00:00+01:00`) into local
+/// time, or a local date and time without zone, or a bare date.
+pub fn parse_reading_timestamp(timestamp: &str) -> Result<chrono::NaiveDateTime> {
+    let timestamp = timestamp.trim();
+
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(timestamp) {
+        return Ok(datetime.with_timezone(&chrono::Local).naive_local());
+    }
+
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(timestamp, format) {
+            return Ok(datetime);
+        }
+    }
+
+    chrono::NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or_else(|| invalid_input(&format!("Invalid date '{}'", timestamp)))
+}
+
+/// Tier a history request is answered from. Explicit buckets are computed
+/// from the raw reads while those cover `from`, and from their rollup after.
+fn choose_reading_tier(
+    bucket: ReadingBucket,
+    raw_retained: bool,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    now: chrono::NaiveDateTime,
+) -> RollupTier {
+    match (bucket, bucket.get_rollup_tier()) {
+        (ReadingBucket::Auto, _) => choose_tier(from, to, now),
+        (_, Some(rollup_tier)) if !raw_retained => rollup_tier,
+        _ => RollupTier::Raw,
+    }
+}
+
+fn format_cursor(datetime: chrono::NaiveDateTime) -> String {
+    datetime.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+/// A page of a sensor's history between two dates. Raw reads page by id,
+/// buckets by the start of the next bucket; pages run oldest first.
+pub fn get_sensor_readings(request: &GetSensorReadings) -> Result<SensorReadingsPage> {
+    let conn = &mut connect()?;
+
+    let from_datetime = parse_reading_timestamp(request.get_from_date())?;
+    let to_datetime = parse_reading_timestamp(request.get_to_date())?;
+
+    if from_datetime > to_datetime {
+        return Err(invalid_input("from_date is after to_date"));
+    }
+
+    let bucket = ReadingBucket::parse(request.get_bucket().as_deref())?;
+    let aggregate = ReadingAggregate::parse(request.get_aggregate().as_deref())?;
+    let limit = request
+        .get_limit()
+        .unwrap_or(DEFAULT_READINGS_LIMIT)
+        .clamp(1, MAX_READINGS_LIMIT);
+
+    let now = chrono::Local::now().naive_local();
+    let raw_retained = RollupTier::Raw
+        .get_retention()
+        .is_none_or(|retention| from_datetime >= now - retention);
+
+    let tier = choose_reading_tier(bucket, raw_retained, from_datetime, to_datetime, now);
+
+    if bucket == ReadingBucket::Raw || (bucket == ReadingBucket::Auto && tier == RollupTier::Raw) {
+        let cursor = match request.get_cursor() {
+            Some(cursor) => cursor
+                .parse::<i32>()
+                .map_err(|_| invalid_input(&format!("Invalid cursor '{}'", cursor)))?,
+            None => 0,
+        };
+
+        let sensor_reads = sensor_reads::table
+            .filter(sensor_id.eq(request.get_id()))
+            .filter(created_at.ge(from_datetime))
+            .filter(created_at.le(to_datetime))
+            .filter(sensor_read_id.gt(cursor))
+            .order_by(sensor_read_id.asc())
+            .limit(limit)
+            .get_results::<SensorRead>(conn)?;
+
+        let next_cursor = match sensor_reads.last() {
+            Some(sensor_read) if sensor_reads.len() as i64 == limit => {
+                Some(sensor_read.get_id().to_string())
+            }
+            _ => None,
+        };
+
+        return Ok(SensorReadingsPage::new(
+            ReadingBucket::Raw.get_name(),
+            aggregate.get_name(),
+            RollupTier::Raw.get_name(),
+            SensorReadings::Raw(sensor_reads),
+            next_cursor,
+        ));
+    }
+
+    let start = match request.get_cursor() {
+        Some(cursor) => parse_reading_timestamp(cursor)?.max(from_datetime),
+        None => from_datetime,
+    };
+
+    let (buckets, bucket_seconds) = if tier == RollupTier::Raw {
+        let buckets = sql_query(format!(
+            "SELECT {} AS bucket_start, {} AS value, MIN(sensor_value) AS min_value, MAX(sensor_value) AS max_value, AVG(sensor_value) AS avg_value, COUNT(sensor_value) AS read_count
+            FROM sensor_reads
+            WHERE sensor_id = ? AND sensor_value IS NOT NULL AND created_at >= ? AND created_at <= ?
+            GROUP BY bucket_start
+            ORDER BY bucket_start
+            LIMIT ?",
+            bucket_expression("created_at"),
+            aggregate.get_sql()
+        ))
+        .bind::<Integer, _>(bucket.get_seconds())
+        .bind::<Integer, _>(bucket.get_seconds())
+        .bind::<Integer, _>(request.get_id())
+        .bind::<Timestamp, _>(start)
+        .bind::<Timestamp, _>(to_datetime)
+        .bind::<BigInt, _>(limit)
+        .load::<SensorReadingBucket>(conn)?;
+
+        (buckets, bucket.get_seconds())
+    } else {
+        let rollups = sensor_read_rollups::table
+            .filter(sensor_read_rollups::sensor_id.eq(request.get_id()))
+            .filter(sensor_read_rollups::tier.eq(tier.get_name()))
+            .filter(sensor_read_rollups::bucket_start.ge(start))
+            .filter(sensor_read_rollups::bucket_start.le(to_datetime))
+            .order_by(sensor_read_rollups::bucket_start.asc())
+            .limit(limit)
+            .get_results::<SensorReadRollup>(conn)?;
+
+        let buckets = rollups
+            .iter()
+            .map(|rollup| {
+                SensorReadingBucket::from_rollup(rollup, Some(aggregate.apply_to_rollup(rollup)))
+            })
+            .collect::<Vec<SensorReadingBucket>>();
+
+        (buckets, tier.get_bucket_seconds())
+    };
+
+    let next_cursor = match buckets.last() {
+        Some(last_bucket) if buckets.len() as i64 == limit => Some(format_cursor(
+            *last_bucket.get_bucket_start() + chrono::Duration::seconds(bucket_seconds as i64),
+        )),
+        _ => None,
+    };
+
+    // Minute buckets past raw retention come back as the 5m rollups.
+    let bucket_name = match bucket {
+        ReadingBucket::Auto => tier.get_name(),
+        _ if tier.get_bucket_seconds() != bucket.get_seconds() => tier.get_name(),
+        _ => bucket.get_name(),
+    };
+
+    Ok(SensorReadingsPage::new(
+        bucket_name,
+        aggregate.get_name(),
+        tier.get_name(),
+        SensorReadings::Buckets(buckets),
+        next_cursor,
+    ))
+}
+
+/// Deletes raw reads past their retention. They live on in the rollups.
 pub fn delete_old_sensor_reads_records() -> Result<usize> {
     let conn = &mut connect()?;
 
+    let retention = match RollupTier::Raw.get_retention() {
+        Some(retention) => retention,
+        None => return Ok(0),
+    };
+
     let res = diesel::delete(
-        sensor_reads::table
-            .filter(created_at.lt(chrono::Local::now().naive_local() - chrono::Duration::days(30))),
+        sensor_reads::table.filter(created_at.lt(chrono::Local::now().naive_local() - retention)),
     )
     .execute(conn);
 
@@ -265,3 +728,159 @@ pub fn delete_old_sensor_reads_records() -> Result<usize> {
         Err(e) => Err(Error::from(e)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sensor_value() {
+        assert_eq!(parse_sensor_value("23.5"), (Some(23.5), None));
+        assert_eq!(parse_sensor_value(" -4 "), (Some(-4.0), None));
+        assert_eq!(parse_sensor_value("1e3"), (Some(1000.0), None));
+        assert_eq!(
+            parse_sensor_value("21.5 °C"),
+            (Some(21.5), Some("°C".to_string()))
+        );
+        assert_eq!(
+            parse_sensor_value("40%"),
+            (Some(40.0), Some("%".to_string()))
+        );
+        assert_eq!(parse_sensor_value("on"), (None, None));
+        assert_eq!(parse_sensor_value(""), (None, None));
+        assert_eq!(parse_sensor_value("3-4"), (None, None));
+        assert_eq!(parse_sensor_value("5e"), (None, None));
+        assert_eq!(parse_sensor_value("1e999 W"), (None, None));
+        assert_eq!(parse_sensor_value("inf"), (None, None));
+    }
+
+    #[test]
+    fn test_reading_request_parameters() {
+        let local =
+            |s: &str| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+
+        assert_eq!(
+            parse_reading_timestamp("2024-02-16 08:30:00").unwrap(),
+            local("2024-02-16 08:30:00")
+        );
+        assert_eq!(
+            parse_reading_timestamp("2024-02-16T08:30:00.250").unwrap(),
+            local("2024-02-16 08:30:00") + chrono::Duration::milliseconds(250)
+        );
+        assert_eq!(
+            parse_reading_timestamp("2024-02-16").unwrap(),
+            local("2024-02-16 00:00:00")
+        );
+
+        let utc = chrono::NaiveDate::from_ymd_opt(2024, 2, 16)
+            .unwrap()
+            .and_hms_opt(7, 30, 0)
+            .unwrap()
+            .and_utc()
+            .with_timezone(&chrono::Local)
+            .naive_local();
+        assert_eq!(
+            parse_reading_timestamp("2024-02-16T08:30:00+01:00").unwrap(),
+            utc
+        );
+        assert!(parse_reading_timestamp("16/02/2024").is_err());
+
+        assert_eq!(ReadingBucket::parse(None).unwrap(), ReadingBucket::Auto);
+        assert_eq!(
+            ReadingBucket::parse(Some("Hour")).unwrap(),
+            ReadingBucket::Hour
+        );
+        assert!(ReadingBucket::parse(Some("week")).is_err());
+
+        assert_eq!(
+            ReadingAggregate::parse(None).unwrap(),
+            ReadingAggregate::Avg
+        );
+        assert!(ReadingAggregate::parse(Some("median")).is_err());
+
+        let rollup = SensorReadRollup::new(
+            1,
+            RollupTier::Hourly.get_name(),
+            local("2024-02-16 08:00:00"),
+            (1.0, 4.0, 2.5),
+            4,
+        );
+        assert_eq!(ReadingAggregate::Sum.apply_to_rollup(&rollup), 10.0);
+        assert_eq!(ReadingAggregate::Count.apply_to_rollup(&rollup), 4.0);
+    }
+
+    #[test]
+    fn test_choose_reading_tier() {
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 6, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let from = now - chrono::Duration::days(400);
+        let to = from + chrono::Duration::hours(6);
+
+        assert_eq!(
+            choose_reading_tier(ReadingBucket::Minute, true, from, to, now),
+            RollupTier::Raw
+        );
+        assert_eq!(
+            choose_reading_tier(ReadingBucket::Minute, false, from, to, now),
+            RollupTier::FiveMinutes
+        );
+        assert_eq!(
+            choose_reading_tier(ReadingBucket::Hour, false, from, to, now),
+            RollupTier::Hourly
+        );
+        assert_eq!(
+            choose_reading_tier(ReadingBucket::Raw, false, from, to, now),
+            RollupTier::Raw
+        );
+    }
+
+    #[test]
+    fn test_match_sensor_channels() {
+        let device_sensors = vec![
+            Sensor::new(1, "temperature", "10.0.0.5"),
+            Sensor::new(2, "humidity", "10.0.0.5"),
+            Sensor::new(3, "pressure", "10.0.0.5"),
+        ];
+
+        let channels_read = from_str::<SensorChannelsRead>(
+            r#"{
+                "ip_address": "10.0.0.5",
+                "channels": {"temperature": 21.5, "Humidity": "40 %", "pressure": 1013},
+                "units": {"pressure": "hPa"}
+            }"#,
+        )
+        .unwrap();
+
+        let new_sensor_reads = match_sensor_channels(&channels_read, &device_sensors).unwrap();
+
+        assert_eq!(
+            new_sensor_reads
+                .iter()
+                .map(|read| (
+                    read.get_sensor_id(),
+                    read.get_sensor_value(),
+                    read.get_unit().clone()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, Some(40.0), Some("%".to_string())),
+                (3, Some(1013.0), Some("hPa".to_string())),
+                (1, Some(21.5), None),
+            ]
+        );
+
+        let channels_read = from_str::<SensorChannelsRead>(
+            r#"{"ip_address": "10.0.0.5", "channels": {"rain": 1}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            match_sensor_channels(&channels_read, &device_sensors)
+                .unwrap_err()
+                .to_string(),
+            "No rain sensor registered at 10.0.0.5"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::db::connect;
+use crate::models::{NewSensorType, SensorType};
+use crate::schema::{sensor_types, sensors};
+use crate::sensor_types::SENSOR_TYPE_UNKNOWN;
+use anyhow::{Error, Result};
+use diesel::prelude::*;
+use serde_json::from_str;
+
+/// Type names are what devices send when registering, compared in lowercase.
+pub fn normalize_sensor_type_name(name: &str) -> Result<String> {
+    let name = name.trim().to_lowercase();
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::msg(format!("Invalid sensor type name '{}'", name)));
+    }
+
+    Ok(name)
+}
+
+fn validate_sensor_type(sensor_type: &NewSensorType) -> Result<()> {
+    if sensor_type.get_name() == SENSOR_TYPE_UNKNOWN {
+        return Err(Error::msg(format!(
+            "'{}' is reserved for sensors of no known type",
+            SENSOR_TYPE_UNKNOWN
+        )));
+    }
+
+    if sensor_type.get_display_name().trim().is_empty() {
+        return Err(Error::msg("Sensor type display name cannot be empty"));
+    }
+
+    if let (Some(min_value), Some(max_value)) =
+        (sensor_type.get_min_value(), sensor_type.get_max_value())
+    {
+        if min_value > max_value {
+            return Err(Error::msg(format!(
+                "Minimum value {} is above maximum value {}",
+                min_value, max_value
+            )));
+        }
+    }
+
+    if let Some(decimal_places) = sensor_type.get_decimal_places() {
+        if !(0..=6).contains(&decimal_places) {
+            return Err(Error::msg("Decimal places must be between 0 and 6"));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn get_sensor_types() -> Result<Vec<SensorType>> {
+    let conn = &mut connect()?;
+
+    let types = sensor_types::table
+        .order(sensor_types::name.asc())
+        .load::<SensorType>(conn)?;
+
+    Ok(types)
+}
+
+/// Takes the connection so registration can look types up inside its own
+/// transaction.
+pub fn find_sensor_type(conn: &mut SqliteConnection, name: &str) -> Result<Option<SensorType>> {
+    let name = match normalize_sensor_type_name(name) {
+        Ok(name) => name,
+        Err(_) => return Ok(None),
+    };
+
+    let sensor_type = sensor_types::table
+        .filter(sensor_types::name.eq(name))
+        .first(conn)
+        .optional()?;
+
+    Ok(sensor_type)
+}
+
+/// Creates a sensor type or replaces the settings of an existing one.
+pub fn save_sensor_type(payload: String) -> Result<SensorType> {
+    let conn = &mut connect()?;
+
+    let mut new_sensor_type = from_str::<NewSensorType>(&payload)?;
+    new_sensor_type.set_name(normalize_sensor_type_name(new_sensor_type.get_name())?);
+
+    validate_sensor_type(&new_sensor_type)?;
+
+    let now = chrono::Local::now().naive_local();
+    new_sensor_type.set_created_at(now);
+
+    diesel::insert_into(sensor_types::table)
+        .values(&new_sensor_type)
+        .on_conflict(sensor_types::name)
+        .do_update()
+        .set((
+            sensor_types::display_name.eq(new_sensor_type.get_display_name()),
+            sensor_types::unit.eq(new_sensor_type.get_unit()),
+            sensor_types::min_value.eq(new_sensor_type.get_min_value()),
+            sensor_types::max_value.eq(new_sensor_type.get_max_value()),
+            sensor_types::decimal_places.eq(new_sensor_type.get_decimal_places()),
+            sensor_types::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+
+    let sensor_type = sensor_types::table
+        .filter(sensor_types::name.eq(new_sensor_type.get_name()))
+        .first(conn)?;
+
+    Ok(sensor_type)
+}
+
+/// Types still used by a registered sensor cannot be removed.
+pub fn delete_sensor_type(name: &str) -> Result<()> {
+    let conn = &mut connect()?;
+
+    let name = normalize_sensor_type_name(name)?;
+
+    let sensor_count = sensors::table
+        .filter(sensors::sensor_type.eq(&name))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    if sensor_count > 0 {
+        return Err(Error::msg(format!(
+            "Sensor type '{}' is used by {} sensor(s)",
+            name, sensor_count
+        )));
+    }
+
+    diesel::delete(sensor_types::table.filter(sensor_types::name.eq(name))).execute(conn)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_sensor_type() {
+        assert_eq!(normalize_sensor_type_name(" PM2_5 ").unwrap(), "pm2_5");
+        assert!(normalize_sensor_type_name("soil moisture").is_err());
+
+        let mut co2 = NewSensorType::new("co2", "CO2 sensor");
+        co2.set_unit(Some("ppm".to_string()));
+        co2.set_range(Some(400.0), Some(5000.0));
+        co2.set_decimal_places(Some(0));
+        assert!(validate_sensor_type(&co2).is_ok());
+
+        co2.set_range(Some(5000.0), Some(400.0));
+        assert_eq!(
+            validate_sensor_type(&co2).unwrap_err().to_string(),
+            "Minimum value 5000 is above maximum value 400"
+        );
+
+        co2.set_range(None, None);
+        co2.set_decimal_places(Some(9));
+        assert!(validate_sensor_type(&co2).is_err());
+
+        assert!(validate_sensor_type(&NewSensorType::new("unknown", "Unknown")).is_err());
+        assert!(validate_sensor_type(&NewSensorType::new("co2", " ")).is_err());
+    }
+}
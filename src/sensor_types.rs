@@ -1,11 +1,3 @@
-pub const SENSOR_TYPE_CURRENT: &str = "current";
-pub const SENSOR_TYPE_TEMPERATURE: &str = "temperature";
-pub const SENSOR_TYPE_HUMIDITY: &str = "humidity";
-pub const SENSOR_TYPE_PRESSURE: &str = "pressure";
-pub const SENSOR_TYPE_WIND_SPEED: &str = "wind_speed";
-pub const SENSOR_TYPE_WIND_DIRECTION: &str = "wind_direction";
-pub const SENSOR_TYPE_RAIN: &str = "rain";
-pub const SENSOR_TYPE_UV: &str = "uv";
-pub const SENSOR_TYPE_SOLAR_RADIATION: &str = "solar_radiation";
+/// Type given to sensors registering with a type missing from the
+/// `sensor_types` table. The known types live in that table.
 pub const SENSOR_TYPE_UNKNOWN: &str = "unknown";
-
@@ -1,11 +1,22 @@
 use crate::actuator_handlers::ping_actuator;
 use crate::actuator_methods::get_all_registered_actuators;
+use crate::api_docs::{openapi_json_handler, OPENAPI_PATH};
+use crate::api_handlers::{api_router, API_V1_PATH};
+use crate::auth::{
+    authenticate, delete_expired_sessions, disconnect_sessions, get_session_room, AuthSession,
+};
 use crate::events::{
     register_all_callbacks, ALL_ACTUATORS_EVENT, ALL_LAST_SENSOR_READINGS_EVENT, ALL_SENSORS_EVENT,
+    ALL_SENSOR_TYPES_EVENT,
 };
+use crate::export_handlers::{export_sensor_reads_handler, EXPORT_SENSOR_READS_PATH};
 use crate::handlers::path_handler;
+use crate::protocol::{socket_events_schema_handler, SOCKET_EVENTS_SCHEMA_PATH};
+use crate::protocol::{AllActuators, AllLastSensorReadings, AllSensorTypes, AllSensors};
+use crate::script_runner::cancel_socket_debug_sessions;
 use crate::sensor_handlers::ping_sensor;
 use crate::sensor_methods::{get_all_last_sensor_readings, get_all_registered_sensors};
+use crate::sensor_type_methods::get_sensor_types;
 use crate::Server;
 use anyhow::Result;
 use axum::routing::get;
@@ -13,20 +24,36 @@ use axum::Router;
 use axum::Server as AxumServer;
 use axum_util::cors::CorsLayer;
 use serde::Deserialize;
-use serde_json::json;
-use socketioxide::extract::{Data, SocketRef};
+use socketioxide::extract::{SocketRef, TryData};
 use socketioxide::{SocketIo, TransportType};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::{spawn, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct AuthData {
+    #[serde(default)]
     token: String,
 }
 
+/// Sockets outliving their session are closed when it expires.
+fn disconnect_on_expiry(io: &SocketIo, auth_session: &AuthSession) {
+    let io = io.clone();
+    let token_id = auth_session.get_session().get_token_id().to_string();
+
+    let expires_in = (*auth_session.get_session().get_expires_at()
+        - chrono::Local::now().naive_local())
+    .to_std()
+    .unwrap_or_default();
+
+    tokio::spawn(async move {
+        tokio::time::sleep(expires_in).await;
+        disconnect_sessions(&io, &[token_id]);
+    });
+}
+
 pub async fn run_socket_server(address: &'static str) -> Result<SocketIo> {
     let (layer, io) = SocketIo::builder()
         .connect_timeout(Duration::from_secs(30))
@@ -34,51 +61,66 @@ pub async fn run_socket_server(address: &'static str) -> Result<SocketIo> {
         .transports([TransportType::Websocket, TransportType::Polling])
         .build_layer();
 
-    io.ns("/", move |socket: SocketRef, Data(auth): Data<AuthData>| {
-        let login_token = std::env::var("LOGIN_TOKEN").expect("LOGIN_TOKEN must be set");
-
-        if auth.token.is_empty() || auth.token != login_token {
-            println!("Invalid token, disconnecting socket : {:?}", socket.id);
-            socket.disconnect().ok();
-            return;
+    let callbacks_io = io.clone();
+
+    io.ns("/", move |socket: SocketRef, auth: TryData<AuthData>| {
+        // Without a valid auth object the socket is disconnected below.
+        let auth = auth.0.unwrap_or_default();
+
+        let auth_session = match authenticate(&auth.token) {
+            Ok(auth_session) => auth_session,
+            Err(e) => {
+                println!("{}, disconnecting socket : {:?}", e, socket.id);
+                socket.disconnect().ok();
+                return;
+            }
+        };
+
+        match socket.join(get_session_room(auth_session.get_session().get_token_id())) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error joining session room: {:?}", e);
+            }
         }
 
-        println!("Socket connected : {:?}", socket.id);
+        println!(
+            "Socket connected : {:?} as {}",
+            socket.id,
+            auth_session.get_user().get_username()
+        );
+
+        register_all_callbacks(&socket, &callbacks_io, &auth_session);
 
-        register_all_callbacks(&socket);
+        disconnect_on_expiry(&callbacks_io, &auth_session);
 
         socket.on_disconnect(|socket: SocketRef| {
             println!("Socket disconnected : {:?}", socket.id);
+            cancel_socket_debug_sessions(&socket.id.to_string());
         });
 
         if let Ok(sensors) = get_all_registered_sensors() {
-            let _: Result<_, _> = socket.emit(
-                ALL_SENSORS_EVENT,
-                json!({
-                    "sensors": sensors,
-                }),
-            );
+            let _: Result<_, _> = socket.emit(ALL_SENSORS_EVENT, AllSensors::new(sensors));
+        }
+
+        if let Ok(sensor_types) = get_sensor_types() {
+            let _: Result<_, _> =
+                socket.emit(ALL_SENSOR_TYPES_EVENT, AllSensorTypes::new(sensor_types));
         }
 
         if let Ok(sensor_reads) = get_all_last_sensor_readings() {
             let _: Result<_, _> = socket.emit(
                 ALL_LAST_SENSOR_READINGS_EVENT,
-                json!({
-                    "sensor_reads": sensor_reads,
-                }),
+                AllLastSensorReadings::new(sensor_reads),
             );
         }
 
         if let Ok(actuators) = get_all_registered_actuators() {
-            let _: Result<_, _> = socket.emit(
-                ALL_ACTUATORS_EVENT,
-                json!({
-                    "actuators": actuators,
-                }),
-            );
+            let _: Result<_, _> = socket.emit(ALL_ACTUATORS_EVENT, AllActuators::new(actuators));
         }
     });
 
+    let api_io = io.clone();
+
     spawn(move || {
         Runtime::new()
             .expect("Failed to create Tokio runtime")
@@ -87,6 +129,10 @@ pub async fn run_socket_server(address: &'static str) -> Result<SocketIo> {
 
                 let app = Router::new()
                     .route("/", get(|| async { "OK" }))
+                    .route(EXPORT_SENSOR_READS_PATH, get(export_sensor_reads_handler))
+                    .route(OPENAPI_PATH, get(openapi_json_handler))
+                    .route(SOCKET_EVENTS_SCHEMA_PATH, get(socket_events_schema_handler))
+                    .nest(API_V1_PATH, api_router(api_io))
                     .layer(layer)
                     .layer(CorsLayer);
 
@@ -164,10 +210,45 @@ pub async fn run_coap_server(address: &'static str, socket: &SocketIo) {
     });
 }
 
-pub async fn check_for_old_sensor_reads_records() {
-    spawn(move || loop {
-        let _: Result<_, _> = crate::sensor_methods::delete_old_sensor_reads_records();
+/// The 5m tier answers ranges of a few days, so it is kept at most one bucket
+/// behind the raw reads.
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(300);
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
 
-        std::thread::sleep(Duration::from_secs(3600));
+pub async fn check_for_old_sensor_reads_records() {
+    spawn(move || {
+        let mut last_cleanup: Option<Instant> = None;
+
+        loop {
+            let rolled_up = match crate::rollup_methods::roll_up_sensor_reads() {
+                Ok(_) => true,
+                Err(e) => {
+                    println!("Error rolling up sensor reads: {:?}", e);
+                    false
+                }
+            };
+
+            if last_cleanup.is_none_or(|last_cleanup| last_cleanup.elapsed() >= CLEANUP_INTERVAL) {
+                // Nothing is pruned that may not have been summarized yet.
+                if rolled_up {
+                    let _: Result<_, _> = crate::sensor_methods::delete_old_sensor_reads_records();
+                    let _: Result<_, _> = crate::rollup_methods::delete_expired_rollups();
+                } else {
+                    println!("Skipping sensor read pruning until the roll-up succeeds");
+                }
+
+                match delete_expired_sessions() {
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Error deleting expired sessions: {:?}", e);
+                    }
+                }
+
+                last_cleanup = Some(Instant::now());
+            }
+
+            std::thread::sleep(ROLLUP_INTERVAL);
+        }
     });
 }
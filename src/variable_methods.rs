@@ -0,0 +1,95 @@
+use crate::db::connect;
+use crate::models::{NewScriptVariable, ScriptVariable};
+use crate::schema::script_variables;
+use anyhow::{Error, Result};
+use diesel::prelude::*;
+use serde_json::from_str;
+
+/// Global variables are named like script variables, with or without the `$`.
+fn normalize_variable_name(name: &str) -> Result<String> {
+    let name = name.trim().trim_start_matches('$');
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::msg(format!("Invalid variable name '{}'", name)));
+    }
+
+    Ok(name.to_string())
+}
+
+fn validate_variable_value(value: &str) -> Result<()> {
+    match from_str::<serde_json::Value>(value) {
+        Ok(serde_json::Value::Object(_)) => Err(Error::msg("Variable value cannot be an object")),
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::msg(format!(
+            "Variable value is not valid JSON: {}",
+            e
+        ))),
+    }
+}
+
+pub fn get_script_variables() -> Result<Vec<ScriptVariable>> {
+    let conn = &mut connect()?;
+
+    let variables = script_variables::table
+        .order(script_variables::name.asc())
+        .load::<ScriptVariable>(conn)?;
+
+    Ok(variables)
+}
+
+pub fn get_script_variable(name: &str) -> Result<Option<ScriptVariable>> {
+    let conn = &mut connect()?;
+
+    let variable = script_variables::table
+        .filter(script_variables::name.eq(normalize_variable_name(name)?))
+        .first(conn)
+        .optional()?;
+
+    Ok(variable)
+}
+
+/// Creates or overwrites a global variable. `value` is JSON text.
+pub fn set_script_variable(name: &str, value: &str) -> Result<ScriptVariable> {
+    let conn = &mut connect()?;
+
+    let name = normalize_variable_name(name)?;
+    validate_variable_value(value)?;
+
+    let now = chrono::Local::now().naive_local();
+
+    let mut variable = NewScriptVariable::new(&name, value);
+    variable.set_created_at(now);
+
+    diesel::insert_into(script_variables::table)
+        .values(&variable)
+        .on_conflict(script_variables::name)
+        .do_update()
+        .set((
+            script_variables::value.eq(value),
+            script_variables::updated_at.eq(now),
+        ))
+        .execute(conn)?;
+
+    let saved_variable = script_variables::table
+        .filter(script_variables::name.eq(&name))
+        .first(conn)?;
+
+    Ok(saved_variable)
+}
+
+pub fn save_script_variable(payload: String) -> Result<ScriptVariable> {
+    let variable = from_str::<NewScriptVariable>(&payload)?;
+
+    set_script_variable(variable.get_name(), variable.get_value())
+}
+
+pub fn delete_script_variable(name: &str) -> Result<()> {
+    let conn = &mut connect()?;
+
+    diesel::delete(
+        script_variables::table.filter(script_variables::name.eq(normalize_variable_name(name)?)),
+    )
+    .execute(conn)?;
+
+    Ok(())
+}